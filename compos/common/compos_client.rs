@@ -187,7 +187,7 @@ impl ComposClient {
     }
 
     /// Wait for the instance to shut down. If it fails to shutdown within a reasonable time the
-    /// instance is dropped, which forcibly terminates it.
+    /// VM is forcibly stopped, and we wait again for its process to be reaped.
     /// This should only be called when the instance has been requested to quit, or we believe that
     /// it is already in the process of exiting due to some failure.
     fn wait_for_shutdown(self) {
@@ -195,7 +195,17 @@ impl ComposClient {
         match death_reason {
             Some(DeathReason::Shutdown) => info!("VM has exited normally"),
             Some(reason) => warn!("VM died with reason {:?}", reason),
-            None => warn!("VM failed to exit, dropping"),
+            None => {
+                warn!("VM failed to exit within {:?}, stopping it", TIMEOUTS.vm_max_time_to_exit);
+                if let Err(e) = self.0.stop() {
+                    warn!("Failed to stop VM: {e:?}");
+                }
+                // The VM reports its death once the crosvm process has been reaped.
+                match self.0.wait_for_death_with_timeout(TIMEOUTS.vm_max_time_to_exit) {
+                    Some(reason) => info!("VM was forcibly stopped, reason {:?}", reason),
+                    None => warn!("VM failed to exit after being stopped, dropping"),
+                }
+            }
         }
     }
 }
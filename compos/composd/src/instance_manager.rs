@@ -17,7 +17,7 @@
 //! Manages running instances of the CompOS VM. At most one instance should be running at
 //! a time, started on demand.
 
-use crate::instance_starter::{CompOsInstance, InstanceStarter};
+use crate::instance_starter::{CompOsInstance, InstanceInvalid, InstanceStarter};
 use android_system_virtualizationservice::aidl::android::system::virtualizationservice;
 use anyhow::{anyhow, bail, Context, Result};
 use binder::Strong;
@@ -65,7 +65,13 @@ impl InstanceManager {
         drop(state);
 
         let instance_starter = InstanceStarter::new(instance_name, vm_parameters);
-        let instance = instance_starter.start_new_instance(&*self.service);
+        let instance = match instance_starter.start_existing_instance(&*self.service) {
+            Err(e) if e.is::<InstanceInvalid>() => {
+                info!("{e:#}; creating a new instance");
+                instance_starter.start_new_instance(&*self.service)
+            }
+            result => result,
+        };
 
         let mut state = self.state.lock().unwrap();
         if let Ok(ref instance) = instance {
@@ -22,17 +22,26 @@ use android_system_virtualizationservice::aidl::android::system::virtualizations
 };
 use anyhow::{anyhow, Context, Result};
 use binder::{LazyServiceGuard, ParcelFileDescriptor, Strong};
+use ciborium::Value;
 use compos_aidl_interface::aidl::com::android::compos::ICompOsService::ICompOsService;
 use compos_common::compos_client::{ComposClient, VmParameters};
 use compos_common::{
     COMPOS_DATA_ROOT, IDSIG_FILE, IDSIG_MANIFEST_APK_FILE, IDSIG_MANIFEST_EXT_APK_FILE,
     INSTANCE_ID_FILE, INSTANCE_IMAGE_FILE,
 };
-use log::info;
+use log::{info, warn};
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+const BCC_FILE: &str = "bcc";
+
+/// Returned by `InstanceStarter::start_existing_instance` if the existing instance can't be
+/// reused. The caller should fall back to `InstanceStarter::start_new_instance`.
+#[derive(Debug, thiserror::Error)]
+#[error("CompOS instance is invalid: {0}")]
+pub struct InstanceInvalid(String);
+
 pub struct CompOsInstance {
     service: Strong<dyn ICompOsService>,
     #[allow(dead_code)] // Keeps VirtualizationService & the VM alive
@@ -54,7 +63,23 @@ impl CompOsInstance {
         &self.instance_tracker
     }
 
-    /// Attempt to shut down the VM cleanly, giving time for any relevant logs to be written.
+    /// Retrieves the VM's current attestation chain, a CBOR-encoded DICE chain.
+    pub fn attestation_chain(&self) -> Result<Vec<u8>> {
+        self.service.getAttestationChain().context("Getting attestation chain")
+    }
+
+    /// Checks that the VM's current attestation chain matches `expected_bcc`. The chains are
+    /// compared after decoding, so differences in the CBOR encoding alone don't cause a mismatch.
+    /// Fails with `InstanceInvalid` if they don't match, or either can't be retrieved or decoded.
+    pub fn verify_against(&self, expected_bcc: &[u8]) -> Result<()> {
+        let actual_bcc = self
+            .attestation_chain()
+            .map_err(|e| InstanceInvalid(format!("Failed to get attestation chain: {e:#}")))?;
+        compare_attestation_chains(expected_bcc, &actual_bcc)
+    }
+
+    /// Attempt to shut down the VM cleanly, giving time for any relevant logs to be written. If the
+    /// VM doesn't exit in time it is forcibly stopped.
     pub fn shutdown(self) -> LazyServiceGuard {
         self.vm_instance.shutdown(self.service);
         // Return the guard to the caller, since we might be terminated at any point after it is
@@ -63,6 +88,35 @@ impl CompOsInstance {
     }
 }
 
+/// Checks that two CBOR-encoded DICE chains are equal once decoded, failing with
+/// `InstanceInvalid` if they aren't or if either can't be decoded.
+fn compare_attestation_chains(expected_bcc: &[u8], actual_bcc: &[u8]) -> Result<()> {
+    let expected: Value = ciborium::de::from_reader(expected_bcc)
+        .map_err(|e| InstanceInvalid(format!("Failed to decode expected DICE chain: {e}")))?;
+    let actual: Value = ciborium::de::from_reader(actual_bcc)
+        .map_err(|e| InstanceInvalid(format!("Failed to decode DICE chain: {e}")))?;
+    if !cbor_eq(&expected, &actual) {
+        return Err(InstanceInvalid("Attestation chain does not match".to_owned()).into());
+    }
+    Ok(())
+}
+
+/// Compares two CBOR values, ignoring the order of map entries.
+fn cbor_eq(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| cbor_eq(a, b))
+        }
+        (Value::Map(a), Value::Map(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(key, value)| b.iter().any(|(k, v)| cbor_eq(key, k) && cbor_eq(value, v)))
+        }
+        (Value::Tag(tag_a, a), Value::Tag(tag_b, b)) => tag_a == tag_b && cbor_eq(a, b),
+        (a, b) => a == b,
+    }
+}
+
 pub struct InstanceStarter {
     instance_name: String,
     instance_root: PathBuf,
@@ -76,7 +130,18 @@ pub struct InstanceStarter {
 
 impl InstanceStarter {
     pub fn new(instance_name: &str, vm_parameters: VmParameters) -> Self {
-        let instance_root = Path::new(COMPOS_DATA_ROOT).join(instance_name);
+        Self::with_instance_root(
+            instance_name,
+            Path::new(COMPOS_DATA_ROOT).join(instance_name),
+            vm_parameters,
+        )
+    }
+
+    fn with_instance_root(
+        instance_name: &str,
+        instance_root: PathBuf,
+        vm_parameters: VmParameters,
+    ) -> Self {
         let instance_root_path = instance_root.as_path();
         let instance_id_file = instance_root_path.join(INSTANCE_ID_FILE);
         let instance_image = instance_root_path.join(INSTANCE_IMAGE_FILE);
@@ -95,31 +160,84 @@ impl InstanceStarter {
         }
     }
 
+    /// Starts the VM using the existing instance, and checks that its attestation chain matches
+    /// the one saved when the instance was created. Fails with `InstanceInvalid` if the instance
+    /// doesn't exist, the VM can't be started with it, or the attestation chain has changed.
+    pub fn start_existing_instance(
+        &self,
+        virtualization_service: &dyn IVirtualizationService,
+    ) -> Result<CompOsInstance> {
+        info!("Starting existing {} CompOs instance", self.instance_name);
+
+        let bcc_file = self.instance_root.join(BCC_FILE);
+        let saved_bcc = fs::read(&bcc_file)
+            .map_err(|e| InstanceInvalid(format!("Failed to read {bcc_file:?}: {e}")))?;
+        if !self.instance_image.exists() {
+            return Err(InstanceInvalid(format!("{:?} does not exist", self.instance_image)).into());
+        }
+
+        let instance_id = self
+            .read_instance_id()
+            .map_err(|e| InstanceInvalid(format!("Failed to read instance id: {e:#}")))?;
+        let instance = self
+            .start_vm(virtualization_service, instance_id)
+            .map_err(|e| InstanceInvalid(format!("Failed to start VM: {e:#}")))?;
+
+        // On failure, dropping the instance stops the VM.
+        instance.verify_against(&saved_bcc)?;
+
+        Ok(instance)
+    }
+
     pub fn start_new_instance(
         &self,
         virtualization_service: &dyn IVirtualizationService,
     ) -> Result<CompOsInstance> {
         info!("Creating {} CompOs instance", self.instance_name);
 
+        self.populate_instance_root(|| self.create_and_start_new_instance(virtualization_service))
+    }
+
+    /// Creates the instance directory and runs `populate` to fill it. If that fails, the directory
+    /// is removed again so that a partially created instance is never reused.
+    fn populate_instance_root<T>(&self, populate: impl FnOnce() -> Result<T>) -> Result<T> {
         fs::create_dir_all(&self.instance_root)?;
 
-        // Overwrite any existing instance - it's unlikely to be valid with the current set
-        // of APEXes, and finding out it isn't is much more expensive than creating a new one.
+        let result = populate();
+        if result.is_err() {
+            if let Err(e) = fs::remove_dir_all(&self.instance_root) {
+                warn!("Failed to remove {:?}: {e}", self.instance_root);
+            }
+        }
+        result
+    }
+
+    fn create_and_start_new_instance(
+        &self,
+        virtualization_service: &dyn IVirtualizationService,
+    ) -> Result<CompOsInstance> {
+        // Overwrite any existing instance. Callers that want to reuse it if it is still valid
+        // should try start_existing_instance first.
         self.create_instance_image(virtualization_service)?;
         // TODO(b/294177871): Ping VS to delete the old instance's secret.
-        if cfg!(llpvm_changes) {
-            self.allocate_instance_id(virtualization_service)?;
-        }
+        // The new instance always gets a new id, which also replaces a missing or corrupt id file.
+        let instance_id = if cfg!(llpvm_changes) {
+            self.allocate_instance_id(|| {
+                virtualization_service.allocateInstanceId().context("Allocating Instance Id")
+            })?
+        } else {
+            [0u8; 64]
+        };
         // Delete existing idsig files. Ignore error in case idsig doesn't exist.
         let _ignored1 = fs::remove_file(&self.idsig);
         let _ignored2 = fs::remove_file(&self.idsig_manifest_apk);
         let _ignored3 = fs::remove_file(&self.idsig_manifest_ext_apk);
 
-        let instance = self.start_vm(virtualization_service)?;
+        let instance = self.start_vm(virtualization_service, instance_id)?;
 
         // Retrieve the VM's attestation chain as a BCC and save it in the instance directory.
-        let bcc = instance.service.getAttestationChain().context("Getting attestation chain")?;
-        fs::write(self.instance_root.join("bcc"), bcc).context("Writing BCC")?;
+        let bcc = instance.attestation_chain()?;
+        fs::write(self.instance_root.join(BCC_FILE), bcc).context("Writing BCC")?;
 
         Ok(instance)
     }
@@ -127,15 +245,8 @@ impl InstanceStarter {
     fn start_vm(
         &self,
         virtualization_service: &dyn IVirtualizationService,
+        instance_id: [u8; 64],
     ) -> Result<CompOsInstance> {
-        let instance_id: [u8; 64] = if cfg!(llpvm_changes) {
-            fs::read(&self.instance_id_file)?
-                .try_into()
-                .map_err(|_| anyhow!("Failed to get instance_id"))?
-        } else {
-            [0u8; 64]
-        };
-
         let instance_image = fs::OpenOptions::new()
             .read(true)
             .write(true)
@@ -180,12 +291,136 @@ impl InstanceStarter {
         Ok(())
     }
 
+    /// Obtains a new instance id from `allocate` and saves it in the instance directory, replacing
+    /// any previous one.
     fn allocate_instance_id(
         &self,
-        virtualization_service: &dyn IVirtualizationService,
-    ) -> Result<()> {
-        let id = virtualization_service.allocateInstanceId().context("Allocating Instance Id")?;
-        fs::write(&self.instance_id_file, id)?;
+        allocate: impl FnOnce() -> Result<[u8; 64]>,
+    ) -> Result<[u8; 64]> {
+        let id = allocate()?;
+        fs::write(&self.instance_id_file, id).context("Writing instance id")?;
+        Ok(id)
+    }
+
+    /// Reads the instance id saved in the instance directory.
+    fn read_instance_id(&self) -> Result<[u8; 64]> {
+        if !cfg!(llpvm_changes) {
+            return Ok([0u8; 64]);
+        }
+        self.read_instance_id_file()
+    }
+
+    fn read_instance_id_file(&self) -> Result<[u8; 64]> {
+        let id = fs::read(&self.instance_id_file)
+            .with_context(|| format!("Failed to read {:?}", self.instance_id_file))?;
+        let len = id.len();
+        id.try_into().map_err(|_| anyhow!("Failed to get instance_id: invalid length {len}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::bail;
+
+    #[test]
+    fn cbor_eq_ignores_map_order() {
+        let a = Value::Map(vec![
+            (Value::from(1), Value::from("one")),
+            (Value::from(2), Value::Array(vec![Value::from(3)])),
+        ]);
+        let b = Value::Map(vec![
+            (Value::from(2), Value::Array(vec![Value::from(3)])),
+            (Value::from(1), Value::from("one")),
+        ]);
+        let c = Value::Map(vec![
+            (Value::from(1), Value::from("one")),
+            (Value::from(2), Value::Array(vec![Value::from(4)])),
+        ]);
+        assert!(cbor_eq(&a, &b));
+        assert!(!cbor_eq(&a, &c));
+    }
+
+    #[test]
+    fn cbor_eq_ignores_integer_encoding() {
+        // 1 encoded in a single byte, and as a 4-byte integer.
+        let a: Value = ciborium::de::from_reader([0x81, 0x01].as_slice()).unwrap();
+        let b: Value =
+            ciborium::de::from_reader([0x81, 0x1a, 0x00, 0x00, 0x00, 0x01].as_slice()).unwrap();
+        assert!(cbor_eq(&a, &b));
+    }
+
+    #[test]
+    fn garbage_bcc_file_is_invalid() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let bcc_file = temp_dir.path().join(BCC_FILE);
+        // A CBOR array header announcing more items than there are.
+        fs::write(&bcc_file, [0x85, 0x01])?;
+        let saved_bcc = fs::read(&bcc_file)?;
+        let actual_bcc = [0x81, 0x01];
+
+        let e = compare_attestation_chains(&saved_bcc, &actual_bcc).unwrap_err();
+        assert!(e.is::<InstanceInvalid>());
+        let e = compare_attestation_chains(&actual_bcc, &saved_bcc).unwrap_err();
+        assert!(e.is::<InstanceInvalid>());
+        assert!(compare_attestation_chains(&actual_bcc, &actual_bcc).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn failed_creation_removes_instance_files() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let instance_starter = InstanceStarter::with_instance_root(
+            "test",
+            temp_dir.path().join("test"),
+            VmParameters::default(),
+        );
+
+        let result: Result<()> = instance_starter.populate_instance_root(|| {
+            fs::write(&instance_starter.instance_image, b"image")?;
+            fs::write(&instance_starter.instance_id_file, [0u8; 64])?;
+            bail!("Injected start_vm failure");
+        });
+
+        assert!(result.is_err());
+        assert!(!instance_starter.instance_image.exists());
+        assert!(!instance_starter.instance_id_file.exists());
+        assert!(!instance_starter.instance_root.exists());
+        Ok(())
+    }
+
+    fn instance_starter_in(temp_dir: &tempfile::TempDir) -> Result<InstanceStarter> {
+        let instance_starter = InstanceStarter::with_instance_root(
+            "test",
+            temp_dir.path().join("test"),
+            VmParameters::default(),
+        );
+        fs::create_dir_all(&instance_starter.instance_root)?;
+        Ok(instance_starter)
+    }
+
+    #[test]
+    fn truncated_instance_id_is_invalid() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let instance_starter = instance_starter_in(&temp_dir)?;
+        fs::write(&instance_starter.instance_id_file, [1u8; 32])?;
+
+        assert!(instance_starter.read_instance_id_file().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn existing_instance_id_is_reallocated() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let instance_starter = instance_starter_in(&temp_dir)?;
+        for existing_id in [&[1u8; 32][..], &[1u8; 64][..]] {
+            fs::write(&instance_starter.instance_id_file, existing_id)?;
+
+            let id = instance_starter.allocate_instance_id(|| Ok([2u8; 64]))?;
+
+            assert_eq!(id, [2u8; 64]);
+            assert_eq!(fs::read(&instance_starter.instance_id_file)?, [2u8; 64]);
+        }
         Ok(())
     }
 }
@@ -264,7 +264,7 @@ public:
         return ScopedAStatus::ok();
     }
 
-    ScopedAStatus onDied(int32_t, DeathReason) {
+    ScopedAStatus onDied(int32_t, DeathReason, int32_t) {
         std::unique_lock lock(mMutex);
         mCv.notify_all();
         return ScopedAStatus::ok();
@@ -783,9 +783,11 @@ public class VirtualMachine implements AutoCloseable {
             case VirtualMachineState.STARTED:
             case VirtualMachineState.READY:
             case VirtualMachineState.FINISHED:
+            case VirtualMachineState.SUSPENDED:
                 return STATUS_RUNNING;
             case VirtualMachineState.NOT_STARTED:
             case VirtualMachineState.DEAD:
+            case VirtualMachineState.FAILED:
             default:
                 return STATUS_STOPPED;
         }
@@ -1882,7 +1884,7 @@ public class VirtualMachine implements AutoCloseable {
         }
 
         @Override
-        public void onDied(int cid, int reason) {
+        public void onDied(int cid, int reason, int exitCode) {
             int translatedReason = getTranslatedReason(reason);
             reportStopped(translatedReason);
             mService.asBinder().unlinkToDeath(mDeathRecipient, 0);
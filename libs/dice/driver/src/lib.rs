@@ -30,6 +30,9 @@ use std::path::{Path, PathBuf};
 use std::ptr::null_mut;
 use std::slice;
 
+/// Size of the keys returned by [`derive_sealing_key`].
+pub const SEALING_KEY_SIZE: usize = 32;
+
 /// Artifacts that are mapped into the process address space from the driver.
 pub enum DiceDriver<'a> {
     /// Implementation that reads bcc handover from the dice driver.
@@ -121,10 +124,7 @@ impl DiceDriver<'_> {
         // Deterministically derive a key to use for sealing data, rather than using the CDI
         // directly, so we have the chance to rotate the key if needed. A salt isn't needed as the
         // input key material is already cryptographically strong.
-        let mut key = ZVec::new(key_length)?;
-        let salt = &[];
-        hkdf(&mut key, Md::sha256(), self.dice_artifacts().cdi_seal(), salt, identifier)?;
-        Ok(key)
+        hkdf_from_cdi_seal(self.dice_artifacts(), &[], identifier, key_length)
     }
 
     /// Derives a new dice chain.
@@ -168,6 +168,31 @@ impl DiceDriver<'_> {
     }
 }
 
+/// Derives a key of [`SEALING_KEY_SIZE`] bytes from the sealing CDI of `dice_artifacts`, typically
+/// those returned by [`DiceDriver::derive`], to encrypt data of the payload.
+///
+/// The key only depends on the CDI, `salt` and `context`, so it is the same across boots of the
+/// same instance, but it changes if any of the DICE inputs (e.g. the code or authority hash) of
+/// the instance or of the stages that booted it do.
+pub fn derive_sealing_key(
+    dice_artifacts: &dyn DiceArtifacts,
+    salt: &[u8],
+    context: &[u8],
+) -> Result<ZVec> {
+    hkdf_from_cdi_seal(dice_artifacts, salt, context, SEALING_KEY_SIZE)
+}
+
+fn hkdf_from_cdi_seal(
+    dice_artifacts: &dyn DiceArtifacts,
+    salt: &[u8],
+    info: &[u8],
+    key_length: usize,
+) -> Result<ZVec> {
+    let mut key = ZVec::new(key_length)?;
+    hkdf(&mut key, Md::sha256(), dice_artifacts.cdi_seal(), salt, info)?;
+    Ok(key)
+}
+
 impl Drop for DiceDriver<'_> {
     fn drop(&mut self) {
         if let &mut Self::Real { mmap_addr, mmap_size, .. } = self {
@@ -248,4 +273,43 @@ mod tests {
 
         Ok(())
     }
+
+    fn derive_sample(code_hash: &str, authority_hash: &str) -> Result<OwnedDiceArtifacts> {
+        let dice = DiceDriver::Fake(diced_sample_inputs::make_sample_bcc_and_cdis()?);
+        let values = DiceConfigValues {
+            component_name: Some(CStr::from_bytes_with_nul(b"test\0")?),
+            ..Default::default()
+        };
+        let desc = retry_bcc_format_config_descriptor(&values)?;
+        let code_hash = hash(code_hash.as_bytes())?;
+        let authority_hash = hash(authority_hash.as_bytes())?;
+        dice.derive(code_hash, &desc, authority_hash, false, [0; HIDDEN_SIZE])
+    }
+
+    #[test]
+    fn test_derive_sealing_key_is_deterministic() -> Result<()> {
+        let key1 = derive_sealing_key(&derive_sample("code", "authority")?, b"salt", b"context")?;
+        let key2 = derive_sealing_key(&derive_sample("code", "authority")?, b"salt", b"context")?;
+
+        assert_eq!(key1.len(), SEALING_KEY_SIZE);
+        assert_eq_bytes(&key1, &key2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_derive_sealing_key_depends_on_inputs() -> Result<()> {
+        let dice_artifacts = derive_sample("code", "authority")?;
+        let key = derive_sealing_key(&dice_artifacts, b"salt", b"context")?;
+
+        let other_keys = [
+            derive_sealing_key(&dice_artifacts, b"other salt", b"context")?,
+            derive_sealing_key(&dice_artifacts, b"salt", b"other context")?,
+            derive_sealing_key(&derive_sample("other code", "authority")?, b"salt", b"context")?,
+            derive_sealing_key(&derive_sample("code", "other authority")?, b"salt", b"context")?,
+        ];
+        for other_key in other_keys {
+            assert_ne!(&*key, &*other_key);
+        }
+        Ok(())
+    }
 }
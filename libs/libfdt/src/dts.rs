@@ -0,0 +1,127 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Human-readable dumps of DT nodes, in a format close to the DT source format.
+
+use crate::FdtNode;
+use core::fmt::{self, Write as _};
+
+/// Nodes deeper than this (relative to the dumped node) are elided, to bound the recursion.
+const MAX_DEPTH: usize = 16;
+
+impl<'a> FdtNode<'a> {
+    /// Writes the node, its properties and its descendants in a DTS-like format.
+    ///
+    /// Property values are printed as strings, cells or bytes, depending on their contents.
+    /// Descendants nested more than a fixed number of levels below this node are elided.
+    pub fn write_dts(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_node(f, self, 0)
+    }
+
+    /// Returns a value whose `Display` implementation calls [`FdtNode::write_dts`].
+    pub fn dts(&self) -> Dts<'a> {
+        Dts(*self)
+    }
+}
+
+/// Displays a DT node and its descendants, see [`FdtNode::write_dts`].
+#[derive(Clone, Copy, Debug)]
+pub struct Dts<'a>(FdtNode<'a>);
+
+impl fmt::Display for Dts<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.write_dts(f)
+    }
+}
+
+fn write_node(f: &mut fmt::Formatter, node: &FdtNode, depth: usize) -> fmt::Result {
+    let name = node.name().or(Err(fmt::Error))?.to_bytes();
+    indent(f, depth)?;
+    if name.is_empty() {
+        f.write_char('/')?;
+    } else {
+        write!(f, "{}", name.escape_ascii())?;
+    }
+    f.write_str(" {\n")?;
+
+    for property in node.properties().or(Err(fmt::Error))? {
+        let name = property.name().or(Err(fmt::Error))?;
+        let value = property.value().or(Err(fmt::Error))?;
+        indent(f, depth + 1)?;
+        write!(f, "{}", name.to_bytes().escape_ascii())?;
+        write_value(f, value)?;
+        f.write_str(";\n")?;
+    }
+
+    let mut subnodes = node.subnodes().or(Err(fmt::Error))?.peekable();
+    if depth < MAX_DEPTH {
+        for subnode in subnodes {
+            write_node(f, &subnode, depth + 1)?;
+        }
+    } else if subnodes.peek().is_some() {
+        indent(f, depth + 1)?;
+        f.write_str("/* subnodes omitted */\n")?;
+    }
+
+    indent(f, depth)?;
+    f.write_str("};\n")
+}
+
+fn write_value(f: &mut fmt::Formatter, value: &[u8]) -> fmt::Result {
+    if value.is_empty() {
+        Ok(())
+    } else if is_string_list(value) {
+        f.write_str(" = ")?;
+        for (i, s) in value[..value.len() - 1].split(|b| *b == 0).enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "\"{}\"", s.escape_ascii())?;
+        }
+        Ok(())
+    } else if value.len() % 4 == 0 {
+        f.write_str(" = <")?;
+        for (i, cell) in value.chunks_exact(4).enumerate() {
+            if i > 0 {
+                f.write_char(' ')?;
+            }
+            write!(f, "{:#x}", u32::from_be_bytes([cell[0], cell[1], cell[2], cell[3]]))?;
+        }
+        f.write_char('>')
+    } else {
+        f.write_str(" = [")?;
+        for (i, byte) in value.iter().enumerate() {
+            if i > 0 {
+                f.write_char(' ')?;
+            }
+            write!(f, "{byte:02x}")?;
+        }
+        f.write_char(']')
+    }
+}
+
+/// Returns whether the value looks like a list of non-empty printable strings.
+fn is_string_list(value: &[u8]) -> bool {
+    value.first() != Some(&0)
+        && value.last() == Some(&0)
+        && !value.windows(2).any(|w| w == [0, 0])
+        && value.iter().all(|&b| b == 0 || b == b' ' || b.is_ascii_graphic())
+}
+
+fn indent(f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+    for _ in 0..depth {
+        f.write_char('\t')?;
+    }
+    Ok(())
+}
@@ -22,6 +22,7 @@ use crate::{AddrCells, SizeCells};
 use core::ffi::CStr;
 use core::marker::PhantomData;
 use core::{mem::size_of, ops::Range, slice::ChunksExact};
+use cstr::cstr;
 
 use zerocopy::transmute;
 
@@ -52,8 +53,99 @@ impl<'a> Iterator for CompatibleIterator<'a> {
     }
 }
 
+/// Iterator over nodes compatible with any of a list of strings, see
+/// [`Fdt::matching_compatible_nodes`].
+pub struct MatchingCompatibleIterator<'a> {
+    root: Option<FdtNode<'a>>,
+    descendants: DescendantsIterator<'a>,
+    candidates: &'a [&'a CStr],
+}
+
+impl<'a> MatchingCompatibleIterator<'a> {
+    pub(crate) fn new(fdt: &'a Fdt, candidates: &'a [&'a CStr]) -> Self {
+        let root = fdt.root();
+        Self { root: Some(root), descendants: root.descendants(), candidates }
+    }
+}
+
+impl<'a> Iterator for MatchingCompatibleIterator<'a> {
+    type Item = (FdtNode<'a>, &'a CStr);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let node = match self.root.take() {
+                Some(root) => root,
+                None => self.descendants.next()?.0,
+            };
+            for candidate in self.candidates {
+                if node.is_compatible(candidate).ok()? {
+                    return Some((node, *candidate));
+                }
+            }
+        }
+    }
+}
+
+/// Iterator over the available nodes with a given compatible string, see
+/// [`Fdt::available_compatible_nodes`].
+///
+/// Nodes whose status can't be read are returned as errors, rather than skipped.
+pub struct AvailableCompatibleIterator<'a> {
+    inner: CompatibleIterator<'a>,
+}
+
+impl<'a> AvailableCompatibleIterator<'a> {
+    pub(crate) fn new(fdt: &'a Fdt, compatible: &'a CStr) -> Result<Self, FdtError> {
+        Ok(Self { inner: CompatibleIterator::new(fdt, compatible)? })
+    }
+}
+
+impl<'a> Iterator for AvailableCompatibleIterator<'a> {
+    type Item = Result<FdtNode<'a>, FdtError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let node = self.inner.next()?;
+            match node.is_available() {
+                Ok(true) => return Some(Ok(node)),
+                Ok(false) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Iterator over the strings of a DT stringlist (e.g. the value of a compatible property).
+#[derive(Clone, Debug)]
+pub struct StringListIterator<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> StringListIterator<'a> {
+    /// Creates an iterator over the nul-separated strings of `bytes`.
+    ///
+    /// Fails with `BadValue` if `bytes` is non-empty and isn't nul-terminated.
+    pub fn new(bytes: &'a [u8]) -> Result<Self, FdtError> {
+        match bytes.last() {
+            None | Some(0) => Ok(Self { bytes }),
+            Some(_) => Err(FdtError::BadValue),
+        }
+    }
+}
+
+impl<'a> Iterator for StringListIterator<'a> {
+    type Item = &'a CStr;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let s = CStr::from_bytes_until_nul(self.bytes).ok()?;
+        self.bytes = &self.bytes[s.to_bytes_with_nul().len()..];
+
+        Some(s)
+    }
+}
+
 /// Iterator over cells of a DT property.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct CellIterator<'a> {
     chunks: ChunksExact<'a, u8>,
 }
@@ -64,6 +156,14 @@ impl<'a> CellIterator<'a> {
 
         Self { chunks: bytes.chunks_exact(CHUNK_SIZE) }
     }
+
+    /// Returns the trailing bytes of the property that don't form a complete cell, which the
+    /// iterator silently ignores.
+    ///
+    /// A non-empty remainder means that the property length isn't a multiple of the cell size.
+    pub fn remainder(&self) -> &'a [u8] {
+        self.chunks.remainder()
+    }
 }
 
 impl<'a> Iterator for CellIterator<'a> {
@@ -72,10 +172,45 @@ impl<'a> Iterator for CellIterator<'a> {
     fn next(&mut self) -> Option<Self::Item> {
         Some(Self::Item::from_be_bytes(self.chunks.next()?.try_into().ok()?))
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.chunks.size_hint()
+    }
 }
 
+impl ExactSizeIterator for CellIterator<'_> {}
+
+/// Iterator over the big-endian u64 values of a DT property, each made of a pair of cells.
+#[derive(Clone, Debug)]
+pub struct U64Iterator<'a> {
+    cells: CellIterator<'a>,
+}
+
+impl<'a> U64Iterator<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { cells: CellIterator::new(bytes) }
+    }
+}
+
+impl<'a> Iterator for U64Iterator<'a> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let high = self.cells.next()?;
+        let low = self.cells.next()?;
+        Some((u64::from(high) << 32) | u64::from(low))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.cells.len() / 2;
+        (len, Some(len))
+    }
+}
+
+impl ExactSizeIterator for U64Iterator<'_> {}
+
 /// Iterator over a 'reg' property of a DT node.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct RegIterator<'a> {
     cells: CellIterator<'a>,
     addr_cells: AddrCells,
@@ -116,12 +251,19 @@ impl<'a> RegIterator<'a> {
     ) -> Self {
         Self { cells, addr_cells, size_cells }
     }
-}
 
-impl<'a> Iterator for RegIterator<'a> {
-    type Item = Reg<u64>;
+    /// Converts the iterator into one yielding `Reg<u32>`, for nodes whose parent uses single
+    /// address and size cells.
+    ///
+    /// Fails with `BadNCells` if the parent uses double cells, which might not fit in `u32`.
+    pub fn into_u32(self) -> Result<U32RegIterator<'a>, FdtError> {
+        if self.addr_cells != AddrCells::Single || self.size_cells == SizeCells::Double {
+            return Err(FdtError::BadNCells);
+        }
+        Ok(U32RegIterator { reg: self })
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
+    fn next_reg<T: FromAddrCells + FromSizeCells>(&mut self) -> Option<Reg<T>> {
         let addr = FromAddrCells::from_addr_cells(&mut self.cells, self.addr_cells)?;
         // If the parent node specifies a value of 0 for #size-cells, 'size' shall be omitted.
         let size = if self.size_cells == SizeCells::None {
@@ -130,10 +272,51 @@ impl<'a> Iterator for RegIterator<'a> {
             Some(FromSizeCells::from_size_cells(&mut self.cells, self.size_cells)?)
         };
 
-        Some(Self::Item { addr, size })
+        Some(Reg { addr, size })
+    }
+}
+
+impl<'a> Iterator for RegIterator<'a> {
+    type Item = Reg<u64>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_reg()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // A trailing incomplete entry is never returned, so round down. The size cells are
+        // already accounted for as zero when 'size' is omitted.
+        let entry_cells = self.addr_cells as usize + self.size_cells as usize;
+        match self.cells.len().checked_div(entry_cells) {
+            Some(len) => (len, Some(len)),
+            None => (0, None),
+        }
+    }
+}
+
+impl ExactSizeIterator for RegIterator<'_> {}
+
+/// Iterator over a 'reg' property of a DT node using single address and size cells, see
+/// [`RegIterator::into_u32`].
+#[derive(Clone, Debug)]
+pub struct U32RegIterator<'a> {
+    reg: RegIterator<'a>,
+}
+
+impl<'a> Iterator for U32RegIterator<'a> {
+    type Item = Reg<u32>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.reg.next_reg()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.reg.size_hint()
     }
 }
 
+impl ExactSizeIterator for U32RegIterator<'_> {}
+
 impl Reg<u64> {
     const NUM_CELLS: usize = 2;
     /// Converts addr and (optional) size to the format that is consumable by libfdt.
@@ -150,7 +333,7 @@ impl Reg<u64> {
 }
 
 /// Iterator over the address ranges defined by the /memory/ node.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct MemRegIterator<'a> {
     reg: RegIterator<'a>,
 }
@@ -159,15 +342,138 @@ impl<'a> MemRegIterator<'a> {
     pub(crate) fn new(reg: RegIterator<'a>) -> Self {
         Self { reg }
     }
+
+    /// Returns the memory range that the next call to `next()` will return, without consuming it.
+    pub fn peek(&self) -> Option<Range<usize>> {
+        self.clone().next()
+    }
+
+    /// Returns the next memory range, or `None` once all of them have been returned.
+    ///
+    /// Unlike `next()`, which stops at the first invalid region, fails with `BadValue` for a region
+    /// whose bounds don't fit in `usize` and with `NotFound` for a region without a size.
+    pub fn try_next(&mut self) -> Option<Result<Range<usize>, FdtError>> {
+        Some(self.reg.next()?.try_into())
+    }
 }
 
 impl<'a> Iterator for MemRegIterator<'a> {
     type Item = Range<usize>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let next = self.reg.next()?;
-        let addr = usize::try_from(next.addr).ok()?;
-        let size = usize::try_from(next.size?).ok()?;
+        self.try_next()?.ok()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // Iteration stops early at the first region without a size or not fitting in usize.
+        (0, self.reg.size_hint().1)
+    }
+}
+
+/// Flags of a region described by a child of the /reserved-memory node.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ReservedMemFlags {
+    /// The OS must not create a virtual mapping of the region ("no-map").
+    pub no_map: bool,
+    /// The OS can use the memory of the region while it isn't used by its owner ("reusable").
+    pub reusable: bool,
+}
+
+/// A statically allocated region described by a child of the /reserved-memory node.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ReservedMemRegion<'a> {
+    /// Name of the node describing the region.
+    pub name: &'a CStr,
+    /// Physical address range of the region.
+    pub range: Range<usize>,
+    /// Flags of the region.
+    pub flags: ReservedMemFlags,
+}
+
+/// Iterator over the statically allocated regions described by the /reserved-memory node.
+///
+/// Dynamically allocated regions (i.e. using 'size' instead of 'reg') are skipped.
+#[derive(Debug)]
+pub struct ReservedMemIterator<'a> {
+    subnodes: Option<SubnodeIterator<'a>>,
+    current: Option<(FdtNode<'a>, MemRegIterator<'a>)>,
+}
+
+impl<'a> ReservedMemIterator<'a> {
+    pub(crate) fn new(node: Option<FdtNode<'a>>) -> Result<Self, FdtError> {
+        let subnodes = node.map(|node| node.subnodes()).transpose()?;
+
+        Ok(Self { subnodes, current: None })
+    }
+
+    /// Returns the next region, or `None` once all of them have been returned.
+    ///
+    /// Unlike `next()`, which stops at the first error, fails for a node whose properties can't be
+    /// read, and like `MemRegIterator::try_next` for an invalid region, then carries on with the
+    /// following ones.
+    pub fn try_next(&mut self) -> Option<Result<ReservedMemRegion<'a>, FdtError>> {
+        loop {
+            if let Some((node, regs)) = &mut self.current {
+                if let Some(range) = regs.try_next() {
+                    return Some(range.and_then(|range| Self::region(node, range)));
+                }
+            }
+            let node = self.subnodes.as_mut()?.next()?;
+            match node.reg() {
+                Ok(reg) => self.current = reg.map(|reg| (node, MemRegIterator::new(reg))),
+                Err(e) => {
+                    self.current = None;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+
+    fn region(node: &FdtNode<'a>, range: Range<usize>) -> Result<ReservedMemRegion<'a>, FdtError> {
+        let name = node.name()?;
+        let no_map = node.getprop(cstr!("no-map"))?.is_some();
+        let reusable = node.getprop(cstr!("reusable"))?.is_some();
+        let flags = ReservedMemFlags { no_map, reusable };
+
+        Ok(ReservedMemRegion { name, range, flags })
+    }
+}
+
+impl<'a> Iterator for ReservedMemIterator<'a> {
+    type Item = ReservedMemRegion<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next()?.ok()
+    }
+}
+
+/// Iterator over the entries of the memory reservation block of a DT.
+#[derive(Debug)]
+pub struct MemReservationIterator<'a> {
+    fdt: &'a Fdt,
+    index: usize,
+    count: usize,
+}
+
+impl<'a> MemReservationIterator<'a> {
+    pub(crate) fn new(fdt: &'a Fdt) -> Result<Self, FdtError> {
+        let count = fdt.mem_reservation_count()?;
+
+        Ok(Self { fdt, index: 0, count })
+    }
+}
+
+impl<'a> Iterator for MemReservationIterator<'a> {
+    type Item = Range<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index >= self.count {
+            return None;
+        }
+        let (addr, size) = self.fdt.mem_reservation(self.index).ok()?;
+        self.index += 1;
+        let addr = usize::try_from(addr).ok()?;
+        let size = usize::try_from(size).ok()?;
 
         Some(addr..addr.checked_add(size)?)
     }
@@ -232,6 +538,15 @@ trait FromAddrCells: Sized {
     fn from_addr_cells(cells: &mut CellIterator, cell_count: AddrCells) -> Option<Self>;
 }
 
+impl FromAddrCells for u32 {
+    fn from_addr_cells(cells: &mut CellIterator, cell_count: AddrCells) -> Option<Self> {
+        match cell_count {
+            AddrCells::Single => cells.next(),
+            _ => panic!("Invalid addr_cells {:?} for u32", cell_count),
+        }
+    }
+}
+
 impl FromAddrCells for u64 {
     fn from_addr_cells(cells: &mut CellIterator, cell_count: AddrCells) -> Option<Self> {
         Some(match cell_count {
@@ -257,6 +572,15 @@ trait FromSizeCells: Sized {
     fn from_size_cells(cells: &mut CellIterator, cell_count: SizeCells) -> Option<Self>;
 }
 
+impl FromSizeCells for u32 {
+    fn from_size_cells(cells: &mut CellIterator, cell_count: SizeCells) -> Option<Self> {
+        match cell_count {
+            SizeCells::Single => cells.next(),
+            _ => panic!("Invalid size_cells {:?} for u32", cell_count),
+        }
+    }
+}
+
 impl FromSizeCells for u64 {
     fn from_size_cells(cells: &mut CellIterator, cell_count: SizeCells) -> Option<Self> {
         Some(match cell_count {
@@ -341,7 +665,7 @@ pub struct PropertyIterator<'a> {
 }
 
 impl<'a> PropertyIterator<'a> {
-    pub(crate) fn new(node: &'a FdtNode) -> Result<Self, FdtError> {
+    pub(crate) fn new(node: &FdtNode<'a>) -> Result<Self, FdtError> {
         let prop = node.first_property()?;
 
         Ok(Self { prop })
@@ -359,3 +683,76 @@ impl<'a> Iterator for PropertyIterator<'a> {
         res
     }
 }
+
+/// An entry of the `interrupts` or `interrupts-extended` property of a DT node.
+#[derive(Debug)]
+pub struct Interrupt<'a> {
+    /// Interrupt controller of the entry.
+    pub parent: FdtNode<'a>,
+    /// Interrupt specifier, as many cells as the `#interrupt-cells` of the controller.
+    pub cells: CellIterator<'a>,
+}
+
+/// Iterator over the entries of the `interrupts` or `interrupts-extended` property of a DT node.
+#[derive(Clone, Debug)]
+pub struct InterruptIterator<'a> {
+    fdt: &'a Fdt,
+    /// Interrupt controller shared by all entries, or None if each entry starts with its phandle.
+    parent: Option<FdtNode<'a>>,
+    bytes: &'a [u8],
+}
+
+impl<'a> InterruptIterator<'a> {
+    /// Creates an iterator over `bytes`, after checking that all its entries are well-formed.
+    pub(crate) fn new(
+        fdt: &'a Fdt,
+        parent: Option<FdtNode<'a>>,
+        bytes: &'a [u8],
+    ) -> Result<Self, FdtError> {
+        let iter = Self { fdt, parent, bytes };
+        let mut entries = iter.clone();
+        while entries.try_next()?.is_some() {}
+
+        Ok(iter)
+    }
+
+    fn try_next(&mut self) -> Result<Option<Interrupt<'a>>, FdtError> {
+        const CELL_SIZE: usize = size_of::<u32>();
+
+        if self.bytes.is_empty() {
+            return Ok(None);
+        }
+        let parent = if let Some(parent) = self.parent {
+            parent
+        } else {
+            if self.bytes.len() < CELL_SIZE {
+                return Err(FdtError::BadValue);
+            }
+            let (phandle, rest) = self.bytes.split_at(CELL_SIZE);
+            self.bytes = rest;
+            let phandle = u32::from_be_bytes(phandle.try_into().unwrap()).try_into()?;
+            self.fdt.node_with_phandle(phandle)?.ok_or(FdtError::BadPhandle)?
+        };
+        let cells = parent.getprop_u32(cstr!("#interrupt-cells"))?.ok_or(FdtError::BadNCells)?;
+        let len = usize::try_from(cells)
+            .ok()
+            .and_then(|cells| cells.checked_mul(CELL_SIZE))
+            .ok_or(FdtError::BadNCells)?;
+        if self.bytes.len() < len || (len == 0 && self.parent.is_some()) {
+            return Err(FdtError::BadValue);
+        }
+        let (cells, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+
+        Ok(Some(Interrupt { parent, cells: CellIterator::new(cells) }))
+    }
+}
+
+impl<'a> Iterator for InterruptIterator<'a> {
+    type Item = Interrupt<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Entries were all validated by the constructor.
+        self.try_next().ok().flatten()
+    }
+}
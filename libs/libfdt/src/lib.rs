@@ -17,23 +17,30 @@
 
 #![no_std]
 
+mod dts;
 mod iterators;
 mod libfdt;
 mod result;
 mod safe_types;
 
+pub use dts::Dts;
 pub use iterators::{
-    AddressRange, CellIterator, CompatibleIterator, DescendantsIterator, MemRegIterator,
-    PropertyIterator, RangesIterator, Reg, RegIterator, SubnodeIterator,
+    AddressRange, AvailableCompatibleIterator, CellIterator, CompatibleIterator,
+    DescendantsIterator, Interrupt, InterruptIterator, MatchingCompatibleIterator, MemRegIterator,
+    MemReservationIterator, PropertyIterator, RangesIterator, Reg, RegIterator, ReservedMemFlags,
+    ReservedMemIterator, ReservedMemRegion, StringListIterator, SubnodeIterator, U32RegIterator,
+    U64Iterator,
 };
 pub use result::{FdtError, Result};
 pub use safe_types::{FdtHeader, NodeOffset, Phandle, PropOffset, StringOffset};
 
 use core::ffi::{c_void, CStr};
+use core::iter;
+use core::mem;
 use core::ops::Range;
 use cstr::cstr;
 use libfdt::get_slice_at_ptr;
-use zerocopy::AsBytes as _;
+use zerocopy::{AsBytes as _, FromBytes as _};
 
 use crate::libfdt::{Libfdt, LibfdtMut};
 
@@ -134,6 +141,11 @@ impl<'a> FdtProperty<'a> {
         self.fdt.get_from_ptr(self.property.data_ptr(), self.property.data_len())
     }
 
+    /// Returns the offset of the property, see [`FdtNode::getprop_by_offset`].
+    pub fn offset(&self) -> PropOffset {
+        self.offset
+    }
+
     fn next_property(&self) -> Result<Option<Self>> {
         if let Some(offset) = self.fdt.next_property_offset(self.offset)? {
             Ok(Some(Self::new(self.fdt, offset)?))
@@ -166,11 +178,24 @@ impl<'a> FdtNode<'a> {
     }
 
     /// Returns the standard (deprecated) device_type <string> property.
-    pub fn device_type(&self) -> Result<Option<&CStr>> {
+    pub fn device_type(&self) -> Result<Option<&'a CStr>> {
         self.getprop_str(cstr!("device_type"))
     }
 
+    /// Returns whether the node is operational, based on its standard status <string> property.
+    ///
+    /// A node without a status property is operational.
+    pub fn is_available(&self) -> Result<bool> {
+        match self.getprop_str(cstr!("status"))? {
+            None => Ok(true),
+            Some(status) => Ok(status == cstr!("okay") || status == cstr!("ok")),
+        }
+    }
+
     /// Returns the standard reg <prop-encoded-array> property.
+    ///
+    /// Returns `None` if the node has no reg property and an empty iterator if the property is
+    /// present but empty, so that callers can tell the two cases apart.
     pub fn reg(&self) -> Result<Option<RegIterator<'a>>> {
         if let Some(cells) = self.getprop_cells(cstr!("reg"))? {
             let parent = self.parent()?;
@@ -184,6 +209,50 @@ impl<'a> FdtNode<'a> {
         }
     }
 
+    /// Returns whether the node has a reg property, even if it is empty.
+    pub fn has_reg(&self) -> Result<bool> {
+        self.getprop_bool(cstr!("reg"))
+    }
+
+    /// Returns the interrupt controller of this node.
+    ///
+    /// Follows the `interrupt-parent` phandles or, in their absence, the DT parents until a node
+    /// with `#interrupt-cells` is found.
+    pub fn interrupt_parent(&self) -> Result<Self> {
+        // Bound the walk, as phandles could form a cycle.
+        const MAX_HOPS: usize = 64;
+        let mut node = *self;
+
+        for _ in 0..MAX_HOPS {
+            node = match node.getprop_u32(cstr!("interrupt-parent"))? {
+                Some(phandle) => {
+                    self.fdt.node_with_phandle(phandle.try_into()?)?.ok_or(FdtError::BadPhandle)?
+                }
+                None => node.parent()?,
+            };
+            if node.getprop(cstr!("#interrupt-cells"))?.is_some() {
+                return Ok(node);
+            }
+        }
+
+        Err(FdtError::BadStructure)
+    }
+
+    /// Returns the interrupts of this node, from `interrupts-extended` if present or else from
+    /// `interrupts`, split according to the `#interrupt-cells` of their controllers.
+    ///
+    /// Fails with `BadValue` if the property length doesn't match the expected cells.
+    pub fn interrupts(&self) -> Result<Option<InterruptIterator<'a>>> {
+        if let Some(bytes) = self.getprop(cstr!("interrupts-extended"))? {
+            Ok(Some(InterruptIterator::new(self.fdt, None, bytes)?))
+        } else if let Some(bytes) = self.getprop(cstr!("interrupts"))? {
+            let parent = self.interrupt_parent()?;
+            Ok(Some(InterruptIterator::new(self.fdt, Some(parent), bytes)?))
+        } else {
+            Ok(None)
+        }
+    }
+
     /// Returns the standard ranges property.
     pub fn ranges<A, P, S>(&self) -> Result<Option<RangesIterator<'a, A, P, S>>> {
         if let Some(cells) = self.getprop_cells(cstr!("ranges"))? {
@@ -202,14 +271,40 @@ impl<'a> FdtNode<'a> {
         }
     }
 
-    /// Returns the node name.
+    /// Translates an address of the child bus defined by this node into an address of the parent
+    /// bus, using the first matching entry of its ranges property.
+    ///
+    /// An empty ranges property is an identity mapping. Fails with `NotFound` if the node has no
+    /// ranges property or if no entry covers the address.
+    pub fn translate_child_address(&self, child: u64) -> Result<u64> {
+        if self.address_cells()? == AddrCells::Triple
+            || self.parent()?.address_cells()? == AddrCells::Triple
+        {
+            return Err(FdtError::BadNCells);
+        }
+        let mut ranges = self.ranges::<u64, u64, u64>()?.ok_or(FdtError::NotFound)?.peekable();
+        if ranges.peek().is_none() {
+            return Ok(child);
+        }
+        for range in ranges {
+            if let Some(offset) = child.checked_sub(range.addr).filter(|x| *x < range.size) {
+                return range.parent_addr.checked_add(offset).ok_or(FdtError::BadValue);
+            }
+        }
+
+        Err(FdtError::NotFound)
+    }
+
+    /// Returns the node name, including its unit-address (e.g. `memory@40000000`), if any.
+    ///
+    /// The name of the root node is the empty string.
     pub fn name(&self) -> Result<&'a CStr> {
         let name = self.fdt.get_name(self.offset)?;
         CStr::from_bytes_with_nul(name).map_err(|_| FdtError::Internal)
     }
 
     /// Returns the value of a given <string> property.
-    pub fn getprop_str(&self, name: &CStr) -> Result<Option<&CStr>> {
+    pub fn getprop_str(&self, name: &CStr) -> Result<Option<&'a CStr>> {
         if let Some(bytes) = self.getprop(name)? {
             Ok(Some(CStr::from_bytes_with_nul(bytes).map_err(|_| FdtError::BadValue)?))
         } else {
@@ -217,6 +312,21 @@ impl<'a> FdtNode<'a> {
         }
     }
 
+    /// Returns the value of a given boolean property, which is true if the property is present,
+    /// whatever its value.
+    pub fn getprop_bool(&self, name: &CStr) -> Result<bool> {
+        Ok(self.getprop(name)?.is_some())
+    }
+
+    /// Returns the value of a given <stringlist> property.
+    pub fn getprop_strings(&self, name: &CStr) -> Result<Option<StringListIterator<'a>>> {
+        if let Some(bytes) = self.getprop(name)? {
+            Ok(Some(StringListIterator::new(bytes)?))
+        } else {
+            Ok(None)
+        }
+    }
+
     /// Returns the value of a given property as an array of cells.
     pub fn getprop_cells(&self, name: &CStr) -> Result<Option<CellIterator<'a>>> {
         if let Some(cells) = self.getprop(name)? {
@@ -244,6 +354,34 @@ impl<'a> FdtNode<'a> {
         }
     }
 
+    /// Returns the value of a given <u32> array property.
+    ///
+    /// Fails with `BadValue` if the length of the property isn't a multiple of 4 bytes.
+    pub fn getprop_u32_array(&self, name: &CStr) -> Result<Option<CellIterator<'a>>> {
+        if let Some(bytes) = self.getprop(name)? {
+            if bytes.len() % mem::size_of::<u32>() != 0 {
+                return Err(FdtError::BadValue);
+            }
+            Ok(Some(CellIterator::new(bytes)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Returns the value of a given <u64> array property.
+    ///
+    /// Fails with `BadValue` if the length of the property isn't a multiple of 8 bytes.
+    pub fn getprop_u64_array(&self, name: &CStr) -> Result<Option<U64Iterator<'a>>> {
+        if let Some(bytes) = self.getprop(name)? {
+            if bytes.len() % mem::size_of::<u64>() != 0 {
+                return Err(FdtError::BadValue);
+            }
+            Ok(Some(U64Iterator::new(bytes)))
+        } else {
+            Ok(None)
+        }
+    }
+
     /// Returns the value of a given property.
     pub fn getprop(&self, name: &CStr) -> Result<Option<&'a [u8]>> {
         self.fdt.getprop_namelen(self.offset, name.to_bytes())
@@ -261,11 +399,24 @@ impl<'a> FdtNode<'a> {
         Ok(offset.map(|offset| Self { fdt: self.fdt, offset }))
     }
 
+    /// Returns whether any of the strings of the compatible property matches the given one.
+    ///
+    /// Returns `false` if the node doesn't have a compatible property.
+    pub fn is_compatible(&self, compatible: &CStr) -> Result<bool> {
+        self.fdt.node_check_compatible(self.offset, compatible)
+    }
+
     /// Returns the first range of `reg` in this node.
     pub fn first_reg(&self) -> Result<Reg<u64>> {
         self.reg()?.ok_or(FdtError::NotFound)?.next().ok_or(FdtError::NotFound)
     }
 
+    /// Returns the number of cells used to encode addresses and sizes in the `reg` properties of
+    /// this node's children i.e. the values of its #address-cells and #size-cells properties.
+    pub fn addr_size_cells(&self) -> Result<(usize, usize)> {
+        Ok((self.address_cells()? as usize, self.size_cells()? as usize))
+    }
+
     fn address_cells(&self) -> Result<AddrCells> {
         self.fdt.address_cells(self.offset)?.try_into()
     }
@@ -305,7 +456,7 @@ impl<'a> FdtNode<'a> {
     }
 
     /// Returns an iterator of properties
-    pub fn properties(&'a self) -> Result<PropertyIterator<'a>> {
+    pub fn properties(&self) -> Result<PropertyIterator<'a>> {
         PropertyIterator::new(self)
     }
 
@@ -329,6 +480,14 @@ impl<'a> FdtNode<'a> {
         }
     }
 
+    /// Returns the name and value of the property at the given offset, e.g. one returned by
+    /// [`FdtProperty::offset`], without looking it up by name.
+    ///
+    /// The offset is expected to be one of a property of this node.
+    pub fn getprop_by_offset(&self, offset: PropOffset) -> Result<(&'a CStr, &'a [u8])> {
+        self.fdt.getprop_by_offset(offset)
+    }
+
     /// Returns the subnode of the given name. The name doesn't need to be nul-terminated.
     pub fn subnode(&self, name: &CStr) -> Result<Option<Self>> {
         let name = name.to_bytes();
@@ -343,6 +502,20 @@ impl<'a> FdtNode<'a> {
 
         Ok(offset.map(|offset| Self { fdt: self.fdt, offset }))
     }
+
+    /// Returns the descendant at the given path, relative to this node (e.g. `"bus/dma"`).
+    ///
+    /// Like for absolute paths, repeated slashes are ignored, so an empty path returns this node.
+    pub fn descendant(&self, path: &CStr) -> Result<Option<Self>> {
+        let mut node = *self;
+        for name in path.to_bytes().split(|b| *b == b'/').filter(|name| !name.is_empty()) {
+            match node.subnode_with_name_bytes(name)? {
+                Some(subnode) => node = subnode,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(node))
+    }
 }
 
 impl<'a> PartialEq for FdtNode<'a> {
@@ -352,6 +525,11 @@ impl<'a> PartialEq for FdtNode<'a> {
 }
 
 /// Mutable FDT node.
+///
+/// The node is identified by its offset in the structure block, which structural edits (e.g.
+/// [`FdtNodeMut::add_subnode`] or [`FdtNodeMut::delete`]) may shift. Methods performing such edits
+/// consume the handle and, where applicable, return one that is valid for the edited tree; other
+/// nodes must be looked up again, e.g. with [`Fdt::node_mut`].
 #[derive(Debug)]
 pub struct FdtNodeMut<'a> {
     fdt: &'a mut Fdt,
@@ -372,7 +550,8 @@ impl<'a> FdtNodeMut<'a> {
 
     /// Sets a property name-value pair to the given node.
     ///
-    /// This may create a new prop or replace existing value.
+    /// This may create a new prop or replace existing value. Fails with `NoSpace` if the DT
+    /// would need to grow beyond its current totalsize; see [`Fdt::unpack`].
     pub fn setprop(&mut self, name: &CStr, value: &[u8]) -> Result<()> {
         self.fdt.setprop(self.offset, name, value)
     }
@@ -564,6 +743,45 @@ impl<'a> FdtNodeMut<'a> {
     pub fn nop(self) -> Result<()> {
         self.fdt.nop_node(self.offset)
     }
+
+    /// Removes this node and its subnodes from the DT, shrinking its structure block.
+    ///
+    /// Unlike [`FdtNodeMut::nop`], this moves the following nodes so the offsets of any other
+    /// nodes are invalidated.
+    pub fn delete(self) -> Result<()> {
+        self.fdt.del_node(self.offset)
+    }
+}
+
+/// Decodes a big-endian address made of one or two cells.
+fn decode_address(bytes: &[u8]) -> Result<usize> {
+    let address = if let Ok(bytes) = bytes.try_into() {
+        u32::from_be_bytes(bytes).into()
+    } else if let Ok(bytes) = bytes.try_into() {
+        u64::from_be_bytes(bytes)
+    } else {
+        return Err(FdtError::BadValue);
+    };
+    address.try_into().map_err(|_| FdtError::BadValue)
+}
+
+/// Removes a single trailing slash from a node path, unless it is the root path.
+fn strip_trailing_slash(path: &[u8]) -> &[u8] {
+    match path.strip_suffix(b"/") {
+        Some(stripped) if !stripped.is_empty() => stripped,
+        _ => path,
+    }
+}
+
+/// Alignment of the base address of a DT, required by libfdt.
+const FDT_ALIGNMENT: usize = 8;
+
+fn check_alignment(fdt: &[u8]) -> Result<()> {
+    if (fdt.as_ptr() as usize) % FDT_ALIGNMENT == 0 {
+        Ok(())
+    } else {
+        Err(FdtError::Alignment)
+    }
 }
 
 /// Wrapper around low-level libfdt functions.
@@ -577,7 +795,8 @@ pub struct Fdt {
 // methods to be called on invalid device trees.
 unsafe impl Libfdt for Fdt {
     fn as_fdt_slice(&self) -> &[u8] {
-        &self.buffer[..self.totalsize()]
+        // A validated DT's totalsize fits in the containing buffer, so it fits in a usize.
+        &self.buffer[..self.totalsize().unwrap()]
     }
 }
 
@@ -592,8 +811,10 @@ unsafe impl LibfdtMut for Fdt {
 impl Fdt {
     /// Wraps a slice containing a Flattened Device Tree.
     ///
-    /// Fails if the FDT does not pass validation.
+    /// Fails with `Alignment` if the slice isn't 8-byte aligned, as required by libfdt, or if the
+    /// FDT does not pass validation.
     pub fn from_slice(fdt: &[u8]) -> Result<&Self> {
+        check_alignment(fdt)?;
         libfdt::check_full(fdt)?;
         // SAFETY: The FDT was validated.
         let fdt = unsafe { Self::unchecked_from_slice(fdt) };
@@ -601,10 +822,50 @@ impl Fdt {
         Ok(fdt)
     }
 
+    /// Wraps a slice containing a Flattened Device Tree, only validating its header.
+    ///
+    /// This is cheaper than [`Fdt::from_slice`], which also walks the structure block, and is
+    /// meant for hot paths where a DT is known to be valid and the header check is a sanity check.
+    ///
+    /// Fails with `Alignment` if the slice isn't 8-byte aligned, with `Truncated` if the DT doesn't
+    /// fit in the slice, or if the header does not pass validation.
+    ///
+    /// # Safety
+    ///
+    /// Only the header is validated, so the caller must ensure that the rest of the DT is valid,
+    /// e.g. because it was validated with [`Fdt::from_slice`] before, or comes from a trusted
+    /// source. Use [`Fdt::from_slice`] otherwise.
+    pub unsafe fn from_slice_header_only(fdt: &[u8]) -> Result<&Self> {
+        check_alignment(fdt)?;
+        libfdt::check_header(fdt)?;
+        // SAFETY: The header was validated and the caller guarantees that the rest is valid.
+        let fdt = unsafe { Self::unchecked_from_slice(fdt) };
+
+        Ok(fdt)
+    }
+
+    /// Wraps the Flattened Device Tree at the start of a slice, and returns it along with the bytes
+    /// of the slice that follow it.
+    ///
+    /// Fails with `Truncated` if the totalsize from the header exceeds the length of the slice, or
+    /// if the FDT does not pass validation.
+    pub fn from_slice_with_remainder(fdt: &[u8]) -> Result<(&Self, &[u8])> {
+        let header = FdtHeader::ref_from_prefix(fdt).ok_or(FdtError::Truncated)?;
+        let totalsize = usize::try_from(header.totalsize.get()).or(Err(FdtError::Truncated))?;
+        if totalsize > fdt.len() {
+            return Err(FdtError::Truncated);
+        }
+        let (fdt, remainder) = fdt.split_at(totalsize);
+
+        Ok((Self::from_slice(fdt)?, remainder))
+    }
+
     /// Wraps a mutable slice containing a Flattened Device Tree.
     ///
-    /// Fails if the FDT does not pass validation.
+    /// Fails with `Alignment` if the slice isn't 8-byte aligned, as required by libfdt, or if the
+    /// FDT does not pass validation.
     pub fn from_mut_slice(fdt: &mut [u8]) -> Result<&mut Self> {
+        check_alignment(fdt)?;
         libfdt::check_full(fdt)?;
         // SAFETY: The FDT was validated.
         let fdt = unsafe { Self::unchecked_from_mut_slice(fdt) };
@@ -648,7 +909,7 @@ impl Fdt {
             return Err(FdtError::NoSpace);
         }
 
-        let zeroed_len = self.totalsize().checked_sub(new_len);
+        let zeroed_len = self.totalsize()?.checked_sub(new_len);
         let (cloned, zeroed) = self.buffer.split_at_mut(new_len);
 
         cloned.clone_from_slice(&other.buffer);
@@ -664,11 +925,15 @@ impl Fdt {
         self.open_into_self()
     }
 
-    /// Packs the DT to take a minimum amount of memory.
+    /// Packs the DT to take a minimum amount of memory, and returns the number of bytes reclaimed.
     ///
-    /// Doesn't shrink the underlying memory slice.
-    pub fn pack(&mut self) -> Result<()> {
-        LibfdtMut::pack(self)
+    /// Doesn't shrink the underlying memory slice: the bytes past the new totalsize are left in the
+    /// buffer but are no longer part of the tree.
+    pub fn pack(&mut self) -> Result<usize> {
+        let size_before = self.totalsize()?;
+        LibfdtMut::pack(self)?;
+
+        Ok(size_before.saturating_sub(self.totalsize()?))
     }
 
     /// Applies a DT overlay on the base DT.
@@ -691,7 +956,7 @@ impl Fdt {
     /// Returns an iterator of memory banks specified the "/memory" node.
     /// Throws an error when the "/memory" is not found in the device tree.
     ///
-    /// NOTE: This does not support individual "/memory@XXXX" banks.
+    /// NOTE: This does not support individual "/memory@XXXX" banks, see [`Fdt::all_memory`].
     pub fn memory(&self) -> Result<MemRegIterator> {
         let node = self.root().subnode(cstr!("memory"))?.ok_or(FdtError::NotFound)?;
         if node.device_type()? != Some(cstr!("memory")) {
@@ -700,11 +965,58 @@ impl Fdt {
         node.reg()?.ok_or(FdtError::BadValue).map(MemRegIterator::new)
     }
 
+    /// Returns an iterator of memory banks specified by all the "/memory" and "/memory@XXXX"
+    /// nodes i.e. the children of the root node with a "memory" device_type.
+    ///
+    /// Yields an error, and then continues with the next node, for a child whose device_type can't
+    /// be read or a memory node without a valid "reg". Also yields the errors of
+    /// `MemRegIterator::try_next` for the banks themselves.
+    pub fn all_memory(&self) -> Result<impl Iterator<Item = Result<Range<usize>>> + '_> {
+        let mut nodes = self.root().subnodes()?.filter_map(|node| match node.device_type() {
+            Ok(Some(device_type)) if device_type == cstr!("memory") => {
+                Some(node.reg().and_then(|reg| reg.ok_or(FdtError::BadValue)))
+            }
+            Ok(_) => None,
+            Err(e) => Some(Err(e)),
+        });
+        let mut banks: Option<MemRegIterator> = None;
+
+        Ok(iter::from_fn(move || loop {
+            if let Some(bank) = banks.as_mut().and_then(MemRegIterator::try_next) {
+                return Some(bank);
+            }
+            match nodes.next()? {
+                Ok(reg) => banks = Some(MemRegIterator::new(reg)),
+                Err(e) => return Some(Err(e)),
+            }
+        }))
+    }
+
     /// Returns the first memory range in the `/memory` node.
     pub fn first_memory_range(&self) -> Result<Range<usize>> {
         self.memory()?.next().ok_or(FdtError::NotFound)
     }
 
+    /// Returns an iterator of the regions statically reserved by the children of the
+    /// "/reserved-memory" node. The iterator is empty if the node doesn't exist.
+    pub fn reserved_memory(&self) -> Result<ReservedMemIterator> {
+        ReservedMemIterator::new(self.root().subnode(cstr!("reserved-memory"))?)
+    }
+
+    /// Returns an iterator of the entries of the memory reservation block (/memreserve/).
+    pub fn mem_reservations(&self) -> Result<MemReservationIterator> {
+        MemReservationIterator::new(self)
+    }
+
+    /// Returns the number of entries in the memory reservation block (/memreserve/).
+    pub fn mem_reservation_count(&self) -> Result<usize> {
+        self.num_mem_rsv()
+    }
+
+    fn mem_reservation(&self, index: usize) -> Result<(u64, u64)> {
+        self.get_mem_rsv(index)
+    }
+
     /// Returns the standard /chosen node.
     pub fn chosen(&self) -> Result<Option<FdtNode>> {
         self.root().subnode(cstr!("chosen"))
@@ -715,6 +1027,58 @@ impl Fdt {
         self.node_mut(cstr!("/chosen"))
     }
 
+    /// Returns the standard /chosen/bootargs <string> property.
+    pub fn bootargs(&self) -> Result<Option<&CStr>> {
+        match self.chosen()? {
+            Some(chosen) => chosen.getprop_str(cstr!("bootargs")),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the address range of the initrd, from the /chosen/linux,initrd-start and
+    /// /chosen/linux,initrd-end properties, which may each be either 32-bit or 64-bit.
+    ///
+    /// Returns `None` unless both properties are present, and fails with `BadValue` if the range
+    /// ends before it starts.
+    pub fn initrd_range(&self) -> Result<Option<Range<usize>>> {
+        let Some(chosen) = self.chosen()? else {
+            return Ok(None);
+        };
+        let start = chosen.getprop(cstr!("linux,initrd-start"))?;
+        let end = chosen.getprop(cstr!("linux,initrd-end"))?;
+        let (Some(start), Some(end)) = (start, end) else {
+            return Ok(None);
+        };
+        let (start, end) = (decode_address(start)?, decode_address(end)?);
+        if end < start {
+            return Err(FdtError::BadValue);
+        }
+        Ok(Some(start..end))
+    }
+
+    /// Returns the standard /aliases node.
+    pub fn aliases(&self) -> Result<Option<FdtNode>> {
+        self.root().subnode(cstr!("aliases"))
+    }
+
+    /// Returns the full path of the node referred to by the given alias.
+    pub fn alias(&self, alias: &CStr) -> Result<Option<&CStr>> {
+        if let Some(aliases) = self.aliases()? {
+            aliases.getprop_str(alias)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Returns the node referred to by the given alias.
+    pub fn node_by_alias(&self, alias: &CStr) -> Result<Option<FdtNode>> {
+        if let Some(path) = self.alias(alias)? {
+            self.node(path)
+        } else {
+            Ok(None)
+        }
+    }
+
     /// Returns the root node of the tree.
     pub fn root(&self) -> FdtNode {
         FdtNode { fdt: self, offset: NodeOffset::ROOT }
@@ -730,9 +1094,9 @@ impl Fdt {
         self.node_mut(cstr!("/__symbols__"))
     }
 
-    /// Returns a tree node by its full path.
+    /// Returns a tree node by its full path, which may end with a slash.
     pub fn node(&self, path: &CStr) -> Result<Option<FdtNode>> {
-        let offset = self.path_offset_namelen(path.to_bytes())?;
+        let offset = self.path_offset_namelen(strip_trailing_slash(path.to_bytes()))?;
 
         Ok(offset.map(|offset| FdtNode { fdt: self, offset }))
     }
@@ -742,6 +1106,32 @@ impl Fdt {
         CompatibleIterator::new(self, compatible)
     }
 
+    /// Returns the first node with a given compatible string, if any.
+    pub fn find_compatible(&self, compatible: &CStr) -> Result<Option<FdtNode>> {
+        self.root().next_compatible(compatible)
+    }
+
+    /// Iterate over the nodes compatible with any of the `candidates`, along with the candidate
+    /// that each of them matched.
+    ///
+    /// Nodes are visited in DT order. When a node matches several candidates, the one appearing
+    /// first in `candidates` is returned, so they should be listed from most to least specific.
+    pub fn matching_compatible_nodes<'a>(
+        &'a self,
+        candidates: &'a [&'a CStr],
+    ) -> MatchingCompatibleIterator<'a> {
+        MatchingCompatibleIterator::new(self, candidates)
+    }
+
+    /// Iterate over the available nodes (see [`FdtNode::is_available`]) with a given compatible
+    /// string, skipping the disabled ones.
+    pub fn available_compatible_nodes<'a>(
+        &'a self,
+        compatible: &'a CStr,
+    ) -> Result<AvailableCompatibleIterator<'a>> {
+        AvailableCompatibleIterator::new(self, compatible)
+    }
+
     /// Returns max phandle in the tree.
     pub fn max_phandle(&self) -> Result<Phandle> {
         self.find_max_phandle()
@@ -766,9 +1156,9 @@ impl Fdt {
         FdtNodeMut { fdt: self, offset: NodeOffset::ROOT }
     }
 
-    /// Returns a mutable tree node by its full path.
+    /// Returns a mutable tree node by its full path, which may end with a slash.
     pub fn node_mut(&mut self, path: &CStr) -> Result<Option<FdtNodeMut>> {
-        let offset = self.path_offset_namelen(path.to_bytes())?;
+        let offset = self.path_offset_namelen(strip_trailing_slash(path.to_bytes()))?;
 
         Ok(offset.map(|offset| FdtNodeMut { fdt: self, offset }))
     }
@@ -794,6 +1184,37 @@ impl Fdt {
         self.as_fdt_slice()
     }
 
+    /// Returns the whole buffer containing the device tree.
+    ///
+    /// Unlike [`Fdt::as_slice`], this includes any slack after the first `totalsize()` bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Returns the length of the buffer containing the device tree, which may be larger than
+    /// `totalsize()`.
+    pub fn buffer_len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Returns an iterator over the strings of the strings block, e.g. the property names.
+    ///
+    /// Fails with `BadVersion` if the DT is too old to record the size of the block, with
+    /// `BadOffset` if the block doesn't fit within the DT, or with `BadValue` if it isn't
+    /// nul-terminated.
+    pub fn strings(&self) -> Result<StringListIterator> {
+        let header = self.header();
+        if header.version.get() < 3 {
+            return Err(FdtError::BadVersion);
+        }
+        let offset = usize::try_from(header.off_dt_strings.get()).or(Err(FdtError::BadOffset))?;
+        let size = usize::try_from(header.size_dt_strings.get()).or(Err(FdtError::BadOffset))?;
+        let end = offset.checked_add(size).ok_or(FdtError::BadOffset)?;
+        let block = self.as_slice().get(offset..end).ok_or(FdtError::BadOffset)?;
+
+        StringListIterator::new(block)
+    }
+
     fn get_from_ptr(&self, ptr: *const c_void, len: usize) -> Result<&[u8]> {
         get_slice_at_ptr(self.as_fdt_slice(), ptr.cast(), len).ok_or(FdtError::Internal)
     }
@@ -803,14 +1224,114 @@ impl Fdt {
         self.buffer.as_ptr().cast()
     }
 
-    fn header(&self) -> &FdtHeader {
+    /// Returns the header of the device tree.
+    pub fn header(&self) -> &FdtHeader {
         let p = self.as_ptr().cast::<libfdt_bindgen::fdt_header>();
         // SAFETY: A valid FDT (verified by constructor) must contain a valid fdt_header.
         let header = unsafe { &*p };
         header.as_ref()
     }
 
-    fn totalsize(&self) -> usize {
-        self.header().totalsize.get().try_into().unwrap()
+    /// Returns the total size of the device tree, as reported by its header.
+    ///
+    /// For a validated DT, this never exceeds the length of the containing buffer.
+    pub fn totalsize(&self) -> Result<usize> {
+        self.header().totalsize.get().try_into().map_err(|_| FdtError::BadValue)
+    }
+
+    /// Returns the magic number of the device tree, as reported by its header.
+    pub fn magic(&self) -> u32 {
+        self.header().magic.get()
+    }
+
+    /// Returns the version of the device tree format, as reported by its header.
+    pub fn version(&self) -> u32 {
+        self.header().version.get()
+    }
+
+    /// Returns the oldest version of the DT format that the device tree is backwards compatible
+    /// with, as reported by its header.
+    pub fn last_comp_version(&self) -> u32 {
+        self.header().last_comp_version.get()
+    }
+
+    /// Returns whether libfdt supports the version of the device tree.
+    ///
+    /// This always holds for a validated DT: use [`FdtHeader::is_supported_version`] to check a
+    /// DT that hasn't been validated.
+    pub fn is_supported_version(&self) -> bool {
+        self.header().is_supported_version()
+    }
+
+    /// Returns the physical ID of the boot CPU, as reported by the header.
+    pub fn boot_cpuid_phys(&self) -> u32 {
+        self.header().boot_cpuid_phys.get()
+    }
+}
+
+/// Builder of a Flattened Device Tree from scratch, using the libfdt sequential-write functions.
+///
+/// The root node must be opened first, with an empty name, and all nodes must be closed before
+/// calling [`FdtBuilder::finish`]. Misuse is reported as `BadState`.
+#[derive(Debug)]
+pub struct FdtBuilder<'a> {
+    buffer: &'a mut [u8],
+    depth: usize,
+    root_done: bool,
+}
+
+impl<'a> FdtBuilder<'a> {
+    /// Starts building a DT with an empty memory reservation block in the given buffer.
+    pub fn new(buffer: &'a mut [u8]) -> Result<Self> {
+        libfdt::create(buffer)?;
+        // SAFETY: The buffer was just initialized by create().
+        unsafe { libfdt::finish_reservemap(buffer) }?;
+
+        Ok(Self { buffer, depth: 0, root_done: false })
+    }
+
+    /// Opens a new node as a subnode of the current one.
+    pub fn begin_node(&mut self, name: &CStr) -> Result<()> {
+        if self.root_done {
+            return Err(FdtError::BadState);
+        }
+        // SAFETY: The buffer is only accessed through the sequential-write functions.
+        unsafe { libfdt::begin_node(self.buffer, name) }?;
+        self.depth += 1;
+
+        Ok(())
+    }
+
+    /// Adds a property name-value (possibly empty) pair to the current node.
+    pub fn property(&mut self, name: &CStr, value: &[u8]) -> Result<()> {
+        if self.depth == 0 {
+            return Err(FdtError::BadState);
+        }
+        // SAFETY: The buffer is only accessed through the sequential-write functions.
+        unsafe { libfdt::property(self.buffer, name, value) }
+    }
+
+    /// Closes the current node.
+    pub fn end_node(&mut self) -> Result<()> {
+        if self.depth == 0 {
+            return Err(FdtError::BadState);
+        }
+        // SAFETY: The buffer is only accessed through the sequential-write functions.
+        unsafe { libfdt::end_node(self.buffer) }?;
+        self.depth -= 1;
+        self.root_done = self.depth == 0;
+
+        Ok(())
+    }
+
+    /// Completes the DT and returns it, once validated.
+    pub fn finish(self) -> Result<&'a mut Fdt> {
+        if !self.root_done {
+            return Err(FdtError::BadState);
+        }
+        // SAFETY: The buffer is only accessed through the sequential-write functions.
+        unsafe { libfdt::finish(self.buffer) }?;
+
+        Fdt::from_mut_slice(self.buffer)
     }
 }
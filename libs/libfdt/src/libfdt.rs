@@ -18,12 +18,13 @@
 //! user-friendly higher-level types, allowing the trait to be shared between different ones,
 //! adapted to their use-cases (e.g. alloc-based userspace or statically allocated no_std).
 
-use core::ffi::CStr;
+use core::ffi::{c_int, CStr};
 use core::mem;
 use core::ptr;
 
 use crate::result::FdtRawResult;
-use crate::{FdtError, NodeOffset, Phandle, PropOffset, Result, StringOffset};
+use crate::{FdtError, FdtHeader, NodeOffset, Phandle, PropOffset, Result, StringOffset};
+use zerocopy::FromBytes as _;
 
 // Function names are the C function names without the `fdt_` prefix.
 
@@ -39,6 +40,113 @@ pub(crate) fn create_empty_tree(fdt: &mut [u8]) -> Result<()> {
     FdtRawResult::from(ret).try_into()
 }
 
+/// Safe wrapper around `fdt_create()` (C function).
+///
+/// Initializes the buffer for the sequential-write functions below.
+pub(crate) fn create(fdt: &mut [u8]) -> Result<()> {
+    let len = fdt.len().try_into().unwrap();
+    let fdt = fdt.as_mut_ptr().cast();
+    // SAFETY: fdt_create() only write within the specified length, and returns error if buffer was
+    // insufficient. On success, the header records the length, which bounds subsequent writes.
+    let ret = unsafe { libfdt_bindgen::fdt_create(fdt, len) };
+
+    FdtRawResult::from(ret).try_into()
+}
+
+/// Wrapper around `fdt_finish_reservemap()` (C function).
+///
+/// # Safety
+///
+/// The buffer must have been initialized by `create()` and not modified since, except by other
+/// sequential-write functions.
+pub(crate) unsafe fn finish_reservemap(fdt: &mut [u8]) -> Result<()> {
+    let fdt = fdt.as_mut_ptr().cast();
+    // SAFETY: Writes are constrained to the length recorded by fdt_create() (ensured by caller).
+    let ret = unsafe { libfdt_bindgen::fdt_finish_reservemap(fdt) };
+
+    FdtRawResult::from(ret).try_into()
+}
+
+/// Wrapper around `fdt_begin_node()` (C function).
+///
+/// # Safety
+///
+/// The buffer must have been initialized by `create()` and not modified since, except by other
+/// sequential-write functions.
+pub(crate) unsafe fn begin_node(fdt: &mut [u8], name: &CStr) -> Result<()> {
+    let fdt = fdt.as_mut_ptr().cast();
+    let name = name.as_ptr();
+    // SAFETY: Writes are constrained to the length recorded by fdt_create() (ensured by caller).
+    let ret = unsafe { libfdt_bindgen::fdt_begin_node(fdt, name) };
+
+    FdtRawResult::from(ret).try_into()
+}
+
+/// Wrapper around `fdt_property()` (C function).
+///
+/// # Safety
+///
+/// The buffer must have been initialized by `create()` and not modified since, except by other
+/// sequential-write functions.
+pub(crate) unsafe fn property(fdt: &mut [u8], name: &CStr, value: &[u8]) -> Result<()> {
+    let fdt = fdt.as_mut_ptr().cast();
+    let name = name.as_ptr();
+    let len = value.len().try_into().map_err(|_| FdtError::BadValue)?;
+    let value = value.as_ptr().cast();
+    // SAFETY: Writes are constrained to the length recorded by fdt_create() (ensured by caller).
+    let ret = unsafe { libfdt_bindgen::fdt_property(fdt, name, value, len) };
+
+    FdtRawResult::from(ret).try_into()
+}
+
+/// Wrapper around `fdt_end_node()` (C function).
+///
+/// # Safety
+///
+/// The buffer must have been initialized by `create()` and not modified since, except by other
+/// sequential-write functions.
+pub(crate) unsafe fn end_node(fdt: &mut [u8]) -> Result<()> {
+    let fdt = fdt.as_mut_ptr().cast();
+    // SAFETY: Writes are constrained to the length recorded by fdt_create() (ensured by caller).
+    let ret = unsafe { libfdt_bindgen::fdt_end_node(fdt) };
+
+    FdtRawResult::from(ret).try_into()
+}
+
+/// Wrapper around `fdt_finish()` (C function).
+///
+/// # Safety
+///
+/// The buffer must have been initialized by `create()` and not modified since, except by other
+/// sequential-write functions.
+pub(crate) unsafe fn finish(fdt: &mut [u8]) -> Result<()> {
+    let fdt = fdt.as_mut_ptr().cast();
+    // SAFETY: Accesses are constrained to the length recorded by fdt_create() (ensured by caller).
+    let ret = unsafe { libfdt_bindgen::fdt_finish(fdt) };
+
+    FdtRawResult::from(ret).try_into()
+}
+
+/// Safe wrapper around `fdt_check_header()` (C function).
+///
+/// Unlike [`check_full`], this doesn't validate the contents of the structure or strings blocks.
+pub(crate) fn check_header(fdt: &[u8]) -> Result<()> {
+    if fdt.len() < mem::size_of::<FdtHeader>() {
+        return Err(FdtError::Truncated);
+    }
+    // SAFETY: fdt_check_header() only reads the header, which fits in the slice.
+    let ret = unsafe { libfdt_bindgen::fdt_check_header(fdt.as_ptr().cast()) };
+    FdtRawResult::from(ret).try_into()?;
+
+    // fdt_check_header() can't know the length of the slice, so ensure that the blocks it has
+    // validated against the totalsize are within it.
+    let totalsize = FdtHeader::ref_from_prefix(fdt).ok_or(FdtError::Truncated)?.totalsize.get();
+    if usize::try_from(totalsize).map_or(true, |totalsize| totalsize > fdt.len()) {
+        return Err(FdtError::Truncated);
+    }
+    Ok(())
+}
+
 /// Safe wrapper around `fdt_check_full()` (C function).
 pub(crate) fn check_full(fdt: &[u8]) -> Result<()> {
     let len = fdt.len();
@@ -105,6 +213,23 @@ pub(crate) unsafe trait Libfdt {
         FdtRawResult::from(ret).try_into()
     }
 
+    /// Safe wrapper around `fdt_node_check_compatible()` (C function).
+    ///
+    /// Returns `false` if the node doesn't have a compatible property.
+    fn node_check_compatible(&self, node: NodeOffset, compatible: &CStr) -> Result<bool> {
+        let fdt = self.as_fdt_slice().as_ptr().cast();
+        let node = node.into();
+        let compatible = compatible.as_ptr();
+        // SAFETY: Accesses (read-only) are constrained to the DT totalsize.
+        let ret = unsafe { libfdt_bindgen::fdt_node_check_compatible(fdt, node, compatible) };
+
+        match c_int::try_from(FdtRawResult::from(ret)) {
+            Ok(0) => Ok(true),
+            Ok(_) | Err(FdtError::NotFound) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
     /// Safe wrapper around `fdt_next_node()` (C function).
     fn next_node(&self, node: NodeOffset, depth: usize) -> Result<Option<(NodeOffset, usize)>> {
         let fdt = self.as_fdt_slice().as_ptr().cast();
@@ -266,6 +391,27 @@ pub(crate) unsafe trait Libfdt {
         Ok(prop)
     }
 
+    /// Safe wrapper around `fdt_getprop_by_offset()` (C function).
+    fn getprop_by_offset(&self, offset: PropOffset) -> Result<(&CStr, &[u8])> {
+        let fdt = self.as_fdt_slice().as_ptr().cast();
+        let offset = offset.into();
+        let mut name = ptr::null();
+        let mut len = 0;
+        // SAFETY: Accesses (read-only) are constrained to the DT totalsize and the outputs are
+        // written through valid references.
+        let prop =
+            unsafe { libfdt_bindgen::fdt_getprop_by_offset(fdt, offset, &mut name, &mut len) };
+
+        let len = FdtRawResult::from(len).try_into()?;
+        let value =
+            get_slice_at_ptr(self.as_fdt_slice(), prop.cast(), len).ok_or(FdtError::Internal)?;
+        let name =
+            get_slice_from_ptr(self.as_fdt_slice(), name.cast()).ok_or(FdtError::Internal)?;
+        let name = CStr::from_bytes_until_nul(name).map_err(|_| FdtError::Internal)?;
+
+        Ok((name, value))
+    }
+
     /// Safe wrapper around `fdt_first_property_offset()` (C function).
     fn first_property_offset(&self, node: NodeOffset) -> Result<Option<PropOffset>> {
         let fdt = self.as_fdt_slice().as_ptr().cast();
@@ -298,6 +444,30 @@ pub(crate) unsafe trait Libfdt {
         phandle.try_into()
     }
 
+    /// Safe wrapper around `fdt_num_mem_rsv()` (C function).
+    fn num_mem_rsv(&self) -> Result<usize> {
+        let fdt = self.as_fdt_slice().as_ptr().cast();
+        // SAFETY: Accesses (read-only) are constrained to the DT totalsize.
+        let ret = unsafe { libfdt_bindgen::fdt_num_mem_rsv(fdt) };
+
+        FdtRawResult::from(ret).try_into()
+    }
+
+    /// Safe wrapper around `fdt_get_mem_rsv()` (C function).
+    fn get_mem_rsv(&self, index: usize) -> Result<(u64, u64)> {
+        let fdt = self.as_fdt_slice().as_ptr().cast();
+        let index = index.try_into().map_err(|_| FdtError::BadOffset)?;
+        let mut address = 0;
+        let mut size = 0;
+        // SAFETY: Accesses (read-only) are constrained to the DT totalsize and the outputs are
+        // written through valid references.
+        let ret = unsafe { libfdt_bindgen::fdt_get_mem_rsv(fdt, index, &mut address, &mut size) };
+
+        FdtRawResult::from(ret).try_into()?;
+
+        Ok((address, size))
+    }
+
     /// Safe wrapper around `fdt_string()` (C function).
     fn string(&self, offset: StringOffset) -> Result<&CStr> {
         let fdt = self.as_fdt_slice().as_ptr().cast();
@@ -349,6 +519,16 @@ pub(crate) unsafe trait LibfdtMut {
         FdtRawResult::from(ret).try_into()
     }
 
+    /// Safe wrapper around `fdt_del_node()` (C function).
+    fn del_node(&mut self, node: NodeOffset) -> Result<()> {
+        let fdt = self.as_fdt_slice_mut().as_mut_ptr().cast();
+        let node = node.into();
+        // SAFETY: Accesses are constrained to the DT totalsize (validated by ctor).
+        let ret = unsafe { libfdt_bindgen::fdt_del_node(fdt, node) };
+
+        FdtRawResult::from(ret).try_into()
+    }
+
     /// Safe wrapper around `fdt_add_subnode_namelen()` (C function).
     fn add_subnode_namelen(&mut self, node: NodeOffset, name: &[u8]) -> Result<NodeOffset> {
         let fdt = self.as_fdt_slice_mut().as_mut_ptr().cast();
@@ -91,6 +91,9 @@ impl fmt::Display for FdtError {
     }
 }
 
+/// This makes `FdtError` accepted by anyhow and usable as `dyn Error`.
+impl core::error::Error for FdtError {}
+
 /// Result type with FdtError enum.
 pub type Result<T> = result::Result<T, FdtError>;
 
@@ -180,13 +183,39 @@ impl TryFrom<FdtRawResult> for Option<usize> {
     }
 }
 
+/// Only for C functions documented to return exactly 0 on success. Functions returning a
+/// non-negative value (e.g. an offset or a count) on success must convert to that value instead.
 impl TryFrom<FdtRawResult> for () {
     type Error = FdtError;
 
     fn try_from(res: FdtRawResult) -> Result<Self> {
         match res.try_into()? {
             0 => Ok(()),
-            n => Err(FdtError::Unknown(n)),
+            // Not an error code: libfdt broke its own contract.
+            _ => Err(FdtError::Internal),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positive_raw_results_are_successes() {
+        assert_eq!(c_int::try_from(FdtRawResult::from(0)), Ok(0));
+        assert_eq!(c_int::try_from(FdtRawResult::from(42)), Ok(42));
+        assert_eq!(usize::try_from(FdtRawResult::from(42)), Ok(42));
+        assert_eq!(Option::<c_int>::try_from(FdtRawResult::from(42)), Ok(Some(42)));
+    }
+
+    #[test]
+    fn unit_conversion_only_accepts_zero() {
+        let not_found = -(libfdt_bindgen::FDT_ERR_NOTFOUND as c_int);
+
+        assert_eq!(<()>::try_from(FdtRawResult::from(0)), Ok(()));
+        assert_eq!(<()>::try_from(FdtRawResult::from(1)), Err(FdtError::Internal));
+        assert_eq!(<()>::try_from(FdtRawResult::from(c_int::MAX)), Err(FdtError::Internal));
+        assert_eq!(<()>::try_from(FdtRawResult::from(not_found)), Err(FdtError::NotFound));
+    }
+}
@@ -72,6 +72,31 @@ assert_offset_eq!(libfdt_bindgen::fdt_header, FdtHeader, boot_cpuid_phys);
 assert_offset_eq!(libfdt_bindgen::fdt_header, FdtHeader, size_dt_strings);
 assert_offset_eq!(libfdt_bindgen::fdt_header, FdtHeader, size_dt_struct);
 
+impl FdtHeader {
+    /// Oldest version of the DT format that libfdt can read.
+    pub const FIRST_SUPPORTED_VERSION: u32 = libfdt_bindgen::FDT_FIRST_SUPPORTED_VERSION;
+    /// Newest version of the DT format that libfdt can read.
+    pub const LAST_SUPPORTED_VERSION: u32 = libfdt_bindgen::FDT_LAST_SUPPORTED_VERSION;
+
+    /// Returns the header at the start of `bytes`, without validating it nor the rest of the DT.
+    ///
+    /// This allows inspecting a DT which the constructors of `Fdt` reject, e.g. to report why.
+    pub fn from_slice(bytes: &[u8]) -> Result<&Self> {
+        Self::ref_from_prefix(bytes).ok_or(FdtError::Truncated)
+    }
+
+    /// Returns whether libfdt can read a DT with this header, i.e. whether its version is at
+    /// least [`Self::FIRST_SUPPORTED_VERSION`] and it is backwards compatible with a version no
+    /// newer than [`Self::LAST_SUPPORTED_VERSION`].
+    pub fn is_supported_version(&self) -> bool {
+        let version = self.version.get();
+        let last_comp_version = self.last_comp_version.get();
+        version >= Self::FIRST_SUPPORTED_VERSION
+            && last_comp_version <= Self::LAST_SUPPORTED_VERSION
+            && last_comp_version <= version
+    }
+}
+
 impl AsRef<FdtHeader> for libfdt_bindgen::fdt_header {
     fn as_ref(&self) -> &FdtHeader {
         let ptr = self as *const _ as *const _;
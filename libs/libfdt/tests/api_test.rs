@@ -18,7 +18,10 @@
 
 use core::ffi::CStr;
 use cstr::cstr;
-use libfdt::{Fdt, FdtError, FdtNodeMut, Phandle};
+use libfdt::{
+    Fdt, FdtBuilder, FdtError, FdtHeader, FdtNode, FdtNodeMut, Phandle, ReservedMemFlags,
+    ReservedMemRegion, StringListIterator,
+};
 use std::collections::HashSet;
 use std::ffi::CString;
 use std::fs;
@@ -29,7 +32,18 @@ const TEST_TREE_WITH_MULTIPLE_MEMORY_RANGES_PATH: &str =
     "data/test_tree_multiple_memory_ranges.dtb";
 const TEST_TREE_WITH_EMPTY_MEMORY_RANGE_PATH: &str = "data/test_tree_empty_memory_range.dtb";
 const TEST_TREE_WITH_NO_MEMORY_NODE_PATH: &str = "data/test_tree_no_memory_node.dtb";
+const TEST_TREE_WITH_MEMORY_BANKS_PATH: &str = "data/test_tree_memory_banks.dtb";
 const TEST_TREE_PHANDLE_PATH: &str = "data/test_tree_phandle.dtb";
+const TEST_TREE_RANGES_PATH: &str = "data/test_tree_ranges.dtb";
+const TEST_TREE_RESERVED_MEMORY_PATH: &str = "data/test_tree_reserved_memory.dtb";
+const TEST_TREE_OVERLAY_BASE_PATH: &str = "data/test_tree_overlay_base.dtb";
+const TEST_OVERLAY_ADD_NODE_PATH: &str = "data/test_overlay_add_node.dtb";
+const TEST_OVERLAY_PHANDLE_TARGET_PATH: &str = "data/test_overlay_phandle_target.dtb";
+const TEST_OVERLAY_NO_TARGET_PATH: &str = "data/test_overlay_no_target.dtb";
+const TEST_TREE_ALIASES_PATH: &str = "data/test_tree_aliases.dtb";
+const TEST_TREE_STATUS_PATH: &str = "data/test_tree_status.dtb";
+const TEST_TREE_INTERRUPTS_PATH: &str = "data/test_tree_interrupts.dtb";
+const TEST_TREE_BOOT_CPUID_PATH: &str = "data/test_tree_boot_cpuid.dtb";
 
 #[test]
 fn retrieving_memory_from_fdt_with_one_memory_range_succeeds() {
@@ -57,6 +71,94 @@ fn retrieving_memory_from_fdt_with_multiple_memory_ranges_succeeds() {
     assert_eq!(fdt.first_memory_range(), Ok(EXPECTED_FIRST_MEMORY_RANGE));
 }
 
+#[test]
+fn memory_peek() {
+    let data = fs::read(TEST_TREE_WITH_MULTIPLE_MEMORY_RANGES_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+
+    let mut memory = fdt.memory().unwrap();
+    assert_eq!(memory.peek(), Some(0..256));
+    assert_eq!(memory.peek(), Some(0..256));
+    assert_eq!(memory.next(), Some(0..256));
+    let snapshot = memory.clone();
+    assert_eq!(memory.peek(), Some(512..1024));
+    assert_eq!(memory.next(), Some(512..1024));
+    assert_eq!(memory.peek(), None);
+    assert_eq!(memory.next(), None);
+    assert_eq!(snapshot.collect::<Vec<_>>(), [512..1024]);
+}
+
+#[test]
+fn memory_try_next() {
+    let mut data = fs::read(TEST_TREE_WITH_ONE_MEMORY_RANGE_PATH).unwrap();
+    data.resize(data.len() * 2, 0_u8);
+    let fdt = Fdt::from_mut_slice(&mut data).unwrap();
+    fdt.unpack().unwrap();
+    let mut root = fdt.root_mut();
+    root.setprop_inplace(cstr!("#address-cells"), &2u32.to_be_bytes()).unwrap();
+    root.setprop_inplace(cstr!("#size-cells"), &2u32.to_be_bytes()).unwrap();
+    let reg: Vec<u8> = [0x100_u64, 0x100, 0xffff_ffff_ffff_ff00, 0x200]
+        .iter()
+        .flat_map(|cell| cell.to_be_bytes())
+        .collect();
+    fdt.node_mut(cstr!("/memory")).unwrap().unwrap().setprop(cstr!("reg"), &reg).unwrap();
+
+    let mut memory = fdt.memory().unwrap();
+    assert_eq!(memory.try_next(), Some(Ok(0x100..0x200)));
+    assert_eq!(memory.try_next(), Some(Err(FdtError::BadValue)));
+    assert_eq!(memory.try_next(), None);
+
+    let memory = fdt.memory().unwrap();
+    assert_eq!(memory.collect::<Vec<_>>(), [0x100..0x200]);
+}
+
+#[test]
+fn memory_reg_size_hint() {
+    let data = fs::read(TEST_TREE_WITH_MULTIPLE_MEMORY_RANGES_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+
+    let mut reg = fdt.node(cstr!("/memory")).unwrap().unwrap().reg().unwrap().unwrap();
+    assert_eq!(reg.len(), 2);
+    reg.next();
+    assert_eq!(reg.size_hint(), (1, Some(1)));
+    reg.next();
+    assert_eq!(reg.len(), 0);
+
+    let memory = fdt.memory().unwrap();
+    assert_eq!(memory.size_hint(), (0, Some(2)));
+}
+
+#[test]
+fn reg_into_u32() {
+    let data = fs::read(TEST_TREE_WITH_MULTIPLE_MEMORY_RANGES_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+
+    let reg = fdt.node(cstr!("/memory")).unwrap().unwrap().reg().unwrap().unwrap();
+    let expected: Vec<_> = reg.clone().map(|r| (r.addr, r.size)).collect();
+    let reg = reg.into_u32().unwrap();
+    assert_eq!(reg.len(), 2);
+    let regs: Vec<_> = reg.map(|r| (u64::from(r.addr), r.size.map(u64::from))).collect();
+    assert_eq!(regs, expected);
+}
+
+#[test]
+fn reg_into_u32_with_double_cells() {
+    let mut data = fs::read(TEST_TREE_WITH_ONE_MEMORY_RANGE_PATH).unwrap();
+    data.resize(data.len() * 2, 0_u8);
+    let fdt = Fdt::from_mut_slice(&mut data).unwrap();
+    fdt.unpack().unwrap();
+    let mut root = fdt.root_mut();
+    root.setprop_inplace(cstr!("#address-cells"), &2u32.to_be_bytes()).unwrap();
+    root.setprop_inplace(cstr!("#size-cells"), &2u32.to_be_bytes()).unwrap();
+    let reg: Vec<u8> =
+        [0x1_0000_0000_u64, 0x100].iter().flat_map(|cell| cell.to_be_bytes()).collect();
+    fdt.node_mut(cstr!("/memory")).unwrap().unwrap().setprop(cstr!("reg"), &reg).unwrap();
+
+    let reg = fdt.node(cstr!("/memory")).unwrap().unwrap().reg().unwrap().unwrap();
+    assert_eq!(reg.clone().next().unwrap().addr, 0x1_0000_0000);
+    assert_eq!(reg.into_u32().unwrap_err(), FdtError::BadNCells);
+}
+
 #[test]
 fn retrieving_first_memory_from_fdt_with_empty_memory_range_fails() {
     let data = fs::read(TEST_TREE_WITH_EMPTY_MEMORY_RANGE_PATH).unwrap();
@@ -67,6 +169,21 @@ fn retrieving_first_memory_from_fdt_with_empty_memory_range_fails() {
     assert_eq!(fdt.first_memory_range(), Err(FdtError::NotFound));
 }
 
+#[test]
+fn empty_reg_is_distinct_from_missing_reg() {
+    let data = fs::read(TEST_TREE_WITH_EMPTY_MEMORY_RANGE_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+
+    let memory = fdt.node(cstr!("/memory")).unwrap().unwrap();
+    assert_eq!(memory.has_reg(), Ok(true));
+    let reg = memory.reg().unwrap().expect("Empty reg should be present");
+    assert_eq!(reg.len(), 0);
+
+    let cpus = fdt.node(cstr!("/cpus")).unwrap().unwrap();
+    assert_eq!(cpus.has_reg(), Ok(false));
+    assert!(cpus.reg().unwrap().is_none());
+}
+
 #[test]
 fn retrieving_memory_from_fdt_with_no_memory_node_fails() {
     let data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
@@ -76,6 +193,257 @@ fn retrieving_memory_from_fdt_with_no_memory_node_fails() {
     assert_eq!(fdt.first_memory_range(), Err(FdtError::NotFound));
 }
 
+#[test]
+fn retrieving_all_memory_from_fdt_with_memory_banks_succeeds() {
+    let data = fs::read(TEST_TREE_WITH_MEMORY_BANKS_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+
+    let expected = vec![0x0..0x100, 0x200..0x300, 0x400..0x600];
+    let memory: Vec<_> = fdt.all_memory().unwrap().collect::<Result<_, _>>().unwrap();
+    assert_eq!(memory, expected);
+}
+
+#[test]
+fn retrieving_all_memory_from_fdt_with_memory_node_succeeds() {
+    let data = fs::read(TEST_TREE_WITH_MULTIPLE_MEMORY_RANGES_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+
+    let memory: Vec<_> = fdt.all_memory().unwrap().collect::<Result<_, _>>().unwrap();
+    let expected: Vec<_> = fdt.memory().unwrap().collect();
+    assert_eq!(memory, expected);
+}
+
+#[test]
+fn retrieving_all_memory_reports_invalid_banks() {
+    let mut data = fs::read(TEST_TREE_WITH_ONE_MEMORY_RANGE_PATH).unwrap();
+    data.resize(data.len() * 2, 0_u8);
+    let fdt = Fdt::from_mut_slice(&mut data).unwrap();
+    fdt.unpack().unwrap();
+    let mut root = fdt.root_mut();
+    root.setprop_inplace(cstr!("#address-cells"), &2u32.to_be_bytes()).unwrap();
+    root.setprop_inplace(cstr!("#size-cells"), &2u32.to_be_bytes()).unwrap();
+    let reg: Vec<u8> = [0x100_u64, 0x100, 0xffff_ffff_ffff_ff00, 0x200]
+        .iter()
+        .flat_map(|cell| cell.to_be_bytes())
+        .collect();
+    fdt.node_mut(cstr!("/memory")).unwrap().unwrap().setprop(cstr!("reg"), &reg).unwrap();
+
+    let memory: Vec<_> = fdt.all_memory().unwrap().collect();
+    assert_eq!(memory, [Ok(0x100..0x200), Err(FdtError::BadValue)]);
+    assert_eq!(fdt.all_memory().unwrap().collect::<Result<Vec<_>, _>>(), Err(FdtError::BadValue));
+}
+
+#[test]
+fn retrieving_all_memory_from_fdt_with_no_memory_node_is_empty() {
+    let data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+
+    assert_eq!(fdt.all_memory().unwrap().next(), None);
+}
+
+#[test]
+fn retrieving_reserved_memory_succeeds() {
+    let data = fs::read(TEST_TREE_RESERVED_MEMORY_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+
+    let no_map = ReservedMemFlags { no_map: true, reusable: false };
+    let reusable = ReservedMemFlags { no_map: false, reusable: true };
+    let expected = vec![
+        ReservedMemRegion { name: cstr!("region@10000"), range: 0x10000..0x11000, flags: no_map },
+        ReservedMemRegion { name: cstr!("multi@20000"), range: 0x20000..0x21000, flags: reusable },
+        ReservedMemRegion { name: cstr!("multi@20000"), range: 0x30000..0x32000, flags: reusable },
+    ];
+    let regions: Vec<_> = fdt.reserved_memory().unwrap().collect();
+    assert_eq!(regions, expected);
+}
+
+#[test]
+fn reserved_memory_try_next() {
+    let mut data = fs::read(TEST_TREE_RESERVED_MEMORY_PATH).unwrap();
+    let fdt = Fdt::from_mut_slice(&mut data).unwrap();
+    let reg: Vec<u8> =
+        [0xffff_ffff_ffff_ff00_u64, 0x200].iter().flat_map(|cell| cell.to_be_bytes()).collect();
+    let mut region = fdt.node_mut(cstr!("/reserved-memory/region@10000")).unwrap().unwrap();
+    region.setprop_inplace(cstr!("reg"), &reg).unwrap();
+
+    let reusable = ReservedMemFlags { no_map: false, reusable: true };
+    let mut regions = fdt.reserved_memory().unwrap();
+    assert_eq!(regions.try_next(), Some(Err(FdtError::BadValue)));
+    assert_eq!(
+        regions.try_next(),
+        Some(Ok(ReservedMemRegion {
+            name: cstr!("multi@20000"),
+            range: 0x20000..0x21000,
+            flags: reusable
+        }))
+    );
+    assert_eq!(
+        regions.try_next(),
+        Some(Ok(ReservedMemRegion {
+            name: cstr!("multi@20000"),
+            range: 0x30000..0x32000,
+            flags: reusable
+        }))
+    );
+    assert_eq!(regions.try_next(), None);
+
+    assert_eq!(fdt.reserved_memory().unwrap().next(), None);
+}
+
+#[test]
+fn retrieving_reserved_memory_without_node_is_empty() {
+    let data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+
+    assert_eq!(fdt.reserved_memory().unwrap().next(), None);
+}
+
+#[test]
+fn retrieving_mem_reservations_succeeds() {
+    let data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+
+    assert_eq!(fdt.mem_reservation_count(), Ok(1));
+    let reservations: Vec<_> = fdt.mem_reservations().unwrap().collect();
+    assert_eq!(reservations, vec![0x0..0xe]);
+}
+
+#[test]
+fn retrieving_mem_reservations_without_reservation_is_empty() {
+    let data = fs::read(TEST_TREE_PHANDLE_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+
+    assert_eq!(fdt.mem_reservation_count(), Ok(0));
+    assert_eq!(fdt.mem_reservations().unwrap().next(), None);
+}
+
+#[test]
+fn fdt_header_accessors() {
+    let mut data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
+    let len = data.len();
+    data.resize(len * 2, 0_u8);
+    let fdt = Fdt::from_slice(&data).unwrap();
+
+    assert_eq!(fdt.magic(), 0xd00dfeed);
+    assert_eq!(fdt.version(), 17);
+    assert_eq!(fdt.totalsize().unwrap(), len);
+    assert_eq!(fdt.as_slice().len(), len);
+    assert_eq!(fdt.header().totalsize.get(), u32::try_from(len).unwrap());
+    assert_eq!(fdt.boot_cpuid_phys(), 0);
+}
+
+#[test]
+fn fdt_buffer_accessors() {
+    let mut data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
+    let len = data.len();
+    data.resize(len * 2, 0_u8);
+    let fdt = Fdt::from_slice(&data).unwrap();
+
+    assert_eq!(fdt.buffer_len(), len * 2);
+    assert_eq!(fdt.as_bytes(), &data[..]);
+    assert_eq!(&fdt.as_bytes()[..fdt.totalsize().unwrap()], fdt.as_slice());
+}
+
+#[test]
+fn fdt_version() {
+    let data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+
+    assert_eq!(fdt.version(), 17);
+    assert_eq!(fdt.last_comp_version(), 16);
+    assert!(fdt.is_supported_version());
+    assert!(FdtHeader::from_slice(&data).unwrap().is_supported_version());
+}
+
+#[test]
+fn fdt_unsupported_version() {
+    const LAST_COMP_VERSION_OFFSET: usize = 24;
+    let mut data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
+    let last_comp_version = FdtHeader::LAST_SUPPORTED_VERSION + 1;
+    data[LAST_COMP_VERSION_OFFSET..(LAST_COMP_VERSION_OFFSET + 4)]
+        .copy_from_slice(&last_comp_version.to_be_bytes());
+
+    let header = FdtHeader::from_slice(&data).unwrap();
+    assert_eq!(header.last_comp_version.get(), last_comp_version);
+    assert!(!header.is_supported_version());
+    assert_eq!(Fdt::from_slice(&data).err(), Some(FdtError::BadVersion));
+    assert_eq!(FdtHeader::from_slice(&data[..8]).err(), Some(FdtError::Truncated));
+}
+
+#[test]
+fn fdt_boot_cpuid_phys() {
+    let data = fs::read(TEST_TREE_BOOT_CPUID_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+
+    assert_eq!(fdt.boot_cpuid_phys(), 2);
+}
+
+#[test]
+fn fdt_from_misaligned_slice() {
+    let data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
+    let mut buffer = vec![0_u8; data.len() + 16];
+    // Find an 8-byte aligned offset, then move one byte past it.
+    let aligned = buffer.as_ptr().align_offset(8);
+    let misaligned = aligned + 1;
+    buffer[aligned..(aligned + data.len())].copy_from_slice(&data);
+    assert!(Fdt::from_slice(&buffer[aligned..]).is_ok());
+    buffer.copy_within(aligned..(aligned + data.len()), misaligned);
+
+    assert_eq!(Fdt::from_slice(&buffer[misaligned..]).err(), Some(FdtError::Alignment));
+    assert_eq!(Fdt::from_mut_slice(&mut buffer[misaligned..]).err(), Some(FdtError::Alignment));
+}
+
+#[test]
+fn fdt_from_slice_with_remainder() {
+    let mut data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
+    let totalsize = data.len();
+    data.extend_from_slice(b"trailing");
+
+    let (fdt, remainder) = Fdt::from_slice_with_remainder(&data).unwrap();
+    assert_eq!(fdt.totalsize().unwrap(), totalsize);
+    assert_eq!(remainder, b"trailing");
+
+    assert_eq!(
+        Fdt::from_slice_with_remainder(&data[..totalsize - 1]).err(),
+        Some(FdtError::Truncated)
+    );
+    assert_eq!(Fdt::from_slice_with_remainder(&data[..8]).err(), Some(FdtError::Truncated));
+}
+
+#[test]
+fn fdt_from_slice_header_only() {
+    let mut data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
+
+    // SAFETY: The DT is valid.
+    let fdt = unsafe { Fdt::from_slice_header_only(&data) }.unwrap();
+    assert_eq!(fdt.root().getprop_str(cstr!("model")), Ok(Some(cstr!("MyBoardName"))));
+
+    let len = data.len();
+    // SAFETY: The header is rejected, so the DT isn't used.
+    unsafe {
+        assert_eq!(Fdt::from_slice_header_only(&data[..len - 1]).err(), Some(FdtError::Truncated));
+        assert_eq!(Fdt::from_slice_header_only(&data[..8]).err(), Some(FdtError::Truncated));
+    }
+
+    data[0] = !data[0];
+    // SAFETY: The header is rejected, so the DT isn't used.
+    let result = unsafe { Fdt::from_slice_header_only(&data) };
+    assert_eq!(result.err(), Some(FdtError::BadMagic));
+}
+
+#[test]
+fn fdt_strings() {
+    let data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+
+    let strings: Vec<_> = fdt.strings().unwrap().collect();
+    for name in [cstr!("model"), cstr!("#address-cells"), cstr!("device_type"), cstr!("nbytes")] {
+        assert!(strings.contains(&name), "{name:?} not in {strings:?}");
+    }
+    let block_size = usize::try_from(fdt.header().size_dt_strings.get()).unwrap();
+    assert_eq!(strings.iter().map(|s| s.to_bytes_with_nul().len()).sum::<usize>(), block_size);
+}
+
 #[test]
 fn node_name() {
     let data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
@@ -92,6 +460,23 @@ fn node_name() {
     assert_eq!(nested_node.name(), Ok(cstr!("PowerPC,970@0")));
 }
 
+#[test]
+fn node_with_trailing_slash() {
+    let mut data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
+    let fdt = Fdt::from_mut_slice(&mut data).unwrap();
+
+    let cpus = fdt.node(cstr!("/cpus")).unwrap().unwrap();
+    assert_eq!(fdt.node(cstr!("/cpus/")), Ok(Some(cpus)));
+    assert_eq!(fdt.node(cstr!("/")), Ok(Some(fdt.root())));
+    assert_eq!(
+        fdt.node(cstr!("/cpus/PowerPC,970@0/")).unwrap().unwrap().name(),
+        Ok(cstr!("PowerPC,970@0"))
+    );
+
+    let cpus = fdt.node_mut(cstr!("/cpus/")).unwrap().unwrap();
+    assert_eq!(cpus.as_node().name(), Ok(cstr!("cpus")));
+}
+
 #[test]
 fn node_subnodes() {
     let data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
@@ -104,6 +489,28 @@ fn node_subnodes() {
     assert_eq!(subnode_names, expected);
 }
 
+#[test]
+fn node_subnodes_without_children() {
+    let data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+    let node = fdt.node(cstr!("/randomnode/child")).unwrap().unwrap();
+
+    let mut subnodes = node.subnodes().unwrap();
+    assert!(subnodes.next().is_none());
+    assert!(subnodes.next().is_none());
+}
+
+#[test]
+fn node_subnodes_in_document_order() {
+    let data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+    let cpus = fdt.node(cstr!("/cpus")).unwrap().unwrap();
+    let expected = [Ok(cstr!("PowerPC,970@0")), Ok(cstr!("PowerPC,970@1"))];
+
+    let subnode_names: Vec<_> = cpus.subnodes().unwrap().map(|node| node.name()).collect();
+    assert_eq!(subnode_names, expected);
+}
+
 #[test]
 fn node_properties() {
     let data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
@@ -125,6 +532,270 @@ fn node_properties() {
     assert_eq!(subnode_properties, expected);
 }
 
+#[test]
+fn node_getprop_by_offset() {
+    let data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+    let root = fdt.root();
+
+    for prop in root.properties().unwrap() {
+        let (name, value) = root.getprop_by_offset(prop.offset()).unwrap();
+        assert_eq!(Ok(name), prop.name());
+        assert_eq!(Ok(value), prop.value());
+        assert_eq!(Ok(Some(value)), root.getprop(name));
+    }
+}
+
+#[test]
+fn node_is_compatible() {
+    let data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+    let root = fdt.root();
+
+    assert_eq!(root.is_compatible(cstr!("MyBoardName")), Ok(true));
+    assert_eq!(root.is_compatible(cstr!("MyBoardFamilyName")), Ok(true));
+    assert_eq!(root.is_compatible(cstr!("MyBoard")), Ok(false));
+
+    let chosen = fdt.chosen().unwrap().unwrap();
+    assert_eq!(chosen.is_compatible(cstr!("MyBoardName")), Ok(false));
+}
+
+#[test]
+fn node_is_available() {
+    let data = fs::read(TEST_TREE_STATUS_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+
+    let expected = [
+        (cstr!("node_default"), true),
+        (cstr!("node_okay"), true),
+        (cstr!("node_ok"), true),
+        (cstr!("node_disabled"), false),
+        (cstr!("node_fail"), false),
+        (cstr!("node_fail_sss"), false),
+    ];
+    for (name, available) in expected {
+        let node = fdt.root().subnode(name).unwrap().unwrap();
+        assert_eq!(node.is_available(), Ok(available), "{name:?}");
+    }
+}
+
+#[test]
+fn find_compatible() {
+    let data = fs::read(TEST_TREE_STATUS_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+
+    let node = fdt.find_compatible(cstr!("test,device")).unwrap().unwrap();
+    assert_eq!(node.name(), Ok(cstr!("node_default")));
+    assert_eq!(fdt.find_compatible(cstr!("test,missing")), Ok(None));
+}
+
+#[test]
+fn matching_compatible_nodes() {
+    let mut data = fs::read(TEST_TREE_STATUS_PATH).unwrap();
+    data.resize(data.len() * 2, 0_u8);
+    let fdt = Fdt::from_mut_slice(&mut data).unwrap();
+    fdt.unpack().unwrap();
+    let mut node = fdt.node_mut(cstr!("/node_default")).unwrap().unwrap();
+    node.setprop(cstr!("compatible"), b"test,device-v2\0test,device\0").unwrap();
+    let mut node = fdt.node_mut(cstr!("/node_okay")).unwrap().unwrap();
+    node.setprop(cstr!("compatible"), b"test,device\0test,device-v2\0").unwrap();
+    let mut node = fdt.node_mut(cstr!("/node_ok")).unwrap().unwrap();
+    node.setprop(cstr!("compatible"), b"test,other\0").unwrap();
+
+    let candidates = [cstr!("test,device-v2"), cstr!("test,device")];
+    let nodes: Vec<_> = fdt
+        .matching_compatible_nodes(&candidates)
+        .map(|(node, compatible)| (node.name().unwrap(), compatible))
+        .collect();
+
+    assert_eq!(
+        nodes,
+        [
+            (cstr!("node_default"), cstr!("test,device-v2")),
+            (cstr!("node_okay"), cstr!("test,device-v2")),
+            (cstr!("node_disabled"), cstr!("test,device")),
+            (cstr!("node_fail"), cstr!("test,device")),
+            (cstr!("node_fail_sss"), cstr!("test,device")),
+        ]
+    );
+    assert_eq!(fdt.matching_compatible_nodes(&[cstr!("test,missing")]).count(), 0);
+}
+
+#[test]
+fn available_compatible_nodes() {
+    let data = fs::read(TEST_TREE_STATUS_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+
+    let nodes: Vec<_> = fdt
+        .available_compatible_nodes(cstr!("test,device"))
+        .unwrap()
+        .map(|node| node.unwrap().name().unwrap())
+        .collect();
+
+    assert_eq!(nodes, [cstr!("node_default"), cstr!("node_okay"), cstr!("node_ok")]);
+}
+
+#[test]
+fn available_compatible_nodes_with_malformed_status() {
+    let mut data = fs::read(TEST_TREE_STATUS_PATH).unwrap();
+    let fdt = Fdt::from_mut_slice(&mut data).unwrap();
+    let mut node = fdt.node_mut(cstr!("/node_disabled")).unwrap().unwrap();
+    // Not nul-terminated, so can't be read as a string.
+    node.setprop(cstr!("status"), b"disabled").unwrap();
+
+    let mut nodes = fdt.available_compatible_nodes(cstr!("test,device")).unwrap();
+
+    assert_eq!(nodes.next().unwrap().unwrap().name(), Ok(cstr!("node_default")));
+    assert_eq!(nodes.next().unwrap().unwrap().name(), Ok(cstr!("node_okay")));
+    assert_eq!(nodes.next().unwrap().unwrap().name(), Ok(cstr!("node_ok")));
+    assert!(nodes.next().unwrap().is_err());
+    assert!(nodes.next().is_none());
+}
+
+#[test]
+fn string_list_iterator() {
+    let strings: Vec<_> = StringListIterator::new(b"foo\0stuff\0").unwrap().collect();
+    assert_eq!(strings, vec![cstr!("foo"), cstr!("stuff")]);
+
+    let strings: Vec<_> = StringListIterator::new(b"foo\0\0bar\0").unwrap().collect();
+    assert_eq!(strings, vec![cstr!("foo"), cstr!(""), cstr!("bar")]);
+
+    assert_eq!(StringListIterator::new(b"").unwrap().next(), None);
+    assert_eq!(StringListIterator::new(b"foo\0bar").unwrap_err(), FdtError::BadValue);
+}
+
+#[test]
+fn node_ranges() {
+    let data = fs::read(TEST_TREE_RANGES_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+
+    let bus = fdt.node(cstr!("/translated-bus")).unwrap().unwrap();
+    let ranges: Vec<_> = bus
+        .ranges::<u64, u64, u64>()
+        .unwrap()
+        .unwrap()
+        .map(|r| (r.addr, r.parent_addr, r.size))
+        .collect();
+    assert_eq!(ranges, vec![(0x0, 0x1000_0000, 0x1000), (0x2000, 0x1_0000_0000, 0x1000)]);
+
+    let bus = fdt.node(cstr!("/identity-bus")).unwrap().unwrap();
+    assert!(bus.ranges::<u64, u64, u64>().unwrap().unwrap().next().is_none());
+
+    let bus = fdt.node(cstr!("/no-ranges-bus")).unwrap().unwrap();
+    assert!(bus.ranges::<u64, u64, u64>().unwrap().is_none());
+}
+
+#[test]
+fn node_translate_child_address() {
+    let data = fs::read(TEST_TREE_RANGES_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+
+    let bus = fdt.node(cstr!("/translated-bus")).unwrap().unwrap();
+    assert_eq!(bus.translate_child_address(0x10), Ok(0x1000_0010));
+    assert_eq!(bus.translate_child_address(0x2fff), Ok(0x1_0000_0fff));
+    assert_eq!(bus.translate_child_address(0x1000), Err(FdtError::NotFound));
+
+    let bus = fdt.node(cstr!("/identity-bus")).unwrap().unwrap();
+    assert_eq!(bus.translate_child_address(0x1234), Ok(0x1234));
+
+    let bus = fdt.node(cstr!("/no-ranges-bus")).unwrap().unwrap();
+    assert_eq!(bus.translate_child_address(0x1234), Err(FdtError::NotFound));
+}
+
+#[test]
+fn node_addr_size_cells() {
+    let data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+
+    assert_eq!(fdt.root().addr_size_cells(), Ok((1, 1)));
+
+    let cpus = fdt.node(cstr!("/cpus")).unwrap().unwrap();
+    assert_eq!(cpus.addr_size_cells(), Ok((1, 0)));
+
+    let randomnode = fdt.node(cstr!("/randomnode")).unwrap().unwrap();
+    assert_eq!(randomnode.addr_size_cells(), Ok((2, 0)));
+}
+
+#[test]
+fn node_getprop_strings() {
+    let data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+    let node = fdt.node(cstr!("/randomnode")).unwrap().unwrap();
+
+    let strings: Vec<_> = node.getprop_strings(cstr!("string")).unwrap().unwrap().collect();
+    assert_eq!(strings, vec![cstr!("foo"), cstr!("stuff")]);
+
+    let compatible: Vec<_> =
+        fdt.root().getprop_strings(cstr!("compatible")).unwrap().unwrap().collect();
+    assert_eq!(compatible, vec![cstr!("MyBoardName"), cstr!("MyBoardFamilyName")]);
+
+    let mut empty = fdt.root().getprop_strings(cstr!("empty_prop")).unwrap().unwrap();
+    assert_eq!(empty.next(), None);
+
+    assert_eq!(node.getprop_strings(cstr!("bytes")).unwrap_err(), FdtError::BadValue);
+    assert!(node.getprop_strings(cstr!("missing")).unwrap().is_none());
+}
+
+#[test]
+fn node_getprop_cells_remainder() {
+    let data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+    let node = fdt.node(cstr!("/randomnode")).unwrap().unwrap();
+
+    let mut cells = node.getprop_cells(cstr!("bytes")).unwrap().unwrap();
+    assert_eq!(cells.next(), Some(0x61626364));
+    assert_eq!(cells.next(), None);
+    assert_eq!(cells.remainder(), &[0x65]);
+
+    let mut cells = node.getprop_cells(cstr!("nbytes")).unwrap().unwrap();
+    assert_eq!(cells.next(), None);
+    assert_eq!(cells.remainder(), &[0x80, 0xff]);
+
+    let cpu = fdt.node(cstr!("/cpus/PowerPC,970@1")).unwrap().unwrap();
+    let mut cells = cpu.getprop_cells(cstr!("reg")).unwrap().unwrap();
+    assert_eq!(cells.next(), Some(1));
+    assert!(cells.remainder().is_empty());
+}
+
+#[test]
+fn node_getprop_bool() {
+    let data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+    let root = fdt.root();
+
+    assert_eq!(root.getprop_bool(cstr!("empty_prop")), Ok(true));
+    assert_eq!(root.getprop_bool(cstr!("model")), Ok(true));
+    assert_eq!(root.getprop_bool(cstr!("missing")), Ok(false));
+}
+
+#[test]
+fn node_getprop_arrays() {
+    let mut data = vec![0_u8; 1000];
+    let mut builder = FdtBuilder::new(&mut data).unwrap();
+    builder.begin_node(cstr!("")).unwrap();
+    builder.property(cstr!("one"), &[0x12, 0x34, 0x56, 0x78]).unwrap();
+    builder.property(cstr!("two"), &[0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0]).unwrap();
+    builder.property(cstr!("odd"), &[0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3]).unwrap();
+    builder.property(cstr!("bytes"), &[0x61, 0x62]).unwrap();
+    builder.end_node().unwrap();
+    let fdt = builder.finish().unwrap();
+    let root = fdt.root();
+
+    let u32s = |name| root.getprop_u32_array(name).map(|v| v.map(Iterator::collect::<Vec<_>>));
+    assert_eq!(u32s(cstr!("one")), Ok(Some(vec![0x12345678])));
+    assert_eq!(u32s(cstr!("two")), Ok(Some(vec![0x12345678, 0x9abcdef0])));
+    assert_eq!(u32s(cstr!("odd")), Ok(Some(vec![1, 2, 3])));
+    assert_eq!(u32s(cstr!("bytes")), Err(FdtError::BadValue));
+    assert_eq!(u32s(cstr!("missing")), Ok(None));
+
+    let u64s = |name| root.getprop_u64_array(name).map(|v| v.map(Iterator::collect::<Vec<_>>));
+    assert_eq!(u64s(cstr!("two")), Ok(Some(vec![0x123456789abcdef0])));
+    assert_eq!(u64s(cstr!("one")), Err(FdtError::BadValue));
+    assert_eq!(u64s(cstr!("odd")), Err(FdtError::BadValue));
+    assert_eq!(u64s(cstr!("missing")), Ok(None));
+    assert_eq!(root.getprop_u64_array(cstr!("two")).unwrap().unwrap().len(), 1);
+}
+
 #[test]
 fn node_supernode_at_depth() {
     let data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
@@ -195,6 +866,15 @@ fn node_with_phandle() {
     assert_eq!(node.name(), Ok(cstr!("node_abc")));
 }
 
+#[test]
+fn node_with_unknown_phandle() {
+    let data = fs::read(TEST_TREE_PHANDLE_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+
+    let phandle = Phandle::new(0x1234).unwrap();
+    assert_eq!(fdt.node_with_phandle(phandle), Ok(None));
+}
+
 #[test]
 fn node_mut_with_phandle() {
     let mut data = fs::read(TEST_TREE_PHANDLE_PATH).unwrap();
@@ -251,6 +931,60 @@ fn node_nop() {
     assert_eq!(fdt.node(path), Ok(None));
 }
 
+#[test]
+fn node_delete() {
+    let mut data = fs::read(TEST_TREE_PHANDLE_PATH).unwrap();
+    let fdt = Fdt::from_mut_slice(&mut data).unwrap();
+    let size_before = fdt.totalsize().unwrap();
+    let phandle = Phandle::new(0xFF).unwrap();
+    let path = cstr!("/node_z/node_zz");
+
+    let node = fdt.node_mut(path).unwrap().unwrap();
+    node.delete().unwrap();
+
+    assert_eq!(fdt.node_with_phandle(phandle), Ok(None));
+    assert_eq!(fdt.node(path), Ok(None));
+    assert!(fdt.node(cstr!("/node_z")).unwrap().is_some());
+
+    fdt.pack().unwrap();
+    assert!(fdt.totalsize().unwrap() < size_before);
+}
+
+#[test]
+fn fdt_pack_after_edits() {
+    let mut data = fs::read(TEST_TREE_PHANDLE_PATH).unwrap();
+    data.resize(data.len() * 2, 0_u8);
+    let fdt = Fdt::from_mut_slice(&mut data).unwrap();
+    fdt.unpack().unwrap();
+    let size_before = fdt.totalsize().unwrap();
+
+    fdt.node_mut(cstr!("/node_z/node_zz")).unwrap().unwrap().delete().unwrap();
+    fdt.root_mut().setprop(cstr!("new-prop"), b"value\0").unwrap();
+
+    let reclaimed = fdt.pack().unwrap();
+    assert!(reclaimed > 0);
+    assert_eq!(fdt.totalsize().unwrap(), size_before - reclaimed);
+
+    let fdt = Fdt::from_slice(fdt.as_slice()).unwrap();
+    assert_eq!(fdt.root().getprop_str(cstr!("new-prop")), Ok(Some(cstr!("value"))));
+}
+
+#[test]
+fn node_add_subnode_existing() {
+    let mut data = fs::read(TEST_TREE_PHANDLE_PATH).unwrap();
+    data.resize(data.len() * 2, 0_u8);
+    let fdt = Fdt::from_mut_slice(&mut data).unwrap();
+    fdt.unpack().unwrap();
+
+    let node = fdt.node_mut(cstr!("/node_z")).unwrap().unwrap();
+    assert_eq!(node.add_subnode(cstr!("node_zz")).err(), Some(FdtError::Exists));
+
+    let root = fdt.root_mut();
+    let chosen = root.add_subnode(cstr!("chosen")).unwrap();
+    chosen.delete().unwrap();
+    assert_eq!(fdt.chosen(), Ok(None));
+}
+
 #[test]
 fn node_add_subnode_with_namelen() {
     let mut data = fs::read(TEST_TREE_PHANDLE_PATH).unwrap();
@@ -298,6 +1032,23 @@ fn node_subnode() {
     assert_eq!(Ok(name), node.name());
 }
 
+#[test]
+fn node_descendant() {
+    let data = fs::read(TEST_TREE_PHANDLE_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+    let node_a = fdt.node(cstr!("/node_a")).unwrap().unwrap();
+
+    let node = node_a.descendant(cstr!("node_ab/node_abc")).unwrap().unwrap();
+    assert_eq!(Ok(cstr!("node_abc")), node.name());
+    assert_eq!(Ok(Some(node)), fdt.node(cstr!("/node_a/node_ab/node_abc")));
+    let node = node_a.descendant(cstr!("node_ab//node_abc/")).unwrap().unwrap();
+    assert_eq!(Ok(cstr!("node_abc")), node.name());
+    assert_eq!(Ok(Some(node_a)), node_a.descendant(cstr!("")));
+
+    assert_eq!(Ok(None), node_a.descendant(cstr!("node_ab/node_missing")));
+    assert_eq!(Ok(None), node_a.descendant(cstr!("node_z")));
+}
+
 #[test]
 fn node_subnode_with_name_bytes() {
     let data = fs::read(TEST_TREE_PHANDLE_PATH).unwrap();
@@ -338,6 +1089,31 @@ fn fdt_symbols() {
     let _symbols: FdtNodeMut = fdt.symbols_mut().unwrap().unwrap();
 }
 
+#[test]
+fn fdt_aliases() {
+    let data = fs::read(TEST_TREE_ALIASES_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+
+    assert_eq!(fdt.alias(cstr!("cpu0")), Ok(Some(cstr!("/cpus/PowerPC,970@0"))));
+    let cpu0 = fdt.node_by_alias(cstr!("cpu0")).unwrap().unwrap();
+    assert_eq!(cpu0.name(), Ok(cstr!("PowerPC,970@0")));
+
+    assert_eq!(fdt.alias(cstr!("serial0")), Ok(None));
+    assert_eq!(fdt.node_by_alias(cstr!("serial0")), Ok(None));
+    assert_eq!(fdt.node_by_alias(cstr!("missing")), Ok(None));
+    assert_eq!(fdt.alias(cstr!("bad")), Err(FdtError::BadValue));
+}
+
+#[test]
+fn fdt_aliases_without_aliases_node() {
+    let data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+
+    assert_eq!(fdt.aliases(), Ok(None));
+    assert_eq!(fdt.alias(cstr!("cpu0")), Ok(None));
+    assert_eq!(fdt.node_by_alias(cstr!("cpu0")), Ok(None));
+}
+
 #[test]
 fn node_mut_as_node() {
     let mut data = fs::read(TEST_TREE_WITH_ONE_MEMORY_RANGE_PATH).unwrap();
@@ -353,6 +1129,71 @@ fn node_mut_as_node() {
     memory.setprop_inplace(cstr!("device_type"), b"MEMORY\0").unwrap();
 }
 
+#[test]
+fn node_mut_setprop_smaller_value() {
+    let mut data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
+    let fdt = Fdt::from_mut_slice(&mut data).unwrap();
+    let name = cstr!("model");
+    let value = b"Board\0";
+
+    fdt.root_mut().setprop(name, value).unwrap();
+
+    assert_eq!(fdt.root().getprop(name), Ok(Some(value.as_ref())));
+    assert_eq!(fdt.root().getprop_str(name), Ok(Some(cstr!("Board"))));
+}
+
+#[test]
+fn node_mut_setprop_larger_value() {
+    let mut data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
+    data.resize(data.len() * 2, 0_u8);
+    let fdt = Fdt::from_mut_slice(&mut data).unwrap();
+    let name = cstr!("model");
+    let value = b"MyVeryVeryLongBoardName\0";
+
+    // The DT must first be unpacked to make use of the extra space in the buffer.
+    assert_eq!(fdt.root_mut().setprop(name, value), Err(FdtError::NoSpace));
+    fdt.unpack().unwrap();
+    fdt.root_mut().setprop(name, value).unwrap();
+
+    assert_eq!(fdt.root().getprop(name), Ok(Some(value.as_ref())));
+    // Other properties remain readable after the DT grew.
+    assert_eq!(fdt.root().getprop_u32(cstr!("#size-cells")), Ok(Some(1)));
+}
+
+#[test]
+fn chosen_mut_setprop() {
+    let mut data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
+    data.resize(data.len() * 2, 0_u8);
+    let fdt = Fdt::from_mut_slice(&mut data).unwrap();
+    fdt.unpack().unwrap();
+    let name = cstr!("bootargs");
+    let value = b"console=hvc0\0";
+
+    fdt.chosen_mut().unwrap().unwrap().setprop(name, value).unwrap();
+    // Adding a node shifts the offsets of the following nodes, so /chosen must be looked up again.
+    fdt.root_mut().add_subnode(cstr!("avf")).unwrap();
+    fdt.node_mut(cstr!("/chosen")).unwrap().unwrap().setprop(name, value).unwrap();
+
+    assert_eq!(fdt.chosen().unwrap().unwrap().getprop(name), Ok(Some(value.as_ref())));
+}
+
+#[test]
+fn node_mut_setprop_inplace_with_different_size_fails() {
+    let mut data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
+    let fdt = Fdt::from_mut_slice(&mut data).unwrap();
+    let name = cstr!("model");
+
+    assert_eq!(fdt.root_mut().setprop_inplace(name, b"Board\0"), Err(FdtError::NoSpace));
+    assert_eq!(
+        fdt.root_mut().setprop_inplace(name, b"MyVeryVeryLongBoardName\0"),
+        Err(FdtError::NoSpace)
+    );
+    assert_eq!(fdt.root().getprop(name), Ok(Some(b"MyBoardName\0".as_ref())));
+
+    fdt.root_mut().setprop_inplace(name, b"MyBoardNam3\0").unwrap();
+    assert_eq!(fdt.root().getprop(name), Ok(Some(b"MyBoardNam3\0".as_ref())));
+}
+
 #[test]
 fn node_descendants() {
     let mut data = fs::read(TEST_TREE_PHANDLE_PATH).unwrap();
@@ -373,6 +1214,56 @@ fn node_descendants() {
     );
 }
 
+fn read_overlay_base() -> Vec<u8> {
+    let mut data = fs::read(TEST_TREE_OVERLAY_BASE_PATH).unwrap();
+    data.resize(data.len() * 4, 0_u8);
+    Fdt::from_mut_slice(&mut data).unwrap().unpack().unwrap();
+    data
+}
+
+#[test]
+fn apply_overlay_adding_node() {
+    let mut data = read_overlay_base();
+    let fdt = Fdt::from_mut_slice(&mut data).unwrap();
+    let mut overlay_data = fs::read(TEST_OVERLAY_ADD_NODE_PATH).unwrap();
+    let overlay = Fdt::from_mut_slice(&mut overlay_data).unwrap();
+
+    // SAFETY: The overlay is discarded after this call, as is the base DT on failure.
+    let fdt = unsafe { fdt.apply_overlay(overlay) }.unwrap();
+
+    let node = fdt.node(cstr!("/node_b")).unwrap().unwrap();
+    assert_eq!(node.getprop_u32(cstr!("prop")), Ok(Some(0x2a)));
+    assert_ne!(fdt.node(cstr!("/node_a")), Ok(None));
+}
+
+#[test]
+fn apply_overlay_with_phandle_target() {
+    let mut data = read_overlay_base();
+    let fdt = Fdt::from_mut_slice(&mut data).unwrap();
+    let mut overlay_data = fs::read(TEST_OVERLAY_PHANDLE_TARGET_PATH).unwrap();
+    let overlay = Fdt::from_mut_slice(&mut overlay_data).unwrap();
+
+    // SAFETY: The overlay is discarded after this call, as is the base DT on failure.
+    let fdt = unsafe { fdt.apply_overlay(overlay) }.unwrap();
+
+    let node = fdt.node_with_phandle(Phandle::new(0x1).unwrap()).unwrap().unwrap();
+    assert_eq!(node.name(), Ok(cstr!("node_a")));
+    assert_eq!(node.getprop_str(cstr!("prop")), Ok(Some(cstr!("overlaid"))));
+}
+
+#[test]
+fn apply_overlay_without_target_fails() {
+    let mut data = read_overlay_base();
+    let fdt = Fdt::from_mut_slice(&mut data).unwrap();
+    let mut overlay_data = fs::read(TEST_OVERLAY_NO_TARGET_PATH).unwrap();
+    let overlay = Fdt::from_mut_slice(&mut overlay_data).unwrap();
+
+    // SAFETY: The overlay and the base DT are both discarded after this call.
+    let res = unsafe { fdt.apply_overlay(overlay) };
+
+    assert_eq!(res.unwrap_err(), FdtError::BadOverlay);
+}
+
 #[test]
 fn node_mut_delete_and_next_subnode() {
     let mut data = fs::read(TEST_TREE_PHANDLE_PATH).unwrap();
@@ -545,3 +1436,182 @@ fn node_descendants_lifetime() {
     };
     assert_eq!(Ok(cstr!("node_a")), first_descendant_name);
 }
+
+#[test]
+#[ignore] // Borrow checker test. Compilation success is sufficient.
+fn node_properties_lifetime() {
+    let data = fs::read(TEST_TREE_WITH_NO_MEMORY_NODE_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+
+    let first_property_value = {
+        let first_property = {
+            let mut properties_iter = {
+                let root = fdt.root();
+                root.properties().unwrap()
+                // Make root to be dropped
+            };
+            properties_iter.next().unwrap()
+            // Make properties_iter to be dropped
+        };
+        first_property.value()
+        // Make first_property to be dropped
+    };
+    assert_eq!(Ok(b"MyBoardName\0".as_ref()), first_property_value);
+}
+
+#[test]
+fn fdt_builder() {
+    let mut data = vec![0_u8; 1000];
+    let mut builder = FdtBuilder::new(&mut data).unwrap();
+
+    builder.begin_node(cstr!("")).unwrap();
+    builder.property(cstr!("#address-cells"), &2u32.to_be_bytes()).unwrap();
+    builder.property(cstr!("#size-cells"), &2u32.to_be_bytes()).unwrap();
+    builder.begin_node(cstr!("memory@80000000")).unwrap();
+    builder.property(cstr!("device_type"), b"memory\0").unwrap();
+    let reg = [0x8000_0000_u64.to_be_bytes(), 0x1000_0000_u64.to_be_bytes()].concat();
+    builder.property(cstr!("reg"), &reg).unwrap();
+    builder.end_node().unwrap();
+    builder.begin_node(cstr!("chosen")).unwrap();
+    builder.property(cstr!("empty"), &[]).unwrap();
+    builder.end_node().unwrap();
+    builder.end_node().unwrap();
+    let fdt = builder.finish().unwrap();
+
+    assert_eq!(fdt.first_memory_range(), Ok(0x8000_0000..0x9000_0000));
+    let chosen = fdt.chosen().unwrap().unwrap();
+    assert_eq!(chosen.getprop(cstr!("empty")), Ok(Some(b"".as_ref())));
+}
+
+fn fdt_with_chosen<'a>(data: &'a mut [u8], props: &[(&CStr, &[u8])]) -> &'a Fdt {
+    let mut builder = FdtBuilder::new(data).unwrap();
+    builder.begin_node(cstr!("")).unwrap();
+    builder.begin_node(cstr!("chosen")).unwrap();
+    for (name, value) in props {
+        builder.property(name, value).unwrap();
+    }
+    builder.end_node().unwrap();
+    builder.end_node().unwrap();
+    builder.finish().unwrap()
+}
+
+#[test]
+fn fdt_bootargs() {
+    let mut data = vec![0_u8; 1000];
+    let fdt = fdt_with_chosen(&mut data, &[(cstr!("bootargs"), b"console=hvc0\0")]);
+    assert_eq!(fdt.bootargs(), Ok(Some(cstr!("console=hvc0"))));
+
+    let mut data = vec![0_u8; 1000];
+    let fdt = fdt_with_chosen(&mut data, &[]);
+    assert_eq!(fdt.bootargs(), Ok(None));
+
+    let data = fs::read(TEST_TREE_PHANDLE_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+    assert_eq!(fdt.chosen(), Ok(None));
+    assert_eq!(fdt.bootargs(), Ok(None));
+}
+
+#[test]
+fn fdt_initrd_range() {
+    let start32 = 0x1000_u32.to_be_bytes();
+    let end32 = 0x3000_u32.to_be_bytes();
+    let start64 = 0x1_0000_0000_u64.to_be_bytes();
+    let end64 = 0x1_0000_2000_u64.to_be_bytes();
+    let start = cstr!("linux,initrd-start");
+    let end = cstr!("linux,initrd-end");
+
+    let cases: [(&[(&CStr, &[u8])], _); 6] = [
+        (&[(start, &start32), (end, &end32)], Ok(Some(0x1000..0x3000))),
+        (&[(start, &start64), (end, &end64)], Ok(Some(0x1_0000_0000..0x1_0000_2000))),
+        (&[(start, &start32), (end, &end64)], Ok(Some(0x1000..0x1_0000_2000))),
+        (&[(start, &start32)], Ok(None)),
+        (&[(start, &end32), (end, &start32)], Err(FdtError::BadValue)),
+        (&[(start, &start32), (end, &[0, 0x30, 0])], Err(FdtError::BadValue)),
+    ];
+    for (props, expected) in cases {
+        let mut data = vec![0_u8; 1000];
+        let fdt = fdt_with_chosen(&mut data, props);
+        assert_eq!(fdt.initrd_range(), expected, "{props:?}");
+    }
+}
+
+#[test]
+fn node_write_dts() {
+    let mut data = vec![0_u8; 1000];
+    let mut builder = FdtBuilder::new(&mut data).unwrap();
+    builder.begin_node(cstr!("")).unwrap();
+    builder.property(cstr!("compatible"), b"foo,bar\0foo\0").unwrap();
+    builder.begin_node(cstr!("node@1")).unwrap();
+    builder.property(cstr!("reg"), &[0, 0, 0, 1, 0, 0, 0x10, 0]).unwrap();
+    builder.property(cstr!("bytes"), &[0xab, 0x01, 0x02]).unwrap();
+    builder.property(cstr!("empty"), &[]).unwrap();
+    builder.begin_node(cstr!("child")).unwrap();
+    builder.end_node().unwrap();
+    builder.end_node().unwrap();
+    builder.end_node().unwrap();
+    let fdt = builder.finish().unwrap();
+
+    let expected = "/ {\n\
+                    \tcompatible = \"foo,bar\", \"foo\";\n\
+                    \tnode@1 {\n\
+                    \t\treg = <0x1 0x1000>;\n\
+                    \t\tbytes = [ab 01 02];\n\
+                    \t\tempty;\n\
+                    \t\tchild {\n\
+                    \t\t};\n\
+                    \t};\n\
+                    };\n";
+    assert_eq!(fdt.root().dts().to_string(), expected);
+}
+
+#[test]
+fn fdt_builder_misuse() {
+    let mut data = vec![0_u8; 1000];
+    let mut builder = FdtBuilder::new(&mut data).unwrap();
+
+    assert_eq!(builder.property(cstr!("prop"), &[]), Err(FdtError::BadState));
+    assert_eq!(builder.end_node(), Err(FdtError::BadState));
+    builder.begin_node(cstr!("")).unwrap();
+    builder.begin_node(cstr!("node")).unwrap();
+    builder.end_node().unwrap();
+    builder.end_node().unwrap();
+    assert_eq!(builder.begin_node(cstr!("")), Err(FdtError::BadState));
+    assert!(builder.finish().is_ok());
+
+    let mut builder = FdtBuilder::new(&mut data).unwrap();
+    builder.begin_node(cstr!("")).unwrap();
+    assert_eq!(builder.finish().err(), Some(FdtError::BadState));
+}
+
+#[test]
+fn fdt_builder_no_space() {
+    let mut data = vec![0_u8; 100];
+    let mut builder = FdtBuilder::new(&mut data).unwrap();
+
+    builder.begin_node(cstr!("")).unwrap();
+    assert_eq!(builder.property(cstr!("large"), &[0; 100]), Err(FdtError::NoSpace));
+}
+
+#[test]
+fn node_interrupts() {
+    let data = fs::read(TEST_TREE_INTERRUPTS_PATH).unwrap();
+    let fdt = Fdt::from_slice(&data).unwrap();
+    let gic = fdt.node(cstr!("/intc")).unwrap().unwrap();
+    let pic = fdt.node(cstr!("/pic")).unwrap().unwrap();
+    let interrupts = |path: &CStr| -> Vec<(FdtNode, Vec<u32>)> {
+        let node = fdt.node(path).unwrap().unwrap();
+        node.interrupts().unwrap().unwrap().map(|i| (i.parent, i.cells.collect())).collect()
+    };
+
+    assert_eq!(
+        interrupts(cstr!("/dev_inherited")),
+        vec![(gic, vec![0, 1, 4]), (gic, vec![0, 2, 4])]
+    );
+    assert_eq!(interrupts(cstr!("/bus/dev_bus")), vec![(pic, vec![5]), (pic, vec![6])]);
+    assert_eq!(interrupts(cstr!("/dev_extended")), vec![(gic, vec![0, 3, 4]), (pic, vec![7])]);
+
+    let node = fdt.node(cstr!("/dev_truncated")).unwrap().unwrap();
+    assert_eq!(node.interrupts().err(), Some(FdtError::BadValue));
+    let node = fdt.node(cstr!("/dev_none")).unwrap().unwrap();
+    assert!(node.interrupts().unwrap().is_none());
+}
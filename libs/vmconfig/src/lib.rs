@@ -59,6 +59,9 @@ pub struct VmConfig {
     pub memory_mib: Option<NonZeroU32>,
     /// The CPU topology: either "one_cpu"(default) or "match_host"
     pub cpu_topology: Option<String>,
+    /// The number of vCPUs, which takes precedence over the CPU topology.
+    #[serde(default)]
+    pub num_cpus: Option<NonZeroU32>,
     /// Version or range of versions of the virtual platform that this config is compatible with.
     /// The format follows SemVer (https://semver.org).
     pub platform_version: VersionReq,
@@ -103,6 +106,11 @@ impl VmConfig {
         } else {
             0
         };
+        let num_cpus = if let Some(num_cpus) = self.num_cpus {
+            num_cpus.get().try_into().context("Invalid num_cpus")?
+        } else {
+            0
+        };
         let cpu_topology = match self.cpu_topology.as_deref() {
             None => CpuTopology::ONE_CPU,
             Some("one_cpu") => CpuTopology::ONE_CPU,
@@ -118,6 +126,7 @@ impl VmConfig {
             protectedVm: self.protected,
             memoryMib: memory_mib,
             cpuTopology: cpu_topology,
+            numCpus: num_cpus,
             platformVersion: self.platform_version.to_string(),
             devices: self
                 .devices
@@ -162,6 +171,7 @@ impl DiskImage {
             image: maybe_open_parcel_file(&self.image, self.writable)?,
             writable: self.writable,
             partitions,
+            ..Default::default()
         })
     }
 }
@@ -0,0 +1,257 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Encodes the DICE configuration descriptor of a Microdroid payload, as used by
+//! microdroid_manager for the payload DICE node. See microdroid_manager/src/vm_config.cddl for
+//! the definition of the format.
+//!
+//! The encoding is deterministic: map entries are always emitted in the same order, so the same
+//! payload and subcomponents produce byte-for-byte identical descriptors. This allows the
+//! descriptor to be recomputed outside the VM, e.g. to cross-check an attestation.
+
+use ciborium::value::{Integer, Value};
+use microdroid_metadata::PayloadMetadata;
+
+const COMPONENT_NAME_KEY: i64 = -70002;
+const CONFIG_PATH_KEY: i64 = -71000;
+const CONFIG_KEY: i64 = -71001;
+const SUBCOMPONENTS_KEY: i64 = -71002;
+
+const COMPONENT_NAME: &str = "Microdroid payload";
+
+/// Maximum number of subcomponents that a configuration descriptor can list, which bounds its
+/// size.
+pub const MAX_SUBCOMPONENTS: usize = 64;
+
+/// Errors returned when formatting a configuration descriptor.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The payload metadata can't be described by a configuration descriptor.
+    #[error("Invalid payload metadata: {0}")]
+    InvalidPayload(String),
+    /// More than [`MAX_SUBCOMPONENTS`] subcomponents were given.
+    #[error("Too many subcomponents: {0} (maximum {MAX_SUBCOMPONENTS})")]
+    TooManySubcomponents(usize),
+    /// The descriptor couldn't be CBOR-encoded.
+    #[error("Failed to encode config descriptor: {0}")]
+    Encoding(#[from] ciborium::ser::Error<std::io::Error>),
+}
+
+/// A specialized `Result` type for configuration descriptor operations.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Describes an APK or APEX that is part of the payload.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Subcomponent {
+    /// Name of the subcomponent, e.g. `apk:<package name>` or `apex:<apex name>`.
+    pub name: String,
+    /// Version of the subcomponent.
+    pub version: u64,
+    /// Hash of the code of the subcomponent.
+    pub code_hash: Vec<u8>,
+    /// Hash of the authority that signed the subcomponent.
+    pub authority_hash: Vec<u8>,
+}
+
+impl Subcomponent {
+    fn into_value(self) -> Value {
+        Value::Map(vec![
+            (int(1), Value::Text(self.name)),
+            (int(2), Value::Integer(self.version.into())),
+            (int(3), Value::Bytes(self.code_hash)),
+            (int(4), Value::Bytes(self.authority_hash)),
+        ])
+    }
+}
+
+fn int(value: i64) -> Value {
+    Value::Integer(Integer::from(value))
+}
+
+/// Returns the CBOR-encoded configuration descriptor of the given payload and its subcomponents.
+///
+/// Fails with `TooManySubcomponents` if there are more than [`MAX_SUBCOMPONENTS`] of them.
+pub fn format_payload_config_descriptor(
+    payload: &PayloadMetadata,
+    subcomponents: Vec<Subcomponent>,
+) -> Result<Vec<u8>> {
+    if subcomponents.len() > MAX_SUBCOMPONENTS {
+        return Err(Error::TooManySubcomponents(subcomponents.len()));
+    }
+
+    let mut map = Vec::new();
+    map.push((int(COMPONENT_NAME_KEY), Value::Text(COMPONENT_NAME.to_string())));
+    map.push(match payload {
+        PayloadMetadata::ConfigPath(payload_config_path) => {
+            (int(CONFIG_PATH_KEY), Value::Text(payload_config_path.clone()))
+        }
+        PayloadMetadata::Config(payload_config) => {
+            if payload_config.payload_binary_name.is_empty() {
+                return Err(Error::InvalidPayload(
+                    "Payload config has an empty payload_binary_name".to_string(),
+                ));
+            }
+            let config = vec![(int(1), Value::Text(payload_config.payload_binary_name.clone()))];
+            (int(CONFIG_KEY), Value::Map(config))
+        }
+        _ => {
+            return Err(Error::InvalidPayload(format!(
+                "Failed to match the payload against a config type: {:?}",
+                payload
+            )))
+        }
+    });
+
+    if !subcomponents.is_empty() {
+        let values = subcomponents.into_iter().map(Subcomponent::into_value).collect();
+        map.push((int(SUBCOMPONENTS_KEY), Value::Array(values)));
+    }
+
+    let mut descriptor = Vec::new();
+    ciborium::ser::into_writer(&Value::Map(map), &mut descriptor)?;
+    Ok(descriptor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use microdroid_metadata::PayloadConfig;
+
+    const NO_SUBCOMPONENTS: Vec<Subcomponent> = Vec::new();
+
+    fn assert_eq_bytes(expected: &[u8], actual: &[u8]) {
+        assert_eq!(
+            expected,
+            actual,
+            "Expected {}, got {}",
+            hex::encode(expected),
+            hex::encode(actual)
+        )
+    }
+
+    #[test]
+    fn payload_metadata_with_path_formats_correctly() -> Result<()> {
+        let payload_metadata = PayloadMetadata::ConfigPath("/config_path".to_string());
+        let config_descriptor =
+            format_payload_config_descriptor(&payload_metadata, NO_SUBCOMPONENTS)?;
+        static EXPECTED_CONFIG_DESCRIPTOR: &[u8] = &[
+            0xa2, 0x3a, 0x00, 0x01, 0x11, 0x71, 0x72, 0x4d, 0x69, 0x63, 0x72, 0x6f, 0x64, 0x72,
+            0x6f, 0x69, 0x64, 0x20, 0x70, 0x61, 0x79, 0x6c, 0x6f, 0x61, 0x64, 0x3a, 0x00, 0x01,
+            0x15, 0x57, 0x6c, 0x2f, 0x63, 0x6f, 0x6e, 0x66, 0x69, 0x67, 0x5f, 0x70, 0x61, 0x74,
+            0x68,
+        ];
+        assert_eq_bytes(EXPECTED_CONFIG_DESCRIPTOR, &config_descriptor);
+        Ok(())
+    }
+
+    #[test]
+    fn payload_metadata_with_config_formats_correctly() -> Result<()> {
+        let payload_config = PayloadConfig {
+            payload_binary_name: "payload_binary".to_string(),
+            ..Default::default()
+        };
+        let payload_metadata = PayloadMetadata::Config(payload_config);
+        let config_descriptor =
+            format_payload_config_descriptor(&payload_metadata, NO_SUBCOMPONENTS)?;
+        static EXPECTED_CONFIG_DESCRIPTOR: &[u8] = &[
+            0xa2, 0x3a, 0x00, 0x01, 0x11, 0x71, 0x72, 0x4d, 0x69, 0x63, 0x72, 0x6f, 0x64, 0x72,
+            0x6f, 0x69, 0x64, 0x20, 0x70, 0x61, 0x79, 0x6c, 0x6f, 0x61, 0x64, 0x3a, 0x00, 0x01,
+            0x15, 0x58, 0xa1, 0x01, 0x6e, 0x70, 0x61, 0x79, 0x6c, 0x6f, 0x61, 0x64, 0x5f, 0x62,
+            0x69, 0x6e, 0x61, 0x72, 0x79,
+        ];
+        assert_eq_bytes(EXPECTED_CONFIG_DESCRIPTOR, &config_descriptor);
+        Ok(())
+    }
+
+    #[test]
+    fn payload_metadata_with_empty_binary_name_is_rejected() {
+        let payload_metadata = PayloadMetadata::Config(PayloadConfig::default());
+        let err = format_payload_config_descriptor(&payload_metadata, NO_SUBCOMPONENTS)
+            .expect_err("Empty payload_binary_name should be rejected");
+        assert!(
+            matches!(&err, Error::InvalidPayload(msg) if msg.contains("payload_binary_name")),
+            "Unexpected error: {err:?}"
+        );
+    }
+
+    #[test]
+    fn payload_metadata_with_subcomponents_formats_correctly() -> Result<()> {
+        let payload_metadata = PayloadMetadata::ConfigPath("/config_path".to_string());
+        let subcomponents = vec![
+            Subcomponent {
+                name: "apk1".to_string(),
+                version: 1,
+                code_hash: vec![42, 43],
+                authority_hash: vec![17],
+            },
+            Subcomponent {
+                name: "apk2".to_string(),
+                version: 0x1000_0000_0001,
+                code_hash: vec![43],
+                authority_hash: vec![19, 20],
+            },
+        ];
+        let config_descriptor = format_payload_config_descriptor(&payload_metadata, subcomponents)?;
+        // Verified using cbor.me.
+        static EXPECTED_CONFIG_DESCRIPTOR: &[u8] = &[
+            0xa3, 0x3a, 0x00, 0x01, 0x11, 0x71, 0x72, 0x4d, 0x69, 0x63, 0x72, 0x6f, 0x64, 0x72,
+            0x6f, 0x69, 0x64, 0x20, 0x70, 0x61, 0x79, 0x6c, 0x6f, 0x61, 0x64, 0x3a, 0x00, 0x01,
+            0x15, 0x57, 0x6c, 0x2f, 0x63, 0x6f, 0x6e, 0x66, 0x69, 0x67, 0x5f, 0x70, 0x61, 0x74,
+            0x68, 0x3a, 0x00, 0x01, 0x15, 0x59, 0x82, 0xa4, 0x01, 0x64, 0x61, 0x70, 0x6b, 0x31,
+            0x02, 0x01, 0x03, 0x42, 0x2a, 0x2b, 0x04, 0x41, 0x11, 0xa4, 0x01, 0x64, 0x61, 0x70,
+            0x6b, 0x32, 0x02, 0x1b, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x01, 0x03, 0x41,
+            0x2b, 0x04, 0x42, 0x13, 0x14,
+        ];
+        assert_eq_bytes(EXPECTED_CONFIG_DESCRIPTOR, &config_descriptor);
+        Ok(())
+    }
+
+    #[test]
+    fn too_many_subcomponents_are_rejected() -> Result<()> {
+        let payload_metadata = PayloadMetadata::ConfigPath("/config_path".to_string());
+        let subcomponent = |i| Subcomponent {
+            name: format!("apk{i}"),
+            version: 1,
+            code_hash: vec![42, 43],
+            authority_hash: vec![17],
+        };
+
+        let subcomponents: Vec<_> = (0..MAX_SUBCOMPONENTS).map(subcomponent).collect();
+        format_payload_config_descriptor(&payload_metadata, subcomponents)?;
+
+        let subcomponents: Vec<_> = (0..=MAX_SUBCOMPONENTS).map(subcomponent).collect();
+        let err = format_payload_config_descriptor(&payload_metadata, subcomponents)
+            .expect_err("Too many subcomponents should be rejected");
+        assert!(
+            matches!(err, Error::TooManySubcomponents(n) if n == MAX_SUBCOMPONENTS + 1),
+            "Unexpected error: {err:?}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn formatting_is_deterministic() -> Result<()> {
+        let payload_metadata = PayloadMetadata::ConfigPath("/config_path".to_string());
+        let subcomponents = vec![Subcomponent {
+            name: "apk:com.example.app".to_string(),
+            version: 3,
+            code_hash: vec![42, 43],
+            authority_hash: vec![17],
+        }];
+        let first = format_payload_config_descriptor(&payload_metadata, subcomponents.clone())?;
+        let second = format_payload_config_descriptor(&payload_metadata, subcomponents)?;
+        assert_eq_bytes(&first, &second);
+        Ok(())
+    }
+}
@@ -12,42 +12,36 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::instance::{ApexData, ApkData};
-use crate::{is_debuggable, is_strict_boot, MicrodroidData};
-use anyhow::{bail, Context, Result};
-use ciborium::{cbor, Value};
-use coset::CborSerializable;
+use crate::instance::{ApexData, ApkData, ComponentOrder};
+use crate::{is_debuggable, is_strict_boot, MicrodroidData, MicrodroidError};
+use anyhow::{Context, Result};
 use dice_driver::DiceDriver;
-use diced_open_dice::{Hidden, OwnedDiceArtifacts, HIDDEN_SIZE};
+use diced_open_dice::{Hash, Hidden, OwnedDiceArtifacts, HASH_SIZE, HIDDEN_SIZE};
+use microdroid_config_descriptor::{
+    Error as ConfigDescriptorError, Subcomponent, MAX_SUBCOMPONENTS,
+};
 use microdroid_metadata::PayloadMetadata;
 use openssl::sha::{sha512, Sha512};
 use std::iter::once;
 
+// The DICE driver takes `HASH_SIZE` code and authority hashes, which the payload derives with
+// SHA-512.
+const _: () = assert!(HASH_SIZE == 64, "DICE hashes must be SHA-512 digests");
+
 /// Perform an open DICE derivation for the payload.
+///
+/// See `compound_hashes` for the order in which the payload components contribute to the code and
+/// authority hashes. Both are SHA-512 digests, the `HASH_SIZE` the DICE driver requires.
 pub fn dice_derivation(
     dice: DiceDriver,
     instance_data: &MicrodroidData,
     payload_metadata: &PayloadMetadata,
 ) -> Result<OwnedDiceArtifacts> {
-    let subcomponents = build_subcomponent_list(instance_data);
+    let subcomponents = build_subcomponent_list(instance_data)?;
     let config_descriptor = format_payload_config_descriptor(payload_metadata, subcomponents)
         .context("Building config descriptor")?;
 
-    // Calculate compound digests of code and authorities
-    let mut code_hash_ctx = Sha512::new();
-    let mut authority_hash_ctx = Sha512::new();
-    code_hash_ctx.update(instance_data.apk_data.root_hash.as_ref());
-    authority_hash_ctx.update(instance_data.apk_data.cert_hash.as_ref());
-    for extra_apk in &instance_data.extra_apks_data {
-        code_hash_ctx.update(extra_apk.root_hash.as_ref());
-        authority_hash_ctx.update(extra_apk.cert_hash.as_ref());
-    }
-    for apex in &instance_data.apex_data {
-        code_hash_ctx.update(apex.root_digest.as_ref());
-        authority_hash_ctx.update(apex.public_key.as_ref());
-    }
-    let code_hash = code_hash_ctx.finish();
-    let authority_hash = authority_hash_ctx.finish();
+    let (code_hash, authority_hash) = compound_hashes(instance_data);
 
     // Check debuggability, conservatively assuming it is debuggable
     let debuggable = is_debuggable()?;
@@ -56,11 +50,51 @@ pub fn dice_derivation(
     let hidden = if cfg!(llpvm_changes) {
         hidden_input_from_instance_id()?
     } else {
-        instance_data.salt.clone().try_into().unwrap()
+        hidden_input_from_salt(&instance_data.salt)?
     };
     dice.derive(code_hash, &config_descriptor, authority_hash, debuggable, hidden)
 }
 
+/// Returns the APKs and APEXes of the payload in the order given by its `component_order`: the main
+/// APK first, then the extra APKs and the APEXes either as collected or, with
+/// `ComponentOrder::Sorted`, sorted by package name and by name so that the DICE inputs don't
+/// depend on the order in which they were collected. Ties are broken by the code hash.
+fn ordered_components(instance_data: &MicrodroidData) -> (Vec<&ApkData>, Vec<&ApexData>) {
+    let mut extra_apks: Vec<_> = instance_data.extra_apks_data.iter().collect();
+    let mut apexes: Vec<_> = instance_data.apex_data.iter().collect();
+    if instance_data.component_order == ComponentOrder::Sorted {
+        extra_apks
+            .sort_by(|a, b| (&a.package_name, &a.root_hash).cmp(&(&b.package_name, &b.root_hash)));
+        apexes.sort_by(|a, b| (&a.name, &a.root_digest).cmp(&(&b.name, &b.root_digest)));
+    }
+    let apks = once(&instance_data.apk_data).chain(extra_apks).collect();
+    (apks, apexes)
+}
+
+/// Calculates the compound digests of code and authorities of the payload, hashing the APKs then
+/// the APEXes in the order given by `ordered_components`.
+fn compound_hashes(instance_data: &MicrodroidData) -> (Hash, Hash) {
+    let (apks, apexes) = ordered_components(instance_data);
+
+    let mut code_hash_ctx = Sha512::new();
+    let mut authority_hash_ctx = Sha512::new();
+    for apk in apks {
+        code_hash_ctx.update(apk.root_hash.as_ref());
+        authority_hash_ctx.update(apk.cert_hash.as_ref());
+    }
+    for apex in apexes {
+        code_hash_ctx.update(apex.root_digest.as_ref());
+        authority_hash_ctx.update(apex.public_key.as_ref());
+    }
+    (code_hash_ctx.finish(), authority_hash_ctx.finish())
+}
+
+// Get the "Hidden input" for DICE derivation from the (obsolete) salt of the instance.
+fn hidden_input_from_salt(salt: &[u8]) -> Result<Hidden> {
+    salt.try_into()
+        .with_context(|| format!("Salt has {} bytes, expected {HIDDEN_SIZE}", salt.len()))
+}
+
 // Get the "Hidden input" for DICE derivation.
 // This provides differentiation of secrets for different VM instances with same payload.
 fn hidden_input_from_instance_id() -> Result<Hidden> {
@@ -84,81 +118,70 @@ fn hidden_input_from_instance_id() -> Result<Hidden> {
     Ok(hidden_input)
 }
 
-struct Subcomponent {
-    name: String,
-    version: u64,
-    code_hash: Vec<u8>,
-    authority_hash: Vec<u8>,
+fn apk_subcomponent(apk: &ApkData) -> Subcomponent {
+    Subcomponent {
+        name: format!("apk:{}", apk.package_name),
+        version: apk.version_code,
+        code_hash: apk.root_hash.clone(),
+        authority_hash: apk.cert_hash.clone(),
+    }
 }
 
-impl Subcomponent {
-    fn into_value(self) -> Result<Value> {
-        Ok(cbor!({
-           1 => self.name,
-           2 => self.version,
-           3 => Value::Bytes(self.code_hash),
-           4 => Value::Bytes(self.authority_hash),
-        })?)
-    }
-
-    fn for_apk(apk: &ApkData) -> Self {
-        Self {
-            name: format!("apk:{}", apk.package_name),
-            version: apk.version_code,
-            code_hash: apk.root_hash.clone(),
-            authority_hash: apk.cert_hash.clone(),
-        }
+fn apex_subcomponent(apex: &ApexData) -> Subcomponent {
+    // Note that this is only reachable if the dice_changes flag is on, in which case
+    // the manifest data will always be present.
+    Subcomponent {
+        name: format!("apex:{}", apex.manifest_name.as_ref().unwrap()),
+        version: apex.manifest_version.unwrap() as u64,
+        code_hash: apex.root_digest.clone(),
+        authority_hash: sha512(&apex.public_key).to_vec(),
     }
+}
 
-    fn for_apex(apex: &ApexData) -> Self {
-        // Note that this is only reachable if the dice_changes flag is on, in which case
-        // the manifest data will always be present.
-        Self {
-            name: format!("apex:{}", apex.manifest_name.as_ref().unwrap()),
-            version: apex.manifest_version.unwrap() as u64,
-            code_hash: apex.root_digest.clone(),
-            authority_hash: sha512(&apex.public_key).to_vec(),
-        }
+/// Returns the number of subcomponents that the config descriptor of the payload lists.
+pub fn subcomponent_count(instance_data: &MicrodroidData) -> usize {
+    if !cfg!(dice_changes) {
+        return 0;
     }
+    1 + instance_data.extra_apks_data.len() + instance_data.apex_data.len()
 }
 
-fn build_subcomponent_list(instance_data: &MicrodroidData) -> Vec<Subcomponent> {
+/// Returns the subcomponents of the payload, in the same order as they contribute to
+/// `compound_hashes`.
+fn build_subcomponent_list(instance_data: &MicrodroidData) -> Result<Vec<Subcomponent>> {
     if !cfg!(dice_changes) {
-        return vec![];
+        return Ok(vec![]);
+    }
+    let count = subcomponent_count(instance_data);
+    if count > MAX_SUBCOMPONENTS {
+        return Err(MicrodroidError::PayloadInvalidConfig(format!(
+            "Payload has {count} subcomponents, the maximum is {MAX_SUBCOMPONENTS}"
+        ))
+        .into());
     }
 
-    let apks = once(&instance_data.apk_data)
-        .chain(&instance_data.extra_apks_data)
-        .map(Subcomponent::for_apk);
-    let apexes = instance_data.apex_data.iter().map(Subcomponent::for_apex);
-    apks.chain(apexes).collect()
+    let (apks, apexes) = ordered_components(instance_data);
+    let apks = apks.into_iter().map(apk_subcomponent);
+    let apexes = apexes.into_iter().map(apex_subcomponent);
+    Ok(apks.chain(apexes).collect())
 }
 
-// Returns a configuration descriptor of the given payload. See vm_config.cddl for the definition
-// of the format.
+// Returns a configuration descriptor of the given payload. Fails with
+// `MicrodroidError::PayloadInvalidConfig` if the payload metadata can't be described.
 fn format_payload_config_descriptor(
     payload: &PayloadMetadata,
     subcomponents: Vec<Subcomponent>,
 ) -> Result<Vec<u8>> {
-    let mut map = Vec::new();
-    map.push((cbor!(-70002)?, cbor!("Microdroid payload")?));
-    map.push(match payload {
-        PayloadMetadata::ConfigPath(payload_config_path) => {
-            (cbor!(-71000)?, cbor!(payload_config_path)?)
+    match microdroid_config_descriptor::format_payload_config_descriptor(payload, subcomponents) {
+        Ok(descriptor) => Ok(descriptor),
+        Err(ConfigDescriptorError::InvalidPayload(msg)) => {
+            Err(MicrodroidError::PayloadInvalidConfig(msg).into())
         }
-        PayloadMetadata::Config(payload_config) => {
-            (cbor!(-71001)?, cbor!({1 => payload_config.payload_binary_name})?)
+        Err(e @ ConfigDescriptorError::TooManySubcomponents(_)) => {
+            Err(MicrodroidError::PayloadInvalidConfig(e.to_string()).into())
         }
-        _ => bail!("Failed to match the payload against a config type: {:?}", payload),
-    });
-
-    if !subcomponents.is_empty() {
-        let values =
-            subcomponents.into_iter().map(Subcomponent::into_value).collect::<Result<Vec<_>>>()?;
-        map.push((cbor!(-71002)?, cbor!(values)?));
+        Err(e) => Err(e.into()),
     }
-
-    Ok(Value::Map(map).to_vec()?)
 }
 
 #[cfg(test)]
@@ -179,68 +202,170 @@ mod tests {
     }
 
     #[test]
-    fn payload_metadata_with_path_formats_correctly() -> Result<()> {
+    fn payload_metadata_with_empty_binary_name_is_rejected() {
+        let payload_metadata = PayloadMetadata::Config(PayloadConfig::default());
+        let err = format_payload_config_descriptor(&payload_metadata, NO_SUBCOMPONENTS)
+            .expect_err("Empty payload_binary_name should be rejected");
+        assert!(
+            matches!(
+                err.downcast_ref::<MicrodroidError>(),
+                Some(MicrodroidError::PayloadInvalidConfig(msg)) if msg.contains("payload_binary_name")
+            ),
+            "Unexpected error: {err:?}"
+        );
+    }
+
+    #[test]
+    fn payload_metadata_with_apk_and_apex_subcomponents_formats_correctly() -> Result<()> {
         let payload_metadata = PayloadMetadata::ConfigPath("/config_path".to_string());
-        let config_descriptor =
-            format_payload_config_descriptor(&payload_metadata, NO_SUBCOMPONENTS)?;
+        let apk = ApkData {
+            root_hash: vec![42, 43],
+            cert_hash: vec![17],
+            package_name: "com.example.app".to_string(),
+            version_code: 3,
+        };
+        let apex = ApexData {
+            name: "com.example.apex@4".to_string(),
+            manifest_name: Some("com.example.apex".to_string()),
+            manifest_version: Some(4),
+            public_key: vec![1, 2, 3],
+            root_digest: vec![44],
+            last_update_seconds: 0,
+            is_factory: true,
+        };
+        let subcomponents = vec![apk_subcomponent(&apk), apex_subcomponent(&apex)];
+        let config_descriptor = format_payload_config_descriptor(&payload_metadata, subcomponents)?;
+        // The APEX authority hash is the SHA-512 digest of its public key.
         static EXPECTED_CONFIG_DESCRIPTOR: &[u8] = &[
-            0xa2, 0x3a, 0x00, 0x01, 0x11, 0x71, 0x72, 0x4d, 0x69, 0x63, 0x72, 0x6f, 0x64, 0x72,
+            0xa3, 0x3a, 0x00, 0x01, 0x11, 0x71, 0x72, 0x4d, 0x69, 0x63, 0x72, 0x6f, 0x64, 0x72,
             0x6f, 0x69, 0x64, 0x20, 0x70, 0x61, 0x79, 0x6c, 0x6f, 0x61, 0x64, 0x3a, 0x00, 0x01,
             0x15, 0x57, 0x6c, 0x2f, 0x63, 0x6f, 0x6e, 0x66, 0x69, 0x67, 0x5f, 0x70, 0x61, 0x74,
-            0x68,
+            0x68, 0x3a, 0x00, 0x01, 0x15, 0x59, 0x82, 0xa4, 0x01, 0x73, 0x61, 0x70, 0x6b, 0x3a,
+            0x63, 0x6f, 0x6d, 0x2e, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x2e, 0x61, 0x70,
+            0x70, 0x02, 0x03, 0x03, 0x42, 0x2a, 0x2b, 0x04, 0x41, 0x11, 0xa4, 0x01, 0x75, 0x61,
+            0x70, 0x65, 0x78, 0x3a, 0x63, 0x6f, 0x6d, 0x2e, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c,
+            0x65, 0x2e, 0x61, 0x70, 0x65, 0x78, 0x02, 0x04, 0x03, 0x41, 0x2c, 0x04, 0x58, 0x40,
+            0x27, 0x86, 0x4c, 0xc5, 0x21, 0x9a, 0x95, 0x1a, 0x7a, 0x6e, 0x52, 0xb8, 0xc8, 0xdd,
+            0xdf, 0x69, 0x81, 0xd0, 0x98, 0xda, 0x16, 0x58, 0xd9, 0x62, 0x58, 0xc8, 0x70, 0xb2,
+            0xc8, 0x8d, 0xfb, 0xcb, 0x51, 0x84, 0x1a, 0xea, 0x17, 0x2a, 0x28, 0xba, 0xfa, 0x6a,
+            0x79, 0x73, 0x11, 0x65, 0x58, 0x46, 0x77, 0x06, 0x60, 0x45, 0xc9, 0x59, 0xed, 0x0f,
+            0x99, 0x29, 0x68, 0x8d, 0x04, 0xde, 0xfc, 0x29,
         ];
         assert_eq_bytes(EXPECTED_CONFIG_DESCRIPTOR, &config_descriptor);
         Ok(())
     }
 
+    fn apk(package_name: &str, hash: u8) -> ApkData {
+        ApkData {
+            root_hash: vec![hash],
+            cert_hash: vec![hash, hash],
+            package_name: package_name.to_string(),
+            version_code: 1,
+        }
+    }
+
+    fn apex(name: &str, hash: u8) -> ApexData {
+        ApexData {
+            name: name.to_string(),
+            manifest_name: Some(name.to_string()),
+            manifest_version: Some(1),
+            public_key: vec![hash, hash],
+            root_digest: vec![hash],
+            last_update_seconds: 0,
+            is_factory: true,
+        }
+    }
+
     #[test]
-    fn payload_metadata_with_config_formats_correctly() -> Result<()> {
-        let payload_config = PayloadConfig {
-            payload_binary_name: "payload_binary".to_string(),
-            ..Default::default()
+    fn hidden_input_from_salt_checks_size() {
+        assert!(hidden_input_from_salt(&[0; HIDDEN_SIZE]).is_ok());
+        assert!(hidden_input_from_salt(&[]).is_err());
+        assert!(hidden_input_from_salt(&[0; HIDDEN_SIZE - 1]).is_err());
+        assert!(hidden_input_from_salt(&[0; HIDDEN_SIZE + 1]).is_err());
+    }
+
+    #[test]
+    fn instance_data_with_wrong_salt_size_is_rejected() {
+        let mut instance_data = MicrodroidData {
+            salt: vec![0; HIDDEN_SIZE],
+            apk_data: apk("com.example.main", 1),
+            extra_apks_data: vec![],
+            apex_data: vec![],
+            component_order: ComponentOrder::Collected,
         };
-        let payload_metadata = PayloadMetadata::Config(payload_config);
-        let config_descriptor =
-            format_payload_config_descriptor(&payload_metadata, NO_SUBCOMPONENTS)?;
-        static EXPECTED_CONFIG_DESCRIPTOR: &[u8] = &[
-            0xa2, 0x3a, 0x00, 0x01, 0x11, 0x71, 0x72, 0x4d, 0x69, 0x63, 0x72, 0x6f, 0x64, 0x72,
-            0x6f, 0x69, 0x64, 0x20, 0x70, 0x61, 0x79, 0x6c, 0x6f, 0x61, 0x64, 0x3a, 0x00, 0x01,
-            0x15, 0x58, 0xa1, 0x01, 0x6e, 0x70, 0x61, 0x79, 0x6c, 0x6f, 0x61, 0x64, 0x5f, 0x62,
-            0x69, 0x6e, 0x61, 0x72, 0x79,
-        ];
-        assert_eq_bytes(EXPECTED_CONFIG_DESCRIPTOR, &config_descriptor);
+        assert!(instance_data.check_salt().is_ok());
+
+        instance_data.salt.pop();
+        assert!(instance_data.check_salt().is_err());
+    }
+
+    #[test]
+    fn compound_hashes_follow_collected_component_order() {
+        let mut instance_data = MicrodroidData {
+            salt: vec![],
+            apk_data: apk("com.example.main", 1),
+            extra_apks_data: vec![apk("com.example.b", 3), apk("com.example.a", 2)],
+            apex_data: vec![apex("com.android.b", 5), apex("com.android.a", 4)],
+            component_order: ComponentOrder::Collected,
+        };
+        let hashes = compound_hashes(&instance_data);
+
+        // Existing instances keep the order in which their components were collected.
+        let (apks, apexes) = ordered_components(&instance_data);
+        assert_eq!(apks[1].package_name, "com.example.b");
+        assert_eq!(apexes[0].name, "com.android.b");
+
+        instance_data.component_order = ComponentOrder::Sorted;
+        assert_ne!(hashes, compound_hashes(&instance_data));
+    }
+
+    #[test]
+    fn instance_data_saved_without_component_order_uses_collected_order() -> Result<()> {
+        // Instance data as saved before the component order was recorded.
+        #[derive(serde::Serialize)]
+        struct SavedMicrodroidData {
+            salt: Vec<u8>,
+            apk_data: ApkData,
+            extra_apks_data: Vec<ApkData>,
+            apex_data: Vec<ApexData>,
+        }
+        let saved = SavedMicrodroidData {
+            salt: vec![0; HIDDEN_SIZE],
+            apk_data: apk("com.example.main", 1),
+            extra_apks_data: vec![apk("com.example.b", 3)],
+            apex_data: vec![apex("com.android.b", 5)],
+        };
+
+        let instance_data: MicrodroidData = serde_cbor::from_slice(&serde_cbor::to_vec(&saved)?)?;
+        assert_eq!(instance_data.component_order, ComponentOrder::Collected);
         Ok(())
     }
 
     #[test]
-    fn payload_metadata_with_subcomponents_formats_correctly() -> Result<()> {
+    fn compound_hashes_do_not_depend_on_component_order() -> Result<()> {
         let payload_metadata = PayloadMetadata::ConfigPath("/config_path".to_string());
-        let subcomponents = vec![
-            Subcomponent {
-                name: "apk1".to_string(),
-                version: 1,
-                code_hash: vec![42, 43],
-                authority_hash: vec![17],
-            },
-            Subcomponent {
-                name: "apk2".to_string(),
-                version: 0x1000_0000_0001,
-                code_hash: vec![43],
-                authority_hash: vec![19, 20],
-            },
-        ];
-        let config_descriptor = format_payload_config_descriptor(&payload_metadata, subcomponents)?;
-        // Verified using cbor.me.
-        static EXPECTED_CONFIG_DESCRIPTOR: &[u8] = &[
-            0xa3, 0x3a, 0x00, 0x01, 0x11, 0x71, 0x72, 0x4d, 0x69, 0x63, 0x72, 0x6f, 0x64, 0x72,
-            0x6f, 0x69, 0x64, 0x20, 0x70, 0x61, 0x79, 0x6c, 0x6f, 0x61, 0x64, 0x3a, 0x00, 0x01,
-            0x15, 0x57, 0x6c, 0x2f, 0x63, 0x6f, 0x6e, 0x66, 0x69, 0x67, 0x5f, 0x70, 0x61, 0x74,
-            0x68, 0x3a, 0x00, 0x01, 0x15, 0x59, 0x82, 0xa4, 0x01, 0x64, 0x61, 0x70, 0x6b, 0x31,
-            0x02, 0x01, 0x03, 0x42, 0x2a, 0x2b, 0x04, 0x41, 0x11, 0xa4, 0x01, 0x64, 0x61, 0x70,
-            0x6b, 0x32, 0x02, 0x1b, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x01, 0x03, 0x41,
-            0x2b, 0x04, 0x42, 0x13, 0x14,
-        ];
-        assert_eq_bytes(EXPECTED_CONFIG_DESCRIPTOR, &config_descriptor);
+        let config_descriptor = |instance_data: &MicrodroidData| {
+            let subcomponents = build_subcomponent_list(instance_data)?;
+            format_payload_config_descriptor(&payload_metadata, subcomponents)
+        };
+        let mut instance_data = MicrodroidData {
+            salt: vec![],
+            apk_data: apk("com.example.main", 1),
+            extra_apks_data: vec![apk("com.example.a", 2), apk("com.example.b", 3)],
+            apex_data: vec![apex("com.android.a", 4), apex("com.android.b", 5)],
+            component_order: ComponentOrder::Sorted,
+        };
+        let hashes = compound_hashes(&instance_data);
+        let descriptor = config_descriptor(&instance_data)?;
+
+        instance_data.extra_apks_data.reverse();
+        instance_data.apex_data.reverse();
+        assert_eq!(hashes, compound_hashes(&instance_data));
+        assert_eq_bytes(&descriptor, &config_descriptor(&instance_data)?);
+
+        // Swapping the main APK with an extra APK changes the hashes.
+        std::mem::swap(&mut instance_data.apk_data, &mut instance_data.extra_apks_data[0]);
+        assert_ne!(hashes, compound_hashes(&instance_data));
         Ok(())
     }
 }
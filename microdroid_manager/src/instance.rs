@@ -38,6 +38,7 @@ use crate::ioutil;
 use anyhow::{anyhow, bail, Context, Result};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use dice_driver::DiceDriver;
+use diced_open_dice::HIDDEN_SIZE;
 use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
 use serde::{Deserialize, Serialize};
 use std::fs::{File, OpenOptions};
@@ -47,6 +48,9 @@ use uuid::Uuid;
 /// Path to the instance disk inside the VM
 const INSTANCE_IMAGE_PATH: &str = "/dev/block/by-name/vm-instance";
 
+/// Size of the salt of a VM instance, which is used as the hidden DICE input.
+pub const SALT_SIZE: usize = HIDDEN_SIZE;
+
 /// Identifier for the key used to seal the instance data.
 const INSTANCE_KEY_IDENTIFIER: &[u8] = b"microdroid_manager_key";
 
@@ -146,7 +150,8 @@ impl InstanceDisk {
         let key = dice.get_sealing_key(INSTANCE_KEY_IDENTIFIER, cipher.key_len())?;
         let plaintext = decrypt_aead(cipher, &key, Some(&nonce), &header, &data, &tag)?;
 
-        let microdroid_data = serde_cbor::from_slice(plaintext.as_slice())?;
+        let microdroid_data: MicrodroidData = serde_cbor::from_slice(plaintext.as_slice())?;
+        microdroid_data.check_salt()?;
         Ok(Some(microdroid_data))
     }
 
@@ -279,9 +284,45 @@ pub struct MicrodroidData {
     pub apk_data: ApkData,
     pub extra_apks_data: Vec<ApkData>,
     pub apex_data: Vec<ApexData>,
+    /// Order of the components in the DICE inputs. The data of instances saved before it was
+    /// recorded defaults to the collected order, which they have always used.
+    #[serde(default)]
+    pub component_order: ComponentOrder,
+}
+
+/// Order in which the extra APKs and the APEXes of the payload contribute to the DICE inputs. It
+/// is part of the identity of an instance, as a different order derives different secrets.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ComponentOrder {
+    /// The order in which the components were collected from the payload.
+    #[default]
+    Collected,
+    /// The extra APKs sorted by package name and the APEXes sorted by name.
+    Sorted,
+}
+
+impl ComponentOrder {
+    /// Returns the order used by instances booted for the first time. Instances which aren't
+    /// saved to the instance disk (i.e. when rollback protection is deferred to Secretkeeper) are
+    /// booted for the "first time" on each boot, so the build flag must not change for them.
+    pub fn for_new_instance() -> Self {
+        if cfg!(sorted_dice_components) {
+            Self::Sorted
+        } else {
+            Self::Collected
+        }
+    }
 }
 
 impl MicrodroidData {
+    /// Checks that the salt has the size expected by the DICE derivation.
+    pub fn check_salt(&self) -> Result<()> {
+        if self.salt.len() != SALT_SIZE {
+            bail!("Invalid salt size: {} bytes, expected {SALT_SIZE}", self.salt.len());
+        }
+        Ok(())
+    }
+
     pub fn extra_apk_root_hash_eq(&self, i: usize, root_hash: &[u8]) -> bool {
         self.extra_apks_data.get(i).map_or(false, |apk| apk.root_hash_eq(root_hash))
     }
@@ -290,6 +331,7 @@ impl MicrodroidData {
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct ApkData {
     pub root_hash: Vec<u8>,
+    /// SHA-512 digest of the DER-encoded signing certificate of the APK.
     pub cert_hash: Vec<u8>,
     pub package_name: String,
     pub version_code: u64,
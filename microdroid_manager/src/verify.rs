@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::instance::{ApexData, ApkData, MicrodroidData};
+use crate::instance::{ApexData, ApkData, ComponentOrder, MicrodroidData, SALT_SIZE};
 use crate::payload::{get_apex_data_from_payload, to_metadata};
 use crate::{is_strict_boot, MicrodroidError};
 use anyhow::{anyhow, ensure, Context, Result};
@@ -170,23 +170,28 @@ pub fn verify_payload(
 
     let salt = if cfg!(llpvm_changes) || is_strict_boot() {
         // Salt is obsolete with llpvm_changes.
-        vec![0u8; 64]
+        vec![0u8; SALT_SIZE]
     } else if let Some(saved_data) = saved_data {
         // Use the salt from a verified instance.
         saved_data.salt.clone()
     } else {
         // Generate a salt for a new instance.
-        let mut salt = vec![0u8; 64];
+        let mut salt = vec![0u8; SALT_SIZE];
         salt.as_mut_slice().try_fill(&mut rand::thread_rng())?;
         salt
     };
 
-    Ok(MicrodroidData {
+    let data = MicrodroidData {
         salt,
         apk_data: main_apk_data,
         extra_apks_data,
         apex_data: apex_data_from_payload,
-    })
+        // Keep the order of an existing instance, so that it derives the same secrets.
+        component_order: saved_data
+            .map_or_else(ComponentOrder::for_new_instance, |data| data.component_order),
+    };
+    data.check_salt()?;
+    Ok(data)
 }
 
 fn get_data_from_apk(
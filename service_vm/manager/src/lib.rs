@@ -227,7 +227,12 @@ pub fn protected_vm_instance(instance_img_path: PathBuf) -> Result<VmInstance> {
     let config = VirtualMachineConfig::RawConfig(VirtualMachineRawConfig {
         name: String::from("Service VM"),
         bootloader: Some(ParcelFileDescriptor::new(rialto)),
-        disks: vec![DiskImage { image: None, partitions: writable_partitions, writable: true }],
+        disks: vec![DiskImage {
+            image: None,
+            partitions: writable_partitions,
+            writable: true,
+            ..Default::default()
+        }],
         instanceId: instance_id,
         protectedVm: true,
         memoryMib: MEMORY_MB,
@@ -15,11 +15,16 @@
 //! Implementation of the AIDL interface of the VirtualizationService.
 
 use crate::{get_calling_pid, get_calling_uid, get_this_pid};
-use crate::atom::{write_vm_booted_stats, write_vm_creation_stats};
-use crate::composite::make_composite_image;
-use crate::crosvm::{CrosvmConfig, DiskFile, DisplayConfig, GpuConfig, InputDeviceOption, PayloadState, VmContext, VmInstance, VmState};
+use crate::atom::{get_num_cpus, write_vm_booted_stats, write_vm_creation_stats};
+use crate::cgroup::MAX_CPU_QUOTA_PERCENT;
+use crate::composite::{
+    make_composite_image, reuse_composite_image, CompositeImageError, CompositeSpec,
+    PARTITION_GRANULARITY_BYTES,
+};
+use crate::crosvm::{CrosvmConfig, DiskFile, DisplayConfig, GpuConfig, InputDeviceOption, PayloadState, Protection, SwapPolicy, VmContext, VmInstance, VmState};
 use crate::debug_config::DebugConfig;
 use crate::dt_overlay::{create_device_tree_overlay, VM_DT_OVERLAY_MAX_SIZE, VM_DT_OVERLAY_PATH};
+use crate::ext4;
 use crate::payload::{add_microdroid_payload_images, add_microdroid_system_images, add_microdroid_vendor_image};
 use crate::selinux::{getfilecon, SeContext};
 use android_os_permissions_aidl::aidl::android::os::IPermissionController;
@@ -36,9 +41,11 @@ use android_system_virtualizationservice::aidl::android::system::virtualizations
     IVirtualMachine::{BnVirtualMachine, IVirtualMachine},
     IVirtualMachineCallback::IVirtualMachineCallback,
     IVirtualizationService::IVirtualizationService,
+    MemoryStats::MemoryStats,
     MemoryTrimLevel::MemoryTrimLevel,
     Partition::Partition,
     PartitionType::PartitionType,
+    SwapPolicy::SwapPolicy as SwapPolicyParcelable,
     VirtualMachineAppConfig::{DebugLevel::DebugLevel, Payload::Payload, VirtualMachineAppConfig},
     VirtualMachineConfig::VirtualMachineConfig,
     VirtualMachineDebugInfo::VirtualMachineDebugInfo,
@@ -58,8 +65,8 @@ use android_hardware_security_authgraph::aidl::android::hardware::security::auth
     Key::Key, PubKey::PubKey, SessionIdSignature::SessionIdSignature, SessionInfo::SessionInfo,
     SessionInitiationInfo::SessionInitiationInfo,
 };
-use anyhow::{anyhow, bail, Context, Result};
-use apkverify::{HashAlgorithm, V4Signature};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use apkverify::{get_apk_digest, HashAlgorithm, V4Signature};
 use avflog::LogResult;
 use binder::{
     self, wait_for_interface, BinderFeatures, ExceptionCode, Interface, ParcelFileDescriptor,
@@ -71,25 +78,30 @@ use glob::glob;
 use lazy_static::lazy_static;
 use log::{debug, error, info, warn};
 use microdroid_payload_config::{ApkConfig, Task, TaskType, VmPayloadConfig};
+use nix::sys::statfs::{statfs, FsType, RAMFS_MAGIC, TMPFS_MAGIC};
 use nix::unistd::pipe;
 use rpcbinder::RpcServer;
 use rustutils::system_properties;
 use semver::VersionReq;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
 use std::fs;
 use std::ffi::CStr;
 use std::fs::{canonicalize, read_dir, remove_file, File, OpenOptions};
-use std::io::{BufRead, BufReader, Error, ErrorKind, Seek, SeekFrom, Write};
+use std::io::{BufRead, BufReader, Cursor, Error, ErrorKind, Read, Seek, SeekFrom, Write};
 use std::iter;
-use std::num::{NonZeroU16, NonZeroU32};
+use std::mem;
+use std::num::{NonZeroU16, NonZeroU32, NonZeroU8};
 use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
 use std::os::unix::raw::pid_t;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
 use vbmeta::VbMetaImage;
 use vmconfig::{VmConfig, get_debug_level};
 use vsock::VsockStream;
+use zip::result::ZipError;
 use zip::ZipArchive;
 
 /// The unique ID of a VM used (together with a port number) for vsock communication.
@@ -114,8 +126,22 @@ const SECRETKEEPER_IDENTIFIER: &str =
 
 const UNFORMATTED_STORAGE_MAGIC: &str = "UNFORMATTED-STORAGE";
 
-/// crosvm requires all partitions to be a multiple of 4KiB.
-const PARTITION_GRANULARITY_BYTES: u64 = 4096;
+/// Maximum boot timeout which a client can request.
+const MAX_BOOT_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// Maximum timeout which a client can request from connectVsockWithTimeout.
+const MAX_VSOCK_CONNECT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Delay between attempts of connectVsockWithTimeout.
+const VSOCK_CONNECT_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// System property overriding the maximum size of a partition created by
+/// initializeWritablePartition.
+const SYSPROP_MAX_PARTITION_SIZE: &str = "virtualizationservice.max_partition_size";
+const DEFAULT_MAX_PARTITION_SIZE: u64 = 64 << 30;
+
+/// System property enabling the memory balloon of VMs.
+const SYSPROP_MEMORY_RECLAIM_SUPPORTED: &str = "hypervisor.memory_reclaim.supported";
 
 const VM_REFERENCE_DT_ON_HOST_PATH: &str = "/proc/device-tree/avf/reference";
 
@@ -123,8 +149,8 @@ lazy_static! {
     pub static ref GLOBAL_SERVICE: Strong<dyn IVirtualizationServiceInternal> =
         wait_for_interface(BINDER_SERVICE_IDENTIFIER)
             .expect("Could not connect to VirtualizationServiceInternal");
-    static ref SUPPORTED_OS_NAMES: HashSet<String> =
-        get_supported_os_names().expect("Failed to get list of supported os names");
+    static ref SUPPORTED_OSES: HashMap<String, GuestOs> =
+        get_supported_oses().expect("Failed to get list of supported OSes");
 }
 
 fn create_or_update_idsig_file(
@@ -136,17 +162,18 @@ fn create_or_update_idsig_file(
     if !metadata.is_file() {
         bail!("input is not a regular file");
     }
-    let mut sig =
-        V4Signature::create(&mut input, get_current_sdk()?, 4096, &[], HashAlgorithm::SHA256)
-            .context("failed to create idsig")?;
+    let current_sdk = get_current_sdk()?;
 
     let mut output = clone_file(idsig_fd)?;
 
     // Optimization. We don't have to update idsig file whenever a VM is started. Don't update it,
-    // if the idsig file already has the same APK digest.
+    // if the idsig file already has the same APK digest. The APK digest is read from the signing
+    // block, which is much cheaper than hashing the whole APK.
     if output.metadata()?.len() > 0 {
         if let Ok(out_sig) = V4Signature::from_idsig(&mut output) {
-            if out_sig.signing_info.apk_digest == sig.signing_info.apk_digest {
+            let (_, apk_digest) = get_apk_digest(&mut input, current_sdk, /* verify= */ false)
+                .context("failed to get apk digest")?;
+            if out_sig.signing_info.apk_digest == apk_digest {
                 debug!("idsig {:?} is up-to-date with apk {:?}.", output, input);
                 return Ok(());
             }
@@ -155,14 +182,30 @@ fn create_or_update_idsig_file(
         // We will anyway overwrite the file to the v4signature generated from input_fd.
     }
 
+    // Serialize the idsig before touching the output, so that it isn't left half-written if
+    // anything fails.
+    let sig_data = create_idsig(&mut input, current_sdk)?;
+
     output
         .seek(SeekFrom::Start(0))
         .context("failed to move cursor to start on the idsig output")?;
     output.set_len(0).context("failed to set_len on the idsig output")?;
-    sig.write_into(&mut output).context("failed to write idsig")?;
+    output.write_all(&sig_data).context("failed to write idsig")?;
     Ok(())
 }
 
+/// Returns the contents of the idsig file (V4 signature) of the given APK.
+///
+/// As for `V4Signature::create`, the caller must make sure that `apk` is a regular file.
+fn create_idsig<R: Read + Seek>(apk: &mut R, current_sdk: u32) -> Result<Vec<u8>> {
+    apk.seek(SeekFrom::Start(0)).context("failed to move cursor to start on the apk")?;
+    let mut sig = V4Signature::create(apk, current_sdk, 4096, &[], HashAlgorithm::SHA256)
+        .context("failed to create idsig")?;
+    let mut sig_data = Cursor::new(Vec::new());
+    sig.write_into(&mut sig_data).context("failed to serialize idsig")?;
+    Ok(sig_data.into_inner())
+}
+
 fn get_current_sdk() -> Result<u32> {
     let current_sdk = system_properties::read("ro.build.version.sdk")?;
     let current_sdk = current_sdk.ok_or_else(|| anyhow!("SDK version missing"))?;
@@ -201,6 +244,8 @@ impl Interface for VirtualizationService {
                 .or(Err(StatusCode::UNKNOWN_ERROR))?;
             writeln!(writer, "\trequester_debug_pid: {}", vm.requester_debug_pid)
                 .or(Err(StatusCode::UNKNOWN_ERROR))?;
+            writeln!(writer, "\tcrosvm_args: {:?}", vm.crosvm_args())
+                .or(Err(StatusCode::UNKNOWN_ERROR))?;
         }
         Ok(())
     }
@@ -243,11 +288,8 @@ impl IVirtualizationService for VirtualizationService {
         partition_type: PartitionType,
     ) -> binder::Result<()> {
         check_manage_access()?;
-        let size_bytes = size_bytes
-            .try_into()
-            .with_context(|| format!("Invalid size: {}", size_bytes))
+        let size_bytes = check_partition_size(size_bytes, partition_type, max_partition_size())
             .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT)?;
-        let size_bytes = round_up(size_bytes, PARTITION_GRANULARITY_BYTES);
         let mut image = clone_file(image_fd)?;
         // initialize the file. Any data in the file will be erased.
         image
@@ -266,6 +308,7 @@ impl IVirtualizationService for VirtualizationService {
             PartitionType::RAW => Ok(()),
             PartitionType::ANDROID_VM_INSTANCE => format_as_android_vm_instance(&mut image),
             PartitionType::ENCRYPTEDSTORE => format_as_encryptedstore(&mut image),
+            PartitionType::ANDROID_VM_EXT4 => ext4::format(&mut image, size_bytes),
             _ => Err(Error::new(
                 ErrorKind::Unsupported,
                 format!("Unsupported partition type {:?}", partition_type),
@@ -296,6 +339,24 @@ impl IVirtualizationService for VirtualizationService {
         GLOBAL_SERVICE.debugListVms()
     }
 
+    /// Kills the VM with the given CID, returning whether it was found. This method is only
+    /// intended for debug purposes, and as such is only permitted from the shell user.
+    fn debugKillVm(&self, cid: i32) -> binder::Result<bool> {
+        check_debug_access()?;
+
+        let Ok(cid) = Cid::try_from(cid) else {
+            return Ok(false);
+        };
+        let Some(vm) = self.state.lock().unwrap().get_vm(cid) else {
+            return Ok(false);
+        };
+        vm.kill()
+            .with_context(|| format!("Error killing VM with CID {cid}"))
+            .with_log()
+            .or_service_specific_exception(-1)?;
+        Ok(true)
+    }
+
     /// Get a list of assignable device types.
     fn getAssignableDevices(&self) -> binder::Result<Vec<AssignableDevice>> {
         // Delegate to the global service, including checking the permission.
@@ -304,7 +365,7 @@ impl IVirtualizationService for VirtualizationService {
 
     /// Get a list of supported OSes.
     fn getSupportedOSList(&self) -> binder::Result<Vec<String>> {
-        Ok(Vec::from_iter(SUPPORTED_OS_NAMES.iter().cloned()))
+        Ok(Vec::from_iter(SUPPORTED_OSES.keys().cloned()))
     }
 
     /// Returns whether given feature is enabled
@@ -349,11 +410,12 @@ impl VirtualizationService {
     fn create_vm_context(
         &self,
         requester_debug_pid: pid_t,
+        name: &str,
     ) -> binder::Result<(VmContext, Cid, PathBuf)> {
         const NUM_ATTEMPTS: usize = 5;
 
         for _ in 0..NUM_ATTEMPTS {
-            let vm_context = GLOBAL_SERVICE.allocateGlobalVmContext(requester_debug_pid)?;
+            let vm_context = GLOBAL_SERVICE.allocateGlobalVmContext(requester_debug_pid, name)?;
             let cid = vm_context.getCid()? as Cid;
             let temp_dir: PathBuf = vm_context.getTemporaryDirectory()?.into();
             let service = VirtualMachineService::new_binder(self.state.clone(), cid).as_binder();
@@ -387,8 +449,14 @@ impl VirtualizationService {
 
         check_config_features(config)?;
 
+        let name = match config {
+            VirtualMachineConfig::RawConfig(config) => &config.name,
+            VirtualMachineConfig::AppConfig(config) => &config.name,
+        };
+
         // Allocating VM context checks the MANAGE_VIRTUAL_MACHINE permission.
-        let (vm_context, cid, temporary_directory) = self.create_vm_context(requester_debug_pid)?;
+        let (vm_context, cid, temporary_directory) =
+            self.create_vm_context(requester_debug_pid, name)?;
 
         if is_custom_config(config) {
             check_use_custom_virtual_machine()?;
@@ -456,6 +524,11 @@ impl VirtualizationService {
             })
             .try_for_each(check_label_for_partition)
             .or_service_specific_exception(-1)?;
+        config
+            .disks
+            .iter()
+            .try_for_each(check_label_for_composite_components)
+            .or_service_specific_exception(-1)?;
 
         // Check if files for payloads and bases are NOT coming from /vendor and /odm, as they may
         // have unstable interfaces.
@@ -470,6 +543,8 @@ impl VirtualizationService {
             check_label_for_kernel_files(&kernel, &initrd).or_service_specific_exception(-1)?;
         }
 
+        let protection = get_protection(config)?;
+
         let zero_filler_path = temporary_directory.join("zero.img");
         write_zero_filler(&zero_filler_path)
             .context("Failed to make composite image")
@@ -500,6 +575,26 @@ impl VirtualizationService {
                     .or_service_specific_exception(-1);
             }
         };
+        let (cpus, host_cpu_topology) = match u32::try_from(config.numCpus) {
+            Ok(0) => (cpus, host_cpu_topology),
+            Ok(num_cpus) => {
+                let host_cpus = get_num_cpus().and_then(|n| u32::try_from(n).ok());
+                let num_cpus = host_cpus.map_or(num_cpus, |host_cpus| num_cpus.min(host_cpus));
+                (NonZeroU32::new(num_cpus), false)
+            }
+            Err(_) => {
+                return Err(anyhow!("Invalid number of vCPUs: {}", config.numCpus))
+                    .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT);
+            }
+        };
+        let cpu_quota_percent = match u8::try_from(config.cpuQuotaPercent) {
+            Ok(0) => None,
+            Ok(percent) if percent <= MAX_CPU_QUOTA_PERCENT => NonZeroU8::new(percent),
+            _ => {
+                return Err(anyhow!("Invalid CPU quota: {}%", config.cpuQuotaPercent))
+                    .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT);
+            }
+        };
 
         let (vfio_devices, dtbo) = if !config.devices.is_empty() {
             let mut set = HashSet::new();
@@ -577,6 +672,12 @@ impl VirtualizationService {
         };
         let virtio_snd_backend =
             if cfg!(paravirtualized_devices) { Some(String::from("aaudio")) } else { None };
+        let swap_policy = get_swap_policy(config, *is_protected, &temporary_directory)?;
+        let boot_timeout = get_boot_timeout(config.bootTimeoutMillis, is_app_config)?;
+        let balloon = system_properties::read_bool(SYSPROP_MEMORY_RECLAIM_SUPPORTED, false)
+            .with_context(|| format!("Failed to read {SYSPROP_MEMORY_RECLAIM_SUPPORTED}"))
+            .with_log()
+            .or_service_specific_exception(-1)?;
 
         // Actually start the VM.
         let crosvm_config = CrosvmConfig {
@@ -586,19 +687,24 @@ impl VirtualizationService {
             kernel,
             initrd,
             disks,
-            params: config.params.to_owned(),
-            protected: *is_protected,
+            params: split_kernel_params(config.params.as_deref().unwrap_or_default())
+                .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT)?,
+            protection,
             debug_config,
             memory_mib: config.memoryMib.try_into().ok().and_then(NonZeroU32::new),
+            balloon,
             cpus,
             host_cpu_topology,
+            cpu_quota_percent,
             console_out_fd,
             console_in_fd,
             log_fd,
             ramdump,
             indirect_files,
             platform_version: parse_platform_version_req(&config.platformVersion)?,
+            has_payload: is_app_config,
             detect_hangup: is_app_config,
+            boot_timeout,
             gdb_port,
             vfio_devices,
             dtbo,
@@ -606,6 +712,7 @@ impl VirtualizationService {
             display_config,
             input_device_options,
             hugepages: config.hugePages,
+            swap_policy,
             tap,
             virtio_snd_backend,
             console_input_device: config.consoleInputDevice.clone(),
@@ -768,6 +875,51 @@ fn format_as_encryptedstore(part: &mut dyn Write) -> std::io::Result<()> {
     part.flush()
 }
 
+fn max_partition_size() -> u64 {
+    system_properties::read(SYSPROP_MAX_PARTITION_SIZE)
+        .unwrap_or_else(|e| {
+            error!("Failed to read '{}': {:?}", SYSPROP_MAX_PARTITION_SIZE, e);
+            None
+        })
+        .and_then(|val| {
+            val.parse::<u64>()
+                .inspect_err(|_| {
+                    error!("Invalid value '{}' of property '{}'", val, SYSPROP_MAX_PARTITION_SIZE)
+                })
+                .ok()
+        })
+        .unwrap_or(DEFAULT_MAX_PARTITION_SIZE)
+}
+
+/// Validates the size requested for a writable partition, and returns it rounded up to the
+/// granularity required by crosvm.
+fn check_partition_size(
+    size_bytes: i64,
+    partition_type: PartitionType,
+    max_size_bytes: u64,
+) -> Result<u64> {
+    let min_size_bytes = match partition_type {
+        PartitionType::ANDROID_VM_INSTANCE => {
+            (ANDROID_VM_INSTANCE_MAGIC.len() + mem::size_of_val(&ANDROID_VM_INSTANCE_VERSION))
+                as u64
+        }
+        _ => 1,
+    };
+    let size_bytes: u64 =
+        size_bytes.try_into().with_context(|| format!("Invalid size: {}", size_bytes))?;
+    ensure!(
+        size_bytes >= min_size_bytes,
+        "Invalid size: {size_bytes} is smaller than the minimum of {min_size_bytes} bytes for \
+         {partition_type:?}"
+    );
+    let size_bytes = round_up(size_bytes, PARTITION_GRANULARITY_BYTES);
+    ensure!(
+        size_bytes <= max_size_bytes,
+        "Invalid size: {size_bytes} is larger than the maximum of {max_size_bytes} bytes"
+    );
+    Ok(size_bytes)
+}
+
 fn round_up(input: u64, granularity: u64) -> u64 {
     if granularity == 0 {
         return input;
@@ -802,7 +954,8 @@ fn to_input_device_option_from(input_device: &InputDevice) -> Result<InputDevice
 }
 /// Given the configuration for a disk image, assembles the `DiskFile` to pass to crosvm.
 ///
-/// This may involve assembling a composite disk from a set of partition images.
+/// This may involve assembling a composite disk from a set of partition images, or from a
+/// composite disk image assembled earlier and its components.
 fn assemble_disk_image(
     disk: &DiskImage,
     zero_filler_path: &Path,
@@ -810,12 +963,49 @@ fn assemble_disk_image(
     next_temporary_image_id: &mut u64,
     indirect_files: &mut Vec<File>,
 ) -> Result<DiskFile, Status> {
-    let image = if !disk.partitions.is_empty() {
+    let image = if !disk.compositeComponents.is_empty() {
+        if !disk.partitions.is_empty() {
+            warn!("DiskImage {:?} contains both composite components and partitions.", disk);
+            return Err(anyhow!("DiskImage contains both composite components and partitions"))
+                .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT);
+        }
+        let Some(image) = &disk.image else {
+            warn!("DiskImage {:?} contains composite components but no image.", disk);
+            return Err(anyhow!("DiskImage contains composite components but no image"))
+                .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT);
+        };
+        let spec = CompositeSpec::read(&clone_file(image)?)
+            .context("Invalid composite disk image")
+            .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT)?;
+        check_composite_components(disk, &spec)?;
+
+        let composite_image_filenames =
+            make_composite_image_filenames(temporary_directory, next_temporary_image_id);
+        let (image, component_files) = reuse_composite_image(
+            &spec,
+            &disk.compositeComponents,
+            &composite_image_filenames.composite,
+        )
+        .with_context(|| format!("Failed to reuse composite disk image with config {:?}", disk))
+        .with_log()
+        .or_service_specific_exception(-1)?;
+
+        // Pass the file descriptors for the various component files to crosvm when it is run.
+        indirect_files.extend(component_files);
+
+        image
+    } else if !disk.partitions.is_empty() {
         if disk.image.is_some() {
             warn!("DiskImage {:?} contains both image and partitions.", disk);
             return Err(anyhow!("DiskImage contains both image and partitions"))
                 .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT);
         }
+        if !disk.writable && disk.partitions.iter().any(|partition| partition.writable) {
+            warn!("DiskImage {:?} is read-only but contains writable partitions.", disk);
+            return Err(anyhow!("Read-only DiskImage contains writable partitions"))
+                .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT);
+        }
+        check_partition_labels(&disk.partitions)?;
 
         let composite_image_filenames =
             make_composite_image_filenames(temporary_directory, next_temporary_image_id);
@@ -826,9 +1016,18 @@ fn assemble_disk_image(
             &composite_image_filenames.header,
             &composite_image_filenames.footer,
         )
-        .with_context(|| format!("Failed to make composite disk image with config {:?}", disk))
-        .with_log()
-        .or_service_specific_exception(-1)?;
+        .or_else(|e| {
+            error!("Failed to make composite disk image with config {:?}: {:?}", disk, e);
+            match e {
+                CompositeImageError::PartitionTooSmall { .. }
+                | CompositeImageError::PartitionNotBlockMultiple { .. } => {
+                    Err(e).or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT)
+                }
+                CompositeImageError::Io(_) => Err(e)
+                    .context("Failed to make composite disk image")
+                    .or_service_specific_exception(-1),
+            }
+        })?;
 
         // Pass the file descriptors for the various partition files to crosvm when it
         // is run.
@@ -846,6 +1045,84 @@ fn assemble_disk_image(
     Ok(DiskFile { image, writable: disk.writable })
 }
 
+/// Checks that the components supplied with a composite disk image match the ones it lists.
+fn check_composite_components(disk: &DiskImage, spec: &CompositeSpec) -> binder::Result<()> {
+    if spec.component_count() != disk.compositeComponents.len() {
+        return Err(anyhow!(
+            "Composite disk image has {} components, but {} were supplied",
+            spec.component_count(),
+            disk.compositeComponents.len()
+        ))
+        .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT);
+    }
+    if !disk.writable && spec.has_writable_components() {
+        warn!("DiskImage {:?} is read-only but contains writable components.", disk);
+        return Err(anyhow!("Read-only DiskImage contains writable composite components"))
+            .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT);
+    }
+    Ok(())
+}
+
+/// Maximum length of a GPT partition name, in UTF-16 code units.
+const GPT_PARTITION_NAME_MAX_LEN: usize = 36;
+
+/// Checks that the labels of the partitions of a disk can be used as distinct GPT partition names.
+fn check_partition_labels(partitions: &[Partition]) -> binder::Result<()> {
+    let mut labels = HashSet::new();
+    for label in partitions.iter().map(|partition| partition.label.as_str()) {
+        if label.is_empty() {
+            return Err(anyhow!("Partition label is empty"))
+                .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT);
+        }
+        if label.encode_utf16().count() > GPT_PARTITION_NAME_MAX_LEN {
+            return Err(anyhow!(
+                "Partition label {label:?} is longer than {GPT_PARTITION_NAME_MAX_LEN} UTF-16 units"
+            ))
+            .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT);
+        }
+        if label.chars().any(char::is_control) {
+            return Err(anyhow!("Partition label contains non-printable characters: {label:?}"))
+                .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT);
+        }
+        if !labels.insert(label) {
+            return Err(anyhow!("Duplicate partition label {label:?}"))
+                .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT);
+        }
+    }
+    Ok(())
+}
+
+/// Splits a kernel command line into its parameters, which are separated by whitespace unless it
+/// is enclosed in double quotes. Fails if the command line contains other control characters (e.g.
+/// nul) or an unterminated quote.
+fn split_kernel_params(cmdline: &str) -> Result<Vec<String>> {
+    let mut params = Vec::new();
+    let mut param = String::new();
+    let mut quoted = false;
+    for c in cmdline.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                param.push(c);
+            }
+            c if c.is_ascii_whitespace() && !quoted => {
+                if !param.is_empty() {
+                    params.push(mem::take(&mut param));
+                }
+            }
+            c if c.is_control() => bail!("Kernel params contain a control character: {c:?}"),
+            c => param.push(c),
+        }
+    }
+    if quoted {
+        bail!("Kernel params contain an unterminated quote: {cmdline:?}");
+    }
+    if !param.is_empty() {
+        params.push(param);
+    }
+    Ok(params)
+}
+
 fn append_kernel_param(param: &str, vm_config: &mut VirtualMachineRawConfig) {
     if let Some(ref mut params) = vm_config.params {
         params.push(' ');
@@ -871,16 +1148,54 @@ fn extract_os_names_from_configs(config_glob_pattern: &str) -> Result<HashSet<St
     Ok(os_names)
 }
 
-fn get_supported_os_names() -> Result<HashSet<String>> {
-    if !cfg!(vendor_modules) {
-        return Ok(iter::once(MICRODROID_OS_NAME.to_owned()).collect());
+/// Adds the system images of a guest OS to the config of an app VM running it.
+type AddSystemImagesFn = fn(
+    config: &VirtualMachineAppConfig,
+    instance_file: File,
+    storage_image: Option<File>,
+    os_name: &str,
+    vm_config: &mut VirtualMachineRawConfig,
+) -> Result<()>;
+
+/// A family of guest OSes that app VMs can run, e.g. Microdroid and its GKI variants.
+struct GuestOsFamily {
+    /// Name of the OS, which the names of its variants start with.
+    name: &'static str,
+    add_system_images: AddSystemImagesFn,
+}
+
+/// The guest OS families that app VMs can run. Supporting another one only requires its VM config
+/// (and images) in the APEX, and an entry here.
+const GUEST_OS_FAMILIES: &[GuestOsFamily] =
+    &[GuestOsFamily { name: MICRODROID_OS_NAME, add_system_images: add_microdroid_system_images }];
+
+/// A guest OS that app VMs can run.
+struct GuestOs {
+    /// Path of the VM config of the OS.
+    config_path: PathBuf,
+    add_system_images: AddSystemImagesFn,
+}
+
+fn get_supported_oses() -> Result<HashMap<String, GuestOs>> {
+    let mut oses = HashMap::new();
+    for family in GUEST_OS_FAMILIES {
+        for os_name in get_supported_os_names(family.name)? {
+            // It is safe to construct a filename based on the os_name because it comes from the
+            // name of a config file in the APEX.
+            let config_path = PathBuf::from(format!("/apex/com.android.virt/etc/{}.json", os_name));
+            let os = GuestOs { config_path, add_system_images: family.add_system_images };
+            oses.insert(os_name, os);
+        }
     }
-
-    extract_os_names_from_configs("/apex/com.android.virt/etc/microdroid*.json")
+    Ok(oses)
 }
 
-fn is_valid_os(os_name: &str) -> bool {
-    SUPPORTED_OS_NAMES.contains(os_name)
+fn get_supported_os_names(family_name: &str) -> Result<HashSet<String>> {
+    if !cfg!(vendor_modules) {
+        return Ok(iter::once(family_name.to_owned()).collect());
+    }
+
+    extract_os_names_from_configs(&format!("/apex/com.android.virt/etc/{}*.json", family_name))
 }
 
 fn uses_gki_kernel(config: &VirtualMachineConfig) -> bool {
@@ -937,16 +1252,12 @@ fn load_app_config(
         bail!("'os' in payload config is deprecated");
     }
 
-    // For now, the only supported OS is Microdroid and Microdroid GKI
     let os_name = config.osName.as_str();
-    if !is_valid_os(os_name) {
+    let Some(os) = SUPPORTED_OSES.get(os_name) else {
         bail!("Unknown OS \"{}\"", os_name);
-    }
+    };
 
-    // It is safe to construct a filename based on the os_name because we've already checked that it
-    // is one of the allowed values.
-    let vm_config_path = PathBuf::from(format!("/apex/com.android.virt/etc/{}.json", os_name));
-    let vm_config_file = File::open(vm_config_path)?;
+    let vm_config_file = File::open(&os.config_path)?;
     let mut vm_config = VmConfig::load(&vm_config_file)?.to_parcelable()?;
 
     if let Some(custom_config) = &config.customConfig {
@@ -971,11 +1282,16 @@ fn load_app_config(
     vm_config.name.clone_from(&config.name);
     vm_config.protectedVm = config.protectedVm;
     vm_config.cpuTopology = config.cpuTopology;
+    if config.numCpus != 0 {
+        vm_config.numCpus = config.numCpus;
+    }
     vm_config.hugePages = config.hugePages || vm_payload_config.hugepages;
     vm_config.boostUclamp = config.boostUclamp;
+    vm_config.bootTimeoutMillis = config.bootTimeoutMillis;
 
-    // Microdroid takes additional init ramdisk & (optionally) storage image
-    add_microdroid_system_images(config, instance_file, storage_image, os_name, &mut vm_config)?;
+    // The OS takes additional system images, e.g. Microdroid takes an init ramdisk & (optionally)
+    // a storage image.
+    (os.add_system_images)(config, instance_file, storage_image, os_name, &mut vm_config)?;
 
     // Include Microdroid payload disk (contains apks, idsigs) in vm config
     add_microdroid_payload_images(
@@ -1025,11 +1341,30 @@ fn check_partitions_for_files(config: &VirtualMachineRawConfig) -> Result<()> {
 }
 
 fn load_vm_payload_config_from_file(apk_file: &File, config_path: &str) -> Result<VmPayloadConfig> {
+    check_config_path(config_path)?;
     let mut apk_zip = ZipArchive::new(apk_file)?;
-    let config_file = apk_zip.by_name(config_path)?;
+    let config_file = match apk_zip.by_name(config_path) {
+        Err(ZipError::FileNotFound) => bail!("No entry {config_path:?} in the APK"),
+        result => result?,
+    };
     Ok(serde_json::from_reader(config_file)?)
 }
 
+/// Checks that the path of a payload config is a normalized path relative to the root of the APK.
+fn check_config_path(config_path: &str) -> Result<()> {
+    ensure!(!config_path.is_empty(), "Payload config path is empty");
+    ensure!(!config_path.starts_with('/'), "Payload config path {config_path:?} is absolute");
+    ensure!(
+        !config_path.contains(['\\', '\0']),
+        "Payload config path {config_path:?} contains invalid characters"
+    );
+    ensure!(
+        config_path.split('/').all(|name| !matches!(name, "" | "." | "..")),
+        "Payload config path {config_path:?} isn't normalized"
+    );
+    Ok(())
+}
+
 fn create_vm_payload_config(
     payload_config: &VirtualMachinePayloadConfig,
 ) -> Result<VmPayloadConfig> {
@@ -1095,6 +1430,11 @@ fn check_permission(perm: &str) -> binder::Result<()> {
     }
 }
 
+/// Check whether the caller of the current Binder method is allowed to debug VMs
+fn check_debug_access() -> binder::Result<()> {
+    check_permission("android.permission.DEBUG_VIRTUAL_MACHINE")
+}
+
 /// Check whether the caller of the current Binder method is allowed to manage VMs
 fn check_manage_access() -> binder::Result<()> {
     check_permission("android.permission.MANAGE_VIRTUAL_MACHINE")
@@ -1150,6 +1490,13 @@ fn check_label_for_partition(partition: &Partition) -> Result<()> {
         .with_context(|| format!("Partition {} invalid", &partition.label))
 }
 
+fn check_label_for_composite_components(disk: &DiskImage) -> Result<()> {
+    disk.compositeComponents.iter().try_for_each(|component| {
+        check_label_is_allowed(&getfilecon(component.as_ref())?)
+            .context("Composite image component invalid")
+    })
+}
+
 fn check_label_for_kernel_files(kernel: &Option<File>, initrd: &Option<File>) -> Result<()> {
     if let Some(f) = kernel {
         check_label_for_file(f, "kernel")?;
@@ -1190,6 +1537,15 @@ impl IVirtualMachine for VirtualMachine {
         Ok(get_state(&self.instance))
     }
 
+    fn getDeathReason(&self) -> binder::Result<DeathReason> {
+        // Don't check permission. The owner of the VM might have passed this binder object to
+        // others.
+        self.instance
+            .death_reason()
+            .ok_or_else(|| anyhow!("VM with CID {} is not dead", self.instance.cid))
+            .or_service_specific_exception(-1)
+    }
+
     fn registerCallback(
         &self,
         callback: &Strong<dyn IVirtualMachineCallback>,
@@ -1197,8 +1553,9 @@ impl IVirtualMachine for VirtualMachine {
         // Don't check permission. The owner of the VM might have passed this binder object to
         // others.
         //
-        // TODO: Should this give an error if the VM is already dead?
-        self.instance.callbacks.add(callback.clone());
+        // The callback is immediately notified of the events that it has missed, e.g. the death of
+        // the VM, so that it doesn't matter whether it is registered before or after `start`.
+        self.instance.callbacks.add(self.instance.cid, callback.clone());
         Ok(())
     }
 
@@ -1218,6 +1575,22 @@ impl IVirtualMachine for VirtualMachine {
             .or_service_specific_exception(-1)
     }
 
+    fn suspend(&self) -> binder::Result<()> {
+        self.instance
+            .suspend()
+            .with_context(|| format!("Error suspending VM with CID {}", self.instance.cid))
+            .with_log()
+            .or_service_specific_exception(-1)
+    }
+
+    fn resume(&self) -> binder::Result<()> {
+        self.instance
+            .resume()
+            .with_context(|| format!("Error resuming VM with CID {}", self.instance.cid))
+            .with_log()
+            .or_service_specific_exception(-1)
+    }
+
     fn onTrimMemory(&self, level: MemoryTrimLevel) -> binder::Result<()> {
         self.instance
             .trim_memory(level)
@@ -1226,19 +1599,67 @@ impl IVirtualMachine for VirtualMachine {
             .or_service_specific_exception(-1)
     }
 
-    fn connectVsock(&self, port: i32) -> binder::Result<ParcelFileDescriptor> {
-        if !matches!(&*self.instance.vm_state.lock().unwrap(), VmState::Running { .. }) {
-            return Err(anyhow!("VM is not running")).or_service_specific_exception(-1);
+    fn setMemoryBalloon(&self, size_mib: i64) -> binder::Result<()> {
+        if !self.instance.balloon {
+            return Err(anyhow!(
+                "VM with CID {} has no memory balloon, as {} isn't set",
+                self.instance.cid,
+                SYSPROP_MEMORY_RECLAIM_SUPPORTED
+            ))
+            .or_binder_exception(ExceptionCode::UNSUPPORTED_OPERATION);
+        }
+        let size_mib: u64 = size_mib
+            .try_into()
+            .with_context(|| format!("Invalid balloon size: {}", size_mib))
+            .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT)?;
+        let memory_mib = self.instance.guest_memory_mib();
+        if size_mib > memory_mib.into() {
+            return Err(anyhow!("Balloon size {size_mib} MiB exceeds VM memory {memory_mib} MiB"))
+                .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT);
         }
+        self.instance
+            .set_memory_balloon(size_mib)
+            .with_context(|| format!("Error setting balloon of VM with CID {}", self.instance.cid))
+            .with_log()
+            .or_service_specific_exception(-1)
+    }
+
+    fn getMemoryStats(&self) -> binder::Result<MemoryStats> {
+        // Don't check permission. The owner of the VM might have passed this binder object to
+        // others.
+        Ok(self.instance.memory_stats())
+    }
+
+    fn connectVsock(&self, port: i32) -> binder::Result<ParcelFileDescriptor> {
+        self.connectVsockWithTimeout(port, 0)
+    }
+
+    fn connectVsockWithTimeout(
+        &self,
+        port: i32,
+        timeout_ms: i32,
+    ) -> binder::Result<ParcelFileDescriptor> {
         let port = port as u32;
         if port < 1024 {
             return Err(anyhow!("Can't connect to privileged port {port}"))
                 .or_service_specific_exception(-1);
         }
-        let stream = VsockStream::connect_with_cid_port(self.instance.cid, port)
-            .context("Failed to connect")
-            .or_service_specific_exception(-1)?;
-        Ok(vsock_stream_to_pfd(stream))
+        let deadline = Instant::now() + get_vsock_connect_timeout(timeout_ms)?;
+        loop {
+            if !matches!(&*self.instance.vm_state.lock().unwrap(), VmState::Running { .. }) {
+                return Err(anyhow!("VM is not running")).or_service_specific_exception(-1);
+            }
+            match VsockStream::connect_with_cid_port(self.instance.cid, port) {
+                Ok(stream) => return Ok(vsock_stream_to_pfd(stream)),
+                Err(_) if Instant::now() < deadline => {
+                    // The guest may not be listening yet.
+                    thread::sleep(VSOCK_CONNECT_RETRY_INTERVAL.min(deadline - Instant::now()));
+                }
+                Err(e) => {
+                    return Err(e).context("Failed to connect").or_service_specific_exception(-1)
+                }
+            }
+        }
     }
 }
 
@@ -1253,14 +1674,48 @@ impl Drop for VirtualMachine {
 
 /// A set of Binders to be called back in response to various events on the VM, such as when it
 /// dies.
+///
+/// The lifecycle events notified so far are recorded, so that they can be replayed to callbacks
+/// which are added late, e.g. after the payload has started.
+#[derive(Debug, Default)]
+pub struct VirtualMachineCallbacks(Mutex<CallbacksState>);
+
 #[derive(Debug, Default)]
-pub struct VirtualMachineCallbacks(Mutex<Vec<Strong<dyn IVirtualMachineCallback>>>);
+struct CallbacksState {
+    callbacks: Vec<Strong<dyn IVirtualMachineCallback>>,
+    payload_started: bool,
+    payload_ready: bool,
+    /// The exit code of the payload, if it has finished.
+    payload_finished: Option<i32>,
+    /// Why the VM died and the exit code of its payload, if it has died.
+    died: Option<(DeathReason, i32)>,
+}
+
+impl CallbacksState {
+    /// Notifies a new callback of the lifecycle events that it has missed.
+    fn replay(&self, cid: Cid, callback: &dyn IVirtualMachineCallback) -> binder::Result<()> {
+        if self.payload_started {
+            callback.onPayloadStarted(cid as i32)?;
+        }
+        if self.payload_ready {
+            callback.onPayloadReady(cid as i32)?;
+        }
+        if let Some(exit_code) = self.payload_finished {
+            callback.onPayloadFinished(cid as i32, exit_code)?;
+        }
+        if let Some((reason, exit_code)) = self.died {
+            callback.onDied(cid as i32, reason, exit_code)?;
+        }
+        Ok(())
+    }
+}
 
 impl VirtualMachineCallbacks {
     /// Call all registered callbacks to notify that the payload has started.
     pub fn notify_payload_started(&self, cid: Cid) {
-        let callbacks = &*self.0.lock().unwrap();
-        for callback in callbacks {
+        let state = &mut *self.0.lock().unwrap();
+        state.payload_started = true;
+        for callback in &state.callbacks {
             if let Err(e) = callback.onPayloadStarted(cid as i32) {
                 error!("Error notifying payload start event from VM CID {}: {:?}", cid, e);
             }
@@ -1269,8 +1724,9 @@ impl VirtualMachineCallbacks {
 
     /// Call all registered callbacks to notify that the payload is ready to serve.
     pub fn notify_payload_ready(&self, cid: Cid) {
-        let callbacks = &*self.0.lock().unwrap();
-        for callback in callbacks {
+        let state = &mut *self.0.lock().unwrap();
+        state.payload_ready = true;
+        for callback in &state.callbacks {
             if let Err(e) = callback.onPayloadReady(cid as i32) {
                 error!("Error notifying payload ready event from VM CID {}: {:?}", cid, e);
             }
@@ -1279,8 +1735,9 @@ impl VirtualMachineCallbacks {
 
     /// Call all registered callbacks to notify that the payload has finished.
     pub fn notify_payload_finished(&self, cid: Cid, exit_code: i32) {
-        let callbacks = &*self.0.lock().unwrap();
-        for callback in callbacks {
+        let state = &mut *self.0.lock().unwrap();
+        state.payload_finished = Some(exit_code);
+        for callback in &state.callbacks {
             if let Err(e) = callback.onPayloadFinished(cid as i32, exit_code) {
                 error!("Error notifying payload finish event from VM CID {}: {:?}", cid, e);
             }
@@ -1289,8 +1746,8 @@ impl VirtualMachineCallbacks {
 
     /// Call all registered callbacks to say that the VM encountered an error.
     pub fn notify_error(&self, cid: Cid, error_code: ErrorCode, message: &str) {
-        let callbacks = &*self.0.lock().unwrap();
-        for callback in callbacks {
+        let state = &*self.0.lock().unwrap();
+        for callback in &state.callbacks {
             if let Err(e) = callback.onError(cid as i32, error_code, message) {
                 error!("Error notifying error event from VM CID {}: {:?}", cid, e);
             }
@@ -1298,18 +1755,24 @@ impl VirtualMachineCallbacks {
     }
 
     /// Call all registered callbacks to say that the VM has died.
-    pub fn callback_on_died(&self, cid: Cid, reason: DeathReason) {
-        let callbacks = &*self.0.lock().unwrap();
-        for callback in callbacks {
-            if let Err(e) = callback.onDied(cid as i32, reason) {
+    pub fn callback_on_died(&self, cid: Cid, reason: DeathReason, exit_code: i32) {
+        let state = &mut *self.0.lock().unwrap();
+        state.died = Some((reason, exit_code));
+        for callback in &state.callbacks {
+            if let Err(e) = callback.onDied(cid as i32, reason, exit_code) {
                 error!("Error notifying exit of VM CID {}: {:?}", cid, e);
             }
         }
     }
 
-    /// Add a new callback to the set.
-    fn add(&self, callback: Strong<dyn IVirtualMachineCallback>) {
-        self.0.lock().unwrap().push(callback);
+    /// Add a new callback to the set, first notifying it of the lifecycle events of the VM that
+    /// have already happened.
+    fn add(&self, cid: Cid, callback: Strong<dyn IVirtualMachineCallback>) {
+        let state = &mut *self.0.lock().unwrap();
+        if let Err(e) = state.replay(cid, &*callback) {
+            error!("Error replaying past events of VM CID {} to new callback: {:?}", cid, e);
+        }
+        state.callbacks.push(callback);
     }
 }
 
@@ -1350,6 +1813,7 @@ impl State {
 fn get_state(instance: &VmInstance) -> VirtualMachineState {
     match &*instance.vm_state.lock().unwrap() {
         VmState::NotStarted { .. } => VirtualMachineState::NOT_STARTED,
+        VmState::Running { suspended: true, .. } => VirtualMachineState::SUSPENDED,
         VmState::Running { .. } => match instance.payload_state() {
             PayloadState::Starting => VirtualMachineState::STARTING,
             PayloadState::Started => VirtualMachineState::STARTED,
@@ -1357,8 +1821,8 @@ fn get_state(instance: &VmInstance) -> VirtualMachineState {
             PayloadState::Finished => VirtualMachineState::FINISHED,
             PayloadState::Hangup => VirtualMachineState::DEAD,
         },
-        VmState::Dead => VirtualMachineState::DEAD,
-        VmState::Failed => VirtualMachineState::DEAD,
+        VmState::Dead { .. } => VirtualMachineState::DEAD,
+        VmState::Failed { .. } => VirtualMachineState::FAILED,
     }
 }
 
@@ -1403,6 +1867,98 @@ fn prepare_ramdump_file(temporary_directory: &Path) -> binder::Result<File> {
     Ok(ramdump)
 }
 
+fn get_protection(config: &VirtualMachineRawConfig) -> binder::Result<Protection> {
+    if !config.protectedVm {
+        if config.pvmfw.is_some() {
+            return Err(anyhow!("pvmfw can only be supplied for protected VMs"))
+                .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT);
+        }
+        return Ok(Protection::Unprotected);
+    }
+    let pvmfw = maybe_clone_file(&config.pvmfw)?;
+    // Like custom kernels, the firmware of a protected VM must come from a trusted source.
+    if let Some(pvmfw) = &pvmfw {
+        check_label_for_file(pvmfw, "pvmfw").or_service_specific_exception(-1)?;
+    }
+    Ok(Protection::Protected { pvmfw })
+}
+
+/// Returns the time within which the payload must be ready, or `None` if it isn't limited.
+///
+/// Only VMs with a payload, i.e. created from an app config, report that it is ready, so a timeout
+/// is rejected for other VMs.
+fn get_boot_timeout(
+    boot_timeout_millis: i64,
+    is_app_config: bool,
+) -> binder::Result<Option<Duration>> {
+    if boot_timeout_millis == 0 {
+        return Ok(None);
+    }
+    if !is_app_config {
+        return Err(anyhow!("A boot timeout is only supported for VMs with a payload"))
+            .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT);
+    }
+    match u64::try_from(boot_timeout_millis).map(Duration::from_millis) {
+        Ok(timeout) if timeout <= MAX_BOOT_TIMEOUT => Ok(Some(timeout)),
+        _ => Err(anyhow!("Invalid boot timeout: {boot_timeout_millis} ms"))
+            .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT),
+    }
+}
+
+/// Returns how long connectVsockWithTimeout may retry, which is bounded so that clients can't
+/// hold binder threads for long.
+fn get_vsock_connect_timeout(timeout_millis: i32) -> binder::Result<Duration> {
+    match u64::try_from(timeout_millis).map(Duration::from_millis) {
+        Ok(timeout) if timeout <= MAX_VSOCK_CONNECT_TIMEOUT => Ok(timeout),
+        _ => Err(anyhow!("Invalid vsock connection timeout: {timeout_millis} ms"))
+            .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT),
+    }
+}
+
+fn get_swap_policy(
+    config: &VirtualMachineRawConfig,
+    is_protected: bool,
+    temporary_directory: &Path,
+) -> binder::Result<SwapPolicy> {
+    if config.swapPolicy != SwapPolicyParcelable::OFF && is_protected {
+        return Err(anyhow!("Swapping the memory of a protected VM isn't supported"))
+            .or_binder_exception(ExceptionCode::UNSUPPORTED_OPERATION);
+    }
+    match config.swapPolicy {
+        SwapPolicyParcelable::OFF => Ok(SwapPolicy::Off),
+        SwapPolicyParcelable::FILE => {
+            prepare_swap_directory(temporary_directory).map(SwapPolicy::File)
+        }
+        policy => Err(anyhow!("Invalid swap policy {policy:?}"))
+            .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT),
+    }
+}
+
+fn prepare_swap_directory(temporary_directory: &Path) -> binder::Result<PathBuf> {
+    let fs_type = statfs(temporary_directory)
+        .context("Failed to get the filesystem of the temporary directory")
+        .with_log()
+        .or_service_specific_exception(-1)?
+        .filesystem_type();
+    if !can_host_swap_file(fs_type) {
+        return Err(anyhow!("Temporary directory can't host a swap file (filesystem {fs_type:?})"))
+            .or_binder_exception(ExceptionCode::UNSUPPORTED_OPERATION);
+    }
+    // crosvm creates an unnamed swap file in this directory, which is removed with the rest of
+    // the temporary directory when the VM dies.
+    let swap_directory = temporary_directory.join("swap");
+    fs::create_dir(&swap_directory)
+        .context("Failed to create swap directory")
+        .with_log()
+        .or_service_specific_exception(-1)?;
+    Ok(swap_directory)
+}
+
+/// Returns whether a swap file on the given filesystem would actually move memory out of RAM.
+fn can_host_swap_file(fs_type: FsType) -> bool {
+    fs_type != TMPFS_MAGIC && fs_type != RAMFS_MAGIC
+}
+
 fn is_protected(config: &VirtualMachineConfig) -> bool {
     match config {
         VirtualMachineConfig::RawConfig(config) => config.protectedVm,
@@ -1472,6 +2028,15 @@ fn check_no_devices(config: &VirtualMachineConfig) -> binder::Result<()> {
     Ok(())
 }
 
+fn check_no_display(config: &VirtualMachineConfig) -> binder::Result<()> {
+    let VirtualMachineConfig::RawConfig(config) = config else { return Ok(()) };
+    if config.displayConfig.is_some() || config.gpuConfig.is_some() {
+        return Err(anyhow!("paravirtualized devices feature is disabled, no display available"))
+            .or_binder_exception(ExceptionCode::UNSUPPORTED_OPERATION);
+    }
+    Ok(())
+}
+
 fn check_no_extra_apks(config: &VirtualMachineConfig) -> binder::Result<()> {
     let VirtualMachineConfig::AppConfig(config) = config else { return Ok(()) };
     let Payload::PayloadConfig(payload_config) = &config.payload else { return Ok(()) };
@@ -1492,6 +2057,9 @@ fn check_config_features(config: &VirtualMachineConfig) -> binder::Result<()> {
     if !cfg!(multi_tenant) {
         check_no_extra_apks(config)?;
     }
+    if !cfg!(paravirtualized_devices) {
+        check_no_display(config)?;
+    }
     Ok(())
 }
 
@@ -1600,6 +2168,7 @@ impl IVirtualMachineService for VirtualMachineService {
             info!("VM with CID {} finished payload", cid);
             vm.update_payload_state(PayloadState::Finished)
                 .or_binder_exception(ExceptionCode::ILLEGAL_STATE)?;
+            vm.set_payload_exit_code(exit_code);
             vm.callbacks.notify_payload_finished(cid, exit_code);
             Ok(())
         } else {
@@ -1716,6 +2285,9 @@ impl IAuthGraphKeyExchange for AuthGraphKeyExchangeProxy {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use android_system_virtualizationservice::aidl::android::system::virtualizationservice::IVirtualMachineCallback::{BnVirtualMachineCallback, NO_EXIT_CODE};
+    use android_system_virtualizationservice_internal::aidl::android::system::virtualizationservice_internal::IGlobalVmContext::{BnGlobalVmContext, IGlobalVmContext};
+    use std::os::unix::net::UnixListener;
 
     #[test]
     fn test_is_allowed_label_for_partition() -> Result<()> {
@@ -1755,6 +2327,36 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_create_or_update_idsig_file_garbage_apk() -> Result<()> {
+        let mut apk = tempfile::tempfile().unwrap();
+        apk.write_all(&[0xAB; 8192])?;
+        apk.rewind()?;
+        let idsig = tempfile::tempfile().unwrap();
+
+        let ret = create_or_update_idsig_file(
+            &ParcelFileDescriptor::new(apk),
+            &ParcelFileDescriptor::new(idsig),
+        );
+        assert!(ret.is_err(), "should fail");
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_or_update_idsig_file_read_only_idsig() -> Result<()> {
+        // Pick any APK
+        let apk = File::open("/system/priv-app/Shell/Shell.apk").unwrap();
+        let idsig_path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        let idsig = File::open(&idsig_path)?;
+
+        let ret = create_or_update_idsig_file(
+            &ParcelFileDescriptor::new(apk),
+            &ParcelFileDescriptor::new(idsig),
+        );
+        assert!(ret.is_err(), "should fail");
+        Ok(())
+    }
+
     #[test]
     fn test_create_or_update_idsig_dir_instead_of_file_for_apk() -> Result<()> {
         let tmp_dir = tempfile::TempDir::new().unwrap();
@@ -1788,6 +2390,29 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_create_idsig_matches_idsig_file() -> Result<()> {
+        // Pick any APK
+        let mut apk = File::open("/system/priv-app/Shell/Shell.apk").unwrap();
+        let mut idsig = tempfile::tempfile().unwrap();
+        create_or_update_idsig_file(
+            &ParcelFileDescriptor::new(apk.try_clone()?),
+            &ParcelFileDescriptor::new(idsig.try_clone()?),
+        )?;
+        idsig.rewind()?;
+
+        let sig_data = create_idsig(&mut apk, get_current_sdk()?)?;
+
+        let mut expected = Vec::new();
+        idsig.read_to_end(&mut expected)?;
+        assert!(expected == sig_data, "idsig differs from the idsig file");
+        let sig = V4Signature::from_idsig(Cursor::new(sig_data))?;
+        apk.rewind()?;
+        let (_, apk_digest) = get_apk_digest(&mut apk, get_current_sdk()?, false)?;
+        assert_eq!(sig.signing_info.apk_digest, apk_digest);
+        Ok(())
+    }
+
     #[test]
     fn test_create_or_update_idsig_does_not_update_if_already_valid() -> Result<()> {
         use std::io::Seek;
@@ -1814,6 +2439,40 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_create_or_update_idsig_updates_stale_idsig() -> Result<()> {
+        use std::io::Read;
+
+        // Pick any APK
+        let mut apk = File::open("/system/priv-app/Shell/Shell.apk").unwrap();
+        let mut idsig = tempfile::tempfile().unwrap();
+        create_or_update_idsig_file(
+            &ParcelFileDescriptor::new(apk.try_clone()?),
+            &ParcelFileDescriptor::new(idsig.try_clone()?),
+        )?;
+        apk.rewind()?;
+        idsig.rewind()?;
+
+        // Make an idsig of a different APK
+        let mut stale_sig = V4Signature::from_idsig(idsig.try_clone()?)?;
+        stale_sig.signing_info.apk_digest = vec![0; stale_sig.signing_info.apk_digest.len()].into();
+        let mut idsig_stale = tempfile::tempfile().unwrap();
+        stale_sig.write_into(&mut idsig_stale)?;
+
+        create_or_update_idsig_file(
+            &ParcelFileDescriptor::new(apk.try_clone()?),
+            &ParcelFileDescriptor::new(idsig_stale.try_clone()?),
+        )?;
+        idsig.rewind()?;
+        idsig_stale.rewind()?;
+
+        let (mut expected, mut updated) = (Vec::new(), Vec::new());
+        idsig.read_to_end(&mut expected)?;
+        idsig_stale.read_to_end(&mut updated)?;
+        assert!(expected == updated, "stale idsig was not updated");
+        Ok(())
+    }
+
     #[test]
     fn test_create_or_update_idsig_on_non_empty_file() -> Result<()> {
         use std::io::Read;
@@ -1848,6 +2507,231 @@ mod tests {
         }
         Ok(())
     }
+    #[test]
+    fn test_assemble_disk_image_rejects_writable_partition_in_read_only_disk() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let disk = DiskImage {
+            partitions: vec![
+                Partition { label: "system".to_owned(), writable: false, ..Default::default() },
+                Partition { label: "data".to_owned(), writable: true, ..Default::default() },
+            ],
+            writable: false,
+            ..Default::default()
+        };
+
+        let ret = assemble_disk_image(
+            &disk,
+            &tmp_dir.path().join("zero.img"),
+            tmp_dir.path(),
+            &mut 0,
+            &mut vec![],
+        );
+        assert_eq!(ret.err().map(|e| e.exception_code()), Some(ExceptionCode::ILLEGAL_ARGUMENT));
+    }
+
+    #[test]
+    fn test_assemble_disk_image_rejects_duplicate_partition_labels() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let disk = DiskImage {
+            partitions: vec![
+                Partition { label: "data".to_owned(), writable: true, ..Default::default() },
+                Partition { label: "data".to_owned(), writable: true, ..Default::default() },
+            ],
+            writable: true,
+            ..Default::default()
+        };
+
+        let ret = assemble_disk_image(
+            &disk,
+            &tmp_dir.path().join("zero.img"),
+            tmp_dir.path(),
+            &mut 0,
+            &mut vec![],
+        );
+        assert_eq!(ret.err().map(|e| e.exception_code()), Some(ExceptionCode::ILLEGAL_ARGUMENT));
+    }
+
+    #[test]
+    fn test_assemble_disk_image_rejects_composite_component_count_mismatch() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let image_path = tmp_dir.path().join("composite.img");
+        // A composite disk image with a single component.
+        fs::write(&image_path, b"composite_disk\x1d\x12\x12\x0a\x10/proc/self/fd/10")?;
+        let component = || -> Result<ParcelFileDescriptor> {
+            Ok(ParcelFileDescriptor::new(tempfile::tempfile()?))
+        };
+        let disk = DiskImage {
+            image: Some(ParcelFileDescriptor::new(File::open(&image_path)?)),
+            compositeComponents: vec![component()?, component()?],
+            writable: false,
+            ..Default::default()
+        };
+
+        let ret = assemble_disk_image(
+            &disk,
+            &tmp_dir.path().join("zero.img"),
+            tmp_dir.path(),
+            &mut 0,
+            &mut vec![],
+        );
+        assert_eq!(ret.err().map(|e| e.exception_code()), Some(ExceptionCode::ILLEGAL_ARGUMENT));
+        Ok(())
+    }
+
+    #[test]
+    fn test_assemble_disk_image_rejects_empty_partition() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let zero_filler_path = tmp_dir.path().join("zero.img");
+        fs::write(&zero_filler_path, [0; ZERO_FILLER_SIZE as usize])?;
+        let disk = DiskImage {
+            partitions: vec![Partition {
+                label: "empty".to_owned(),
+                image: Some(ParcelFileDescriptor::new(tempfile::tempfile()?)),
+                ..Default::default()
+            }],
+            writable: false,
+            ..Default::default()
+        };
+
+        let ret =
+            assemble_disk_image(&disk, &zero_filler_path, tmp_dir.path(), &mut 0, &mut vec![]);
+        let status = ret.err().expect("An empty partition should be rejected");
+        assert_eq!(status.exception_code(), ExceptionCode::ILLEGAL_ARGUMENT);
+        assert!(status.get_description().contains("too small"), "{status:?}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_partition_labels() {
+        let partitions = |labels: &[&str]| -> Vec<Partition> {
+            labels
+                .iter()
+                .map(|label| Partition { label: label.to_string(), ..Default::default() })
+                .collect()
+        };
+        assert!(check_partition_labels(&partitions(&["system", "vendor", "data"])).is_ok());
+        assert!(check_partition_labels(&partitions(&["système"])).is_ok());
+        assert!(check_partition_labels(&partitions(&[&"a".repeat(36)])).is_ok());
+        assert!(check_partition_labels(&partitions(&[&"a".repeat(37)])).is_err());
+        assert!(check_partition_labels(&partitions(&["system", ""])).is_err());
+        assert!(check_partition_labels(&partitions(&["sys\ntem"])).is_err());
+        assert!(check_partition_labels(&partitions(&["data", "system", "data"])).is_err());
+    }
+
+    #[test]
+    fn test_can_host_swap_file() {
+        use nix::sys::statfs::EXT4_SUPER_MAGIC;
+
+        assert!(can_host_swap_file(EXT4_SUPER_MAGIC));
+        assert!(!can_host_swap_file(TMPFS_MAGIC));
+        assert!(!can_host_swap_file(RAMFS_MAGIC));
+    }
+
+    #[test]
+    fn test_check_config_path() {
+        assert!(check_config_path("assets/vm_config.json").is_ok());
+        assert!(check_config_path("vm_config.json").is_ok());
+
+        for path in [
+            "",
+            "/assets/vm_config.json",
+            "../vm_config.json",
+            "assets/../../vm_config.json",
+            "assets/./vm_config.json",
+            "assets//vm_config.json",
+            "assets/",
+            "assets\\..\\vm_config.json",
+        ] {
+            assert!(check_config_path(path).is_err(), "{path:?} should be rejected");
+        }
+    }
+
+    #[test]
+    fn test_load_vm_payload_config_rejects_traversal() -> Result<()> {
+        let apk = tempfile::tempfile()?;
+        let err = load_vm_payload_config_from_file(&apk, "../assets/vm_config.json").unwrap_err();
+        assert!(err.to_string().contains("isn't normalized"), "Unexpected error: {err:?}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_protection_rejects_pvmfw_for_non_protected_vm() -> Result<()> {
+        let mut config = VirtualMachineRawConfig { protectedVm: false, ..Default::default() };
+        assert!(matches!(get_protection(&config), Ok(Protection::Unprotected)));
+
+        config.pvmfw = Some(ParcelFileDescriptor::new(tempfile::tempfile()?));
+        assert_eq!(
+            get_protection(&config).unwrap_err().exception_code(),
+            ExceptionCode::ILLEGAL_ARGUMENT
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_boot_timeout() {
+        let max_millis = i64::try_from(MAX_BOOT_TIMEOUT.as_millis()).unwrap();
+        assert_eq!(get_boot_timeout(0, true).unwrap(), None);
+        assert_eq!(get_boot_timeout(0, false).unwrap(), None);
+        assert_eq!(get_boot_timeout(1500, true).unwrap(), Some(Duration::from_millis(1500)));
+        assert_eq!(get_boot_timeout(max_millis, true).unwrap(), Some(MAX_BOOT_TIMEOUT));
+        for (millis, is_app_config) in
+            [(-1, true), (max_millis + 1, true), (i64::MAX, true), (1500, false)]
+        {
+            assert_eq!(
+                get_boot_timeout(millis, is_app_config).unwrap_err().exception_code(),
+                ExceptionCode::ILLEGAL_ARGUMENT,
+                "{millis} ms, app config: {is_app_config}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_vsock_connect_timeout() {
+        let max_millis = i32::try_from(MAX_VSOCK_CONNECT_TIMEOUT.as_millis()).unwrap();
+        assert_eq!(get_vsock_connect_timeout(0).unwrap(), Duration::ZERO);
+        assert_eq!(get_vsock_connect_timeout(1500).unwrap(), Duration::from_millis(1500));
+        assert_eq!(get_vsock_connect_timeout(max_millis).unwrap(), MAX_VSOCK_CONNECT_TIMEOUT);
+        for millis in [-1, max_millis + 1, i32::MAX] {
+            assert_eq!(
+                get_vsock_connect_timeout(millis).unwrap_err().exception_code(),
+                ExceptionCode::ILLEGAL_ARGUMENT,
+                "{millis} ms"
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_swap_policy() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let get = |swap_policy, is_protected| {
+            let config = VirtualMachineRawConfig { swapPolicy: swap_policy, ..Default::default() };
+            get_swap_policy(&config, is_protected, tmp_dir.path())
+        };
+        let exception = |ret: binder::Result<SwapPolicy>| ret.err().map(|e| e.exception_code());
+
+        assert!(matches!(get(SwapPolicyParcelable::OFF, true), Ok(SwapPolicy::Off)));
+        assert_eq!(
+            exception(get(SwapPolicyParcelable::FILE, true)),
+            Some(ExceptionCode::UNSUPPORTED_OPERATION)
+        );
+        assert_eq!(
+            exception(get(SwapPolicyParcelable(42), false)),
+            Some(ExceptionCode::ILLEGAL_ARGUMENT)
+        );
+    }
+
+    #[test]
+    fn test_split_kernel_params() -> Result<()> {
+        assert_eq!(split_kernel_params("")?, Vec::<String>::new());
+        assert_eq!(split_kernel_params("  foo=1 \tbar  ")?, ["foo=1", "bar"]);
+        assert_eq!(split_kernel_params("foo=\"a b\" baz")?, ["foo=\"a b\"", "baz"]);
+        assert_eq!(split_kernel_params("-- init=/bin/sh")?, ["--", "init=/bin/sh"]);
+        assert!(split_kernel_params("foo=\"a b").is_err());
+        assert!(split_kernel_params("foo=1\0bar").is_err());
+        assert!(split_kernel_params("foo=\x1b[0m").is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_append_kernel_param_first_param() {
         let mut vm_config = VirtualMachineRawConfig { ..Default::default() };
@@ -1898,6 +2782,14 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_supported_oses_include_microdroid() -> Result<()> {
+        let oses = get_supported_oses()?;
+        let os = oses.get(MICRODROID_OS_NAME).context("Microdroid isn't supported")?;
+        assert_eq!(os.config_path, Path::new("/apex/com.android.virt/etc/microdroid.json"));
+        Ok(())
+    }
+
     #[test]
     fn test_extract_os_name_from_configs() -> Result<()> {
         let tmp_dir = tempfile::TempDir::new()?;
@@ -1928,4 +2820,196 @@ mod tests {
         }
         Ok(())
     }
+
+    #[test]
+    fn test_check_partition_size() {
+        const MAX: u64 = 1 << 20;
+        let raw = PartitionType::RAW;
+        let instance = PartitionType::ANDROID_VM_INSTANCE;
+
+        assert!(check_partition_size(0, raw, MAX).is_err());
+        assert!(check_partition_size(-1, raw, MAX).is_err());
+        assert!(check_partition_size(i64::MIN, raw, MAX).is_err());
+        assert!(check_partition_size(MAX as i64 + 1, raw, MAX).is_err());
+        assert!(check_partition_size(i64::MAX, raw, MAX).is_err());
+        assert!(check_partition_size(1, instance, MAX).is_err());
+
+        assert_eq!(check_partition_size(1, raw, MAX).unwrap(), PARTITION_GRANULARITY_BYTES);
+        assert_eq!(check_partition_size(4097, raw, MAX).unwrap(), 8192);
+        assert_eq!(check_partition_size(MAX as i64, raw, MAX).unwrap(), MAX);
+        assert_eq!(check_partition_size(21, instance, MAX).unwrap(), PARTITION_GRANULARITY_BYTES);
+    }
+
+    /// Records the events it is notified of.
+    #[derive(Clone, Default)]
+    struct RecordingCallback(Arc<Mutex<Vec<String>>>);
+
+    impl Interface for RecordingCallback {}
+
+    impl IVirtualMachineCallback for RecordingCallback {
+        fn onPayloadStarted(&self, cid: i32) -> binder::Result<()> {
+            self.0.lock().unwrap().push(format!("started {cid}"));
+            Ok(())
+        }
+        fn onPayloadReady(&self, cid: i32) -> binder::Result<()> {
+            self.0.lock().unwrap().push(format!("ready {cid}"));
+            Ok(())
+        }
+        fn onPayloadFinished(&self, cid: i32, exit_code: i32) -> binder::Result<()> {
+            self.0.lock().unwrap().push(format!("finished {cid} {exit_code}"));
+            Ok(())
+        }
+        fn onError(&self, cid: i32, _: ErrorCode, message: &str) -> binder::Result<()> {
+            self.0.lock().unwrap().push(format!("error {cid} {message}"));
+            Ok(())
+        }
+        fn onDied(&self, cid: i32, reason: DeathReason, exit_code: i32) -> binder::Result<()> {
+            self.0.lock().unwrap().push(format!("died {cid} {reason:?} {exit_code}"));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_late_callbacks_are_notified_of_missed_events() {
+        let callbacks = VirtualMachineCallbacks::default();
+        let cid = 42;
+        let early = RecordingCallback::default();
+        callbacks.add(cid, BnVirtualMachineCallback::new_binder(early.clone(), Default::default()));
+
+        callbacks.notify_payload_started(cid);
+        callbacks.notify_error(cid, ErrorCode::UNKNOWN, "oops");
+        callbacks.notify_payload_finished(cid, 3);
+        callbacks.callback_on_died(cid, DeathReason::SHUTDOWN, 3);
+
+        let late = RecordingCallback::default();
+        callbacks.add(cid, BnVirtualMachineCallback::new_binder(late.clone(), Default::default()));
+
+        assert_eq!(
+            *early.0.lock().unwrap(),
+            ["started 42", "error 42 oops", "finished 42 3", "died 42 SHUTDOWN 3"]
+        );
+        // Errors aren't replayed, nor events which didn't happen.
+        assert_eq!(*late.0.lock().unwrap(), ["started 42", "finished 42 3", "died 42 SHUTDOWN 3"]);
+    }
+
+    /// Fake of the context that virtualizationservice allocates for a VM.
+    struct FakeGlobalVmContext;
+
+    impl Interface for FakeGlobalVmContext {}
+
+    impl IGlobalVmContext for FakeGlobalVmContext {
+        fn getCid(&self) -> binder::Result<i32> {
+            Ok(FAKE_CID as i32)
+        }
+        fn getTemporaryDirectory(&self) -> binder::Result<String> {
+            Err(Status::new_exception(ExceptionCode::UNSUPPORTED_OPERATION, None))
+        }
+        fn setCrosvmArgs(&self, _: &[String]) -> binder::Result<()> {
+            Ok(())
+        }
+        fn createCpuCgroup(&self, _: i32) -> binder::Result<String> {
+            Err(Status::new_exception(ExceptionCode::UNSUPPORTED_OPERATION, None))
+        }
+    }
+
+    const FAKE_CID: Cid = 2048;
+
+    /// Stands in for crosvm, exiting with the status that crosvm returns when it fails to start.
+    const FAILING_CROSVM_PATH: &str = "/system/bin/false";
+
+    fn raw_vm_config(temp_dir: &Path) -> Result<CrosvmConfig> {
+        Ok(CrosvmConfig {
+            cid: FAKE_CID,
+            name: "test".to_owned(),
+            bootloader: None,
+            kernel: Some(File::create(temp_dir.join("kernel"))?),
+            initrd: None,
+            disks: vec![],
+            params: vec![],
+            protection: Protection::Unprotected,
+            debug_config: DebugConfig::default(),
+            memory_mib: None,
+            balloon: false,
+            cpus: None,
+            host_cpu_topology: false,
+            cpu_quota_percent: None,
+            console_out_fd: None,
+            console_in_fd: None,
+            log_fd: None,
+            ramdump: None,
+            indirect_files: vec![],
+            platform_version: VersionReq::parse("~1.0")?,
+            has_payload: false,
+            detect_hangup: false,
+            boot_timeout: None,
+            gdb_port: None,
+            vfio_devices: vec![],
+            dtbo: None,
+            device_tree_overlay: None,
+            display_config: None,
+            input_device_options: vec![],
+            hugepages: false,
+            swap_policy: SwapPolicy::Off,
+            tap: None,
+            virtio_snd_backend: None,
+            console_input_device: None,
+            boost_uclamp: false,
+            gpu_config: None,
+        })
+    }
+
+    /// Creates a VM run by `FAILING_CROSVM_PATH`, with its temporary files in `temp_dir`.
+    fn vm_with_failing_crosvm(temp_dir: &Path) -> Result<Arc<VmInstance>> {
+        let global_context =
+            BnGlobalVmContext::new_binder(FakeGlobalVmContext, BinderFeatures::default());
+        let socket = UnixListener::bind(temp_dir.join("vm_service.sock"))?;
+        let vm_server = RpcServer::new_bound_socket(global_context.as_binder(), socket.into())?;
+        Ok(Arc::new(VmInstance::with_crosvm_path(
+            raw_vm_config(temp_dir)?,
+            PathBuf::from(FAILING_CROSVM_PATH),
+            temp_dir.to_owned(),
+            0,
+            0,
+            VmContext::new(global_context, vm_server),
+        )?))
+    }
+
+    #[test]
+    fn test_vm_whose_crosvm_fails_to_start_ends_failed() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let instance = vm_with_failing_crosvm(temp_dir.path())?;
+        let callback = RecordingCallback::default();
+        instance.callbacks.add(
+            FAKE_CID,
+            BnVirtualMachineCallback::new_binder(callback.clone(), Default::default()),
+        );
+
+        assert!(instance.start().is_err());
+
+        // The death callback is notified by the thread monitoring crosvm, once it has exited.
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while callback.0.lock().unwrap().is_empty() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(get_state(&instance), VirtualMachineState::FAILED);
+        assert_eq!(instance.death_reason(), Some(DeathReason::START_FAILED));
+        // Give a duplicate notification the time to arrive.
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(
+            *callback.0.lock().unwrap(),
+            [format!("died {FAKE_CID} START_FAILED {NO_EXIT_CODE}")]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_memory_balloon_without_balloon_is_unsupported() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let vm = VirtualMachine { instance: vm_with_failing_crosvm(temp_dir.path())? };
+        assert!(!vm.instance.balloon);
+
+        let status = vm.setMemoryBalloon(1).unwrap_err();
+        assert_eq!(status.exception_code(), ExceptionCode::UNSUPPORTED_OPERATION);
+        Ok(())
+    }
 }
@@ -0,0 +1,68 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Limits on the host CPU time used by crosvm, enforced with a cgroup of the cpu controller.
+//!
+//! This process runs with the UID of its client, so the cgroup is created (and removed) by
+//! virtualizationservice, which only lets us move crosvm into it.
+
+use android_system_virtualizationservice_internal::aidl::android::system::virtualizationservice_internal::IGlobalVmContext::IGlobalVmContext;
+use anyhow::{Context, Result};
+use log::info;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::num::NonZeroU8;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+/// Maximum value of a CPU quota, in percent.
+pub const MAX_CPU_QUOTA_PERCENT: u8 = 100;
+
+/// Handle to the cgroup limiting the CPU time of the crosvm process of a VM. The cgroup itself is
+/// removed by virtualizationservice when the VM context is dropped.
+#[derive(Debug)]
+pub struct CpuCgroup {
+    procs: File,
+}
+
+impl CpuCgroup {
+    /// Asks virtualizationservice for a cgroup which limits the processes it contains to
+    /// `quota_percent` of the CPU time of the host (across all its CPUs).
+    pub fn create(global_context: &dyn IGlobalVmContext, quota_percent: NonZeroU8) -> Result<Self> {
+        let procs_path = global_context
+            .createCpuCgroup(quota_percent.get().into())
+            .context("Failed to create cpu cgroup")?;
+        let procs = OpenOptions::new()
+            .write(true)
+            .open(&procs_path)
+            .with_context(|| format!("Failed to open {procs_path}"))?;
+        info!("Limiting CPU time of crosvm to {quota_percent}% with {procs_path}");
+        Ok(Self { procs })
+    }
+
+    /// Makes the process spawned by `command` join the cgroup before it executes, so that it is
+    /// never scheduled without the limits applied.
+    pub fn add_on_spawn(&self, command: &mut Command) -> Result<()> {
+        let procs = self.procs.try_clone().context("Failed to clone cgroup.procs FD")?;
+        // SAFETY: The closure doesn't allocate memory nor access any resource of the parent
+        // process other than the file, which we own.
+        unsafe {
+            command.pre_exec(move || {
+                // Writing 0 moves the process doing the write.
+                (&procs).write_all(b"0")
+            });
+        }
+        Ok(())
+    }
+}
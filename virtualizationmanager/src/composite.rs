@@ -15,14 +15,45 @@
 //! Functions for creating a composite disk image.
 
 use android_system_virtualizationservice::aidl::android::system::virtualizationservice::Partition::Partition;
-use anyhow::{anyhow, Context, Error};
+use anyhow::{anyhow, ensure, Context, Error};
+use binder::ParcelFileDescriptor;
+use cdisk_spec_proto::cdisk_spec::CompositeDisk;
+use cdisk_spec_proto::cdisk_spec::ReadWriteCapability::READ_ONLY;
 use disk::{
     create_composite_disk, create_disk_file, ImagePartitionType, PartitionInfo, MAX_NESTING_DEPTH,
 };
+use protobuf::Message;
 use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::FileExt;
 use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
 
+/// crosvm requires writable partitions to be a multiple of 4KiB, read-only ones are padded.
+pub const PARTITION_GRANULARITY_BYTES: u64 = 4096;
+
+/// Errors returned by [`make_composite_image`].
+#[derive(Debug, thiserror::Error)]
+pub enum CompositeImageError {
+    /// A partition image is empty.
+    #[error("Partition {label:?} is too small: {size} bytes")]
+    PartitionTooSmall { label: String, size: u64 },
+    /// The size of a writable partition image isn't a multiple of
+    /// [`PARTITION_GRANULARITY_BYTES`].
+    #[error("Partition {label:?} size {size} isn't a multiple of {PARTITION_GRANULARITY_BYTES}")]
+    PartitionNotBlockMultiple { label: String, size: u64 },
+    /// Any other failure, e.g. to access the partition images or to write the composite image.
+    #[error("{0:#}")]
+    Io(#[from] Error),
+}
+
+/// Magic at the start of the specification of a composite disk image.
+const CDISK_MAGIC: &[u8] = b"composite_disk\x1d";
+
+/// Maximum size of the specification of a composite disk image that we read. It only lists the
+/// components, so it is typically much smaller.
+const MAX_CDISK_SPEC_SIZE: u64 = 1024 * 1024;
+
 /// Constructs a composite disk image for the given list of partitions, and opens it ready to use.
 ///
 /// Returns the composite disk image file, and a list of files whose file descriptors must be passed
@@ -34,7 +65,7 @@ pub fn make_composite_image(
     output_path: &Path,
     header_path: &Path,
     footer_path: &Path,
-) -> Result<(File, Vec<File>), Error> {
+) -> Result<(File, Vec<File>), CompositeImageError> {
     let (partitions, mut files) = convert_partitions(partitions)?;
 
     let mut composite_image = OpenOptions::new()
@@ -63,7 +94,8 @@ pub fn make_composite_image(
         &fd_path_for_file(&footer_file),
         &mut footer_file,
         &mut composite_image,
-    )?;
+    )
+    .map_err(Error::from)?;
 
     // Re-open the composite image as read-only.
     let composite_image = File::open(output_path)
@@ -76,10 +108,100 @@ pub fn make_composite_image(
     Ok((composite_image, files))
 }
 
+/// The specification of a composite disk image, such as one made by [`make_composite_image`].
+#[derive(Debug)]
+pub struct CompositeSpec(CompositeDisk);
+
+impl CompositeSpec {
+    /// Reads the specification of the composite disk image in the given file.
+    pub fn read(image: &File) -> Result<Self, Error> {
+        let len = image.metadata().context("Failed to get composite image size")?.len();
+        ensure!(len <= MAX_CDISK_SPEC_SIZE, "Composite image is too large ({len} bytes)");
+        let mut spec = vec![0; len.try_into()?];
+        image.read_exact_at(&mut spec, 0).context("Failed to read composite image")?;
+        Self::parse(&spec)
+    }
+
+    fn parse(spec: &[u8]) -> Result<Self, Error> {
+        let spec = spec.strip_prefix(CDISK_MAGIC).context("Not a composite image")?;
+        let spec = CompositeDisk::parse_from_bytes(spec).context("Invalid composite image")?;
+        Ok(Self(spec))
+    }
+
+    /// Returns the number of components listed by the image, including the header and footer.
+    pub fn component_count(&self) -> usize {
+        self.0.component_disks.len()
+    }
+
+    /// Returns whether any component of the image may be writable, i.e. isn't read-only.
+    pub fn has_writable_components(&self) -> bool {
+        self.0
+            .component_disks
+            .iter()
+            .any(|component| component.read_write_capability.enum_value() != Ok(READ_ONLY))
+    }
+
+    /// Encodes the specification with the paths of its components replaced. Any field that isn't
+    /// known to us is kept as it is.
+    fn encode_with_paths(&self, paths: &[PathBuf]) -> Result<Vec<u8>, Error> {
+        ensure!(
+            paths.len() == self.0.component_disks.len(),
+            "Composite image has {} components, got {}",
+            self.0.component_disks.len(),
+            paths.len()
+        );
+        let mut spec = self.0.clone();
+        for (component, path) in spec.component_disks.iter_mut().zip(paths) {
+            component.file_path = path.to_string_lossy().into_owned();
+        }
+        let mut encoded = CDISK_MAGIC.to_vec();
+        spec.write_to_vec(&mut encoded).context("Failed to encode composite image")?;
+        Ok(encoded)
+    }
+}
+
+/// Makes a composite disk image from the specification of one assembled earlier, with its
+/// components replaced by the given files, in the order in which the specification lists them.
+///
+/// Like [`make_composite_image`], returns the new composite disk image file, and the list of files
+/// whose file descriptors must be passed to any process which wants to use it.
+pub fn reuse_composite_image(
+    spec: &CompositeSpec,
+    components: &[ParcelFileDescriptor],
+    output_path: &Path,
+) -> Result<(File, Vec<File>), Error> {
+    let files = components
+        .iter()
+        .map(|component| {
+            Ok(component
+                .as_ref()
+                .try_clone()
+                .context("Failed to clone composite image component file descriptor")?
+                .into())
+        })
+        .collect::<Result<Vec<File>, Error>>()?;
+    let paths: Vec<_> = files.iter().map(fd_path_for_file).collect();
+    let spec = spec.encode_with_paths(&paths)?;
+
+    OpenOptions::new()
+        .create_new(true)
+        .write(true)
+        .open(output_path)
+        .and_then(|mut file| file.write_all(&spec))
+        .with_context(|| format!("Failed to write composite image {:?}", output_path))?;
+    // Re-open the composite image as read-only.
+    let composite_image = File::open(output_path)
+        .with_context(|| format!("Failed to open composite image {:?}", output_path))?;
+
+    Ok((composite_image, files))
+}
+
 /// Given the AIDL config containing a list of partitions, with a [`ParcelFileDescriptor`] for each
 /// partition, returns the corresponding list of PartitionInfo and the list of files whose file
 /// descriptors must be passed to any process using the composite image.
-fn convert_partitions(partitions: &[Partition]) -> Result<(Vec<PartitionInfo>, Vec<File>), Error> {
+fn convert_partitions(
+    partitions: &[Partition],
+) -> Result<(Vec<PartitionInfo>, Vec<File>), CompositeImageError> {
     // File descriptors to pass to child process.
     let mut files = vec![];
 
@@ -97,6 +219,7 @@ fn convert_partitions(partitions: &[Partition]) -> Result<(Vec<PartitionInfo>, V
                 .into();
             let path = fd_path_for_file(&file);
             let size = get_partition_size(&file, &path)?;
+            check_partition_size(partition, size)?;
             files.push(file);
 
             Ok(PartitionInfo {
@@ -107,11 +230,23 @@ fn convert_partitions(partitions: &[Partition]) -> Result<(Vec<PartitionInfo>, V
                 size,
             })
         })
-        .collect::<Result<_, Error>>()?;
+        .collect::<Result<_, CompositeImageError>>()?;
 
     Ok((partitions, files))
 }
 
+/// Checks that a partition image of the given size can be part of a composite image.
+fn check_partition_size(partition: &Partition, size: u64) -> Result<(), CompositeImageError> {
+    let label = partition.label.to_owned();
+    if size == 0 {
+        Err(CompositeImageError::PartitionTooSmall { label, size })
+    } else if partition.writable && size % PARTITION_GRANULARITY_BYTES != 0 {
+        Err(CompositeImageError::PartitionNotBlockMultiple { label, size })
+    } else {
+        Ok(())
+    }
+}
+
 fn fd_path_for_file(file: &File) -> PathBuf {
     let fd = file.as_raw_fd();
     format!("/proc/self/fd/{}", fd).into()
@@ -132,3 +267,83 @@ fn get_partition_size(partition: &File, path: &Path) -> Result<u64, Error> {
     .map_err(|e| anyhow!("Failed to open partition image: {}", e))?
     .get_len()?)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cdisk_spec_proto::cdisk_spec::ComponentDisk;
+    use cdisk_spec_proto::cdisk_spec::ReadWriteCapability::READ_WRITE;
+
+    fn component(path: &str, offset: u64, writable: bool) -> ComponentDisk {
+        ComponentDisk {
+            file_path: path.to_owned(),
+            offset,
+            read_write_capability: if writable { READ_WRITE } else { READ_ONLY }.into(),
+            ..Default::default()
+        }
+    }
+
+    fn encode_spec(components: Vec<ComponentDisk>) -> Vec<u8> {
+        let spec = CompositeDisk {
+            version: 1,
+            component_disks: components,
+            length: 0x1_0000_0000,
+            ..Default::default()
+        };
+        let mut encoded = CDISK_MAGIC.to_vec();
+        spec.write_to_vec(&mut encoded).unwrap();
+        encoded
+    }
+
+    #[test]
+    fn test_check_partition_size() {
+        let partition =
+            |writable| Partition { label: "p".to_owned(), writable, ..Default::default() };
+        assert!(check_partition_size(&partition(true), PARTITION_GRANULARITY_BYTES).is_ok());
+        assert!(check_partition_size(&partition(false), 1).is_ok());
+        assert!(matches!(
+            check_partition_size(&partition(false), 0),
+            Err(CompositeImageError::PartitionTooSmall { size: 0, .. })
+        ));
+        assert!(matches!(
+            check_partition_size(&partition(true), PARTITION_GRANULARITY_BYTES + 1),
+            Err(CompositeImageError::PartitionNotBlockMultiple { .. })
+        ));
+    }
+
+    #[test]
+    fn test_composite_spec_paths_are_replaced() -> Result<(), Error> {
+        let spec = encode_spec(vec![
+            component("/proc/self/fd/10", 0, false),
+            component("/proc/self/fd/11", 4096, true),
+        ]);
+        let parsed = CompositeSpec::parse(&spec)?;
+        assert_eq!(parsed.component_count(), 2);
+        assert!(parsed.has_writable_components());
+
+        let paths = [PathBuf::from("/proc/self/fd/20"), PathBuf::from("/proc/self/fd/21")];
+        let expected = encode_spec(vec![
+            component("/proc/self/fd/20", 0, false),
+            component("/proc/self/fd/21", 4096, true),
+        ]);
+        assert_eq!(parsed.encode_with_paths(&paths)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_composite_spec_component_count_must_match() -> Result<(), Error> {
+        let spec = encode_spec(vec![component("/proc/self/fd/10", 0, false)]);
+        let parsed = CompositeSpec::parse(&spec)?;
+        assert!(!parsed.has_writable_components());
+        assert!(parsed.encode_with_paths(&[]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_composite_spec() {
+        assert!(CompositeSpec::parse(b"not a composite disk").is_err());
+        let mut truncated = encode_spec(vec![component("/proc/self/fd/10", 0, false)]);
+        truncated.truncate(truncated.len() - 8);
+        assert!(CompositeSpec::parse(&truncated).is_err());
+    }
+}
@@ -16,13 +16,14 @@
 
 use crate::aidl::{remove_temporary_files, Cid, GLOBAL_SERVICE, VirtualMachineCallbacks};
 use crate::atom::{get_num_cpus, write_vm_exited_stats_sync};
+use crate::cgroup::CpuCgroup;
 use crate::debug_config::DebugConfig;
 use anyhow::{anyhow, bail, Context, Error, Result};
 use binder::ParcelFileDescriptor;
 use command_fds::CommandFdExt;
 use lazy_static::lazy_static;
 use libc::{sysconf, _SC_CLK_TCK};
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use semver::{Version, VersionReq};
 use nix::{fcntl::OFlag, unistd::pipe2, unistd::Uid, unistd::User};
 use regex::{Captures, Regex};
@@ -30,15 +31,17 @@ use rustutils::system_properties;
 use shared_child::SharedChild;
 use std::borrow::Cow;
 use std::cmp::max;
+use std::collections::VecDeque;
 use std::fmt;
 use std::fs::{read_to_string, File};
-use std::io::{self, Read};
+use std::io::{self, BufRead, BufReader, Read};
 use std::mem;
-use std::num::{NonZeroU16, NonZeroU32};
+use std::num::{NonZeroU16, NonZeroU32, NonZeroU8};
 use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
 use std::os::unix::process::ExitStatusExt;
 use std::path::{Path, PathBuf};
-use std::process::{Command, ExitStatus};
+use std::process::{ChildStderr, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
 use std::sync::{Arc, Condvar, Mutex};
 use std::time::{Duration, SystemTime};
 use std::thread::{self, JoinHandle};
@@ -48,6 +51,8 @@ use android_system_virtualizationservice::aidl::android::system::virtualizations
     VirtualMachineAppConfig::DebugLevel::DebugLevel,
     DisplayConfig::DisplayConfig as DisplayConfigParcelable,
     GpuConfig::GpuConfig as GpuConfigParcelable,
+    IVirtualMachineCallback::NO_EXIT_CODE,
+    MemoryStats::MemoryStats,
 };
 use android_system_virtualizationservice_internal::aidl::android::system::virtualizationservice_internal::IGlobalVmContext::IGlobalVmContext;
 use android_system_virtualizationservice_internal::aidl::android::system::virtualizationservice_internal::IBoundDevice::IBoundDevice;
@@ -77,11 +82,24 @@ const CROSVM_REBOOT_STATUS: i32 = 32;
 const CROSVM_CRASH_STATUS: i32 = 33;
 /// The exit status which crosvm returns when vcpu is stalled.
 const CROSVM_WATCHDOG_REBOOT_STATUS: i32 = 36;
+/// Amount of memory, in MiB, that crosvm gives to a VM when `--mem` isn't passed.
+const CROSVM_DEFAULT_MEMORY_MIB: u32 = 256;
 /// The size of memory (in MiB) reserved for ramdump
 const RAMDUMP_RESERVED_MIB: u32 = 17;
 
 const MILLIS_PER_SEC: i64 = 1000;
 
+/// How long to wait for crosvm to either fail to start or run the VM, after which the VM is
+/// reported as started anyway.
+const CROSVM_STARTUP_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long to wait for the end of crosvm's stderr once it has exited, to report why it failed to
+/// start.
+const CROSVM_STDERR_TIMEOUT: Duration = Duration::from_millis(100);
+/// Number of trailing lines of crosvm's stderr kept to report startup failures.
+const CROSVM_STDERR_MAX_LINES: usize = 20;
+/// Maximum length of each line of crosvm's stderr kept to report startup failures.
+const CROSVM_STDERR_MAX_LINE_LEN: usize = 512;
+
 const SYSPROP_CUSTOM_PVMFW_PATH: &str = "hypervisor.pvmfw.path";
 
 /// Serial device for VM console input.
@@ -110,19 +128,28 @@ pub struct CrosvmConfig {
     pub kernel: Option<File>,
     pub initrd: Option<File>,
     pub disks: Vec<DiskFile>,
-    pub params: Option<String>,
-    pub protected: bool,
+    /// Kernel command line parameters, validated by `split_kernel_params`.
+    pub params: Vec<String>,
+    pub protection: Protection,
     pub debug_config: DebugConfig,
     pub memory_mib: Option<NonZeroU32>,
+    /// Whether the VM has a memory balloon, to which the guest also reports its free pages.
+    pub balloon: bool,
     pub cpus: Option<NonZeroU32>,
     pub host_cpu_topology: bool,
+    /// Percentage of the host's CPU time that crosvm may use, or None for no limit.
+    pub cpu_quota_percent: Option<NonZeroU8>,
     pub console_out_fd: Option<File>,
     pub console_in_fd: Option<File>,
     pub log_fd: Option<File>,
     pub ramdump: Option<File>,
     pub indirect_files: Vec<File>,
     pub platform_version: VersionReq,
+    /// Whether the VM runs a payload which reports its state, i.e. it is an app VM.
+    pub has_payload: bool,
     pub detect_hangup: bool,
+    /// Time within which the payload must be ready once crosvm is spawned, or None for no limit.
+    pub boot_timeout: Option<Duration>,
     pub gdb_port: Option<NonZeroU16>,
     pub vfio_devices: Vec<VfioDevice>,
     pub dtbo: Option<File>,
@@ -130,6 +157,7 @@ pub struct CrosvmConfig {
     pub display_config: Option<DisplayConfig>,
     pub input_device_options: Vec<InputDeviceOption>,
     pub hugepages: bool,
+    pub swap_policy: SwapPolicy,
     pub tap: Option<File>,
     pub virtio_snd_backend: Option<String>,
     pub console_input_device: Option<String>,
@@ -137,6 +165,34 @@ pub struct CrosvmConfig {
     pub gpu_config: Option<GpuConfig>,
 }
 
+/// Whether a VM is protected and, if so, the firmware that it boots.
+#[derive(Debug)]
+pub enum Protection {
+    Unprotected,
+    /// The VM boots `pvmfw` if given, otherwise the firmware configured for the device.
+    Protected {
+        pvmfw: Option<File>,
+    },
+}
+
+impl Protection {
+    /// Returns whether the VM is protected.
+    pub fn is_protected(&self) -> bool {
+        matches!(self, Self::Protected { .. })
+    }
+}
+
+/// How guest memory may be swapped out of host RAM.
+#[derive(Debug, Default)]
+pub enum SwapPolicy {
+    /// crosvm doesn't swap guest memory itself.
+    #[default]
+    Off,
+    /// crosvm is prepared to swap guest memory to an unnamed file that it creates in the given
+    /// directory, once asked to through its control socket.
+    File(PathBuf),
+}
+
 #[derive(Debug)]
 pub struct DisplayConfig {
     pub width: NonZeroU32,
@@ -146,10 +202,19 @@ pub struct DisplayConfig {
     pub refresh_rate: NonZeroU32,
 }
 
+/// Maximum width or height of a display, in pixels.
+const MAX_DISPLAY_DIMENSION: u32 = 8192;
+
 impl DisplayConfig {
     pub fn new(raw_config: &DisplayConfigParcelable) -> Result<DisplayConfig> {
-        let width = try_into_non_zero_u32(raw_config.width)?;
-        let height = try_into_non_zero_u32(raw_config.height)?;
+        let width = try_into_non_zero_u32(raw_config.width).context("Invalid display width")?;
+        let height = try_into_non_zero_u32(raw_config.height).context("Invalid display height")?;
+        if width.get() > MAX_DISPLAY_DIMENSION || height.get() > MAX_DISPLAY_DIMENSION {
+            bail!(
+                "Display size {width}x{height} exceeds the maximum of {MAX_DISPLAY_DIMENSION} \
+                 pixels per axis"
+            );
+        }
         let horizontal_dpi = try_into_non_zero_u32(raw_config.horizontalDpi)?;
         let vertical_dpi = try_into_non_zero_u32(raw_config.verticalDpi)?;
         let refresh_rate = try_into_non_zero_u32(raw_config.refreshRate)?;
@@ -239,11 +304,19 @@ pub enum VmState {
         child: Arc<SharedChild>,
         /// The thread waiting for crosvm to finish.
         monitor_vm_exit_thread: Option<JoinHandle<()>>,
+        /// Whether the vCPUs of the VM are suspended.
+        suspended: bool,
     },
     /// The VM died or was killed.
-    Dead,
-    /// The VM failed to start.
-    Failed,
+    Dead {
+        /// Why the VM died.
+        death_reason: DeathReason,
+    },
+    /// The VM failed to start, or died before its payload started.
+    Failed {
+        /// Why the VM failed.
+        death_reason: DeathReason,
+    },
 }
 
 /// RSS values of VM and CrosVM process itself.
@@ -268,20 +341,39 @@ pub struct VmMetric {
 impl VmState {
     /// Tries to start the VM, if it is in the `NotStarted` state.
     ///
-    /// Returns an error if the VM is in the wrong state, or fails to start.
-    fn start(&mut self, instance: Arc<VmInstance>) -> Result<(), Error> {
-        let state = mem::replace(self, VmState::Failed);
+    /// Returns an error if the VM is in the wrong state, or fails to start. Otherwise, returns a
+    /// receiver of whether crosvm got to run the VM: nothing is received if crosvm exited before
+    /// for another reason than `CROSVM_START_ERROR_STATUS`.
+    fn start(&mut self, instance: Arc<VmInstance>) -> Result<Receiver<StartOutcome>, Error> {
+        let state = mem::replace(self, VmState::Failed { death_reason: DeathReason::START_FAILED });
         if let VmState::NotStarted { config } = state {
             let config = *config;
             let detect_hangup = config.detect_hangup;
+            let boot_timeout = config.boot_timeout;
             let (failure_pipe_read, failure_pipe_write) = create_pipe()?;
             let vfio_devices = config.vfio_devices.clone();
             let tap =
                 if let Some(tap_file) = &config.tap { Some(tap_file.try_clone()?) } else { None };
 
+            let cgroup = config
+                .cpu_quota_percent
+                .map(|quota| CpuCgroup::create(&*instance.vm_context.global_context, quota))
+                .transpose()?;
+
             // If this fails and returns an error, `self` will be left in the `Failed` state.
-            let child =
-                Arc::new(run_vm(config, &instance.crosvm_control_socket_path, failure_pipe_write)?);
+            let child = Arc::new(run_vm(config, &instance, failure_pipe_write, cgroup.as_ref())?);
+            let stderr = child.take_stderr().map(|stderr| StderrTail::capture(stderr, child.id()));
+            // One outcome is sent when crosvm runs the VM, the other if it fails to start.
+            let (start_outcome_sender, start_outcome) = mpsc::sync_channel(2);
+
+            let instance_running = instance.clone();
+            let running_sender = start_outcome_sender.clone();
+            thread::spawn(move || {
+                if instance_running.wait_for_control_socket() {
+                    // This fails if VmInstance::start stopped waiting for it, which is fine.
+                    let _ = running_sender.send(Ok(()));
+                }
+            });
 
             let instance_monitor_status = instance.clone();
             let child_monitor_status = child.clone();
@@ -292,9 +384,24 @@ impl VmState {
             let child_clone = child.clone();
             let instance_clone = instance.clone();
             let monitor_vm_exit_thread = Some(thread::spawn(move || {
-                instance_clone.monitor_vm_exit(child_clone, failure_pipe_read, vfio_devices, tap);
+                instance_clone.monitor_vm_exit(
+                    child_clone,
+                    failure_pipe_read,
+                    stderr,
+                    start_outcome_sender,
+                    vfio_devices,
+                    tap,
+                );
             }));
 
+            if let Some(timeout) = boot_timeout {
+                let child_clone = child.clone();
+                let instance_clone = instance.clone();
+                thread::spawn(move || {
+                    instance_clone.monitor_boot_timeout(child_clone, timeout);
+                });
+            }
+
             if detect_hangup {
                 let child_clone = child.clone();
                 thread::spawn(move || {
@@ -303,8 +410,8 @@ impl VmState {
             }
 
             // If it started correctly, update the state.
-            *self = VmState::Running { child, monitor_vm_exit_thread };
-            Ok(())
+            *self = VmState::Running { child, monitor_vm_exit_thread, suspended: false };
+            Ok(start_outcome)
         } else {
             *self = state;
             bail!("VM already started or failed")
@@ -312,10 +419,13 @@ impl VmState {
     }
 }
 
+/// Whether crosvm got to run a VM, or the reason why it failed to start.
+type StartOutcome = Result<(), String>;
+
 /// Internal struct that holds the handles to globally unique resources of a VM.
 #[derive(Debug)]
 pub struct VmContext {
-    #[allow(dead_code)] // Keeps the global context alive
+    /// Also keeps the global context alive.
     global_context: Strong<dyn IGlobalVmContext>,
     #[allow(dead_code)] // Keeps the server alive
     vm_server: RpcServer,
@@ -333,6 +443,8 @@ impl VmContext {
 pub struct VmInstance {
     /// The current state of the VM.
     pub vm_state: Mutex<VmState>,
+    /// Held while suspending or resuming the vCPUs of the VM.
+    suspend_lock: Mutex<()>,
     /// Global resources allocated for this VM.
     #[allow(dead_code)] // Keeps the context alive
     vm_context: VmContext,
@@ -344,6 +456,16 @@ pub struct VmInstance {
     pub name: String,
     /// Whether the VM is a protected VM.
     pub protected: bool,
+    /// The amount of memory requested for the VM, if not the crosvm default.
+    pub memory_mib: Option<NonZeroU32>,
+    /// Whether the VM has a memory balloon.
+    pub balloon: bool,
+    /// The size that the memory balloon was last asked to have, in bytes.
+    balloon_target_bytes: Mutex<u64>,
+    /// Whether the VM runs a payload which reports its state, i.e. it is an app VM.
+    has_payload: bool,
+    /// Path of the crosvm binary to run.
+    crosvm_path: PathBuf,
     /// Directory of temporary files used by the VM while it is running.
     pub temporary_directory: PathBuf,
     /// The UID of the process which requested the VM.
@@ -361,8 +483,13 @@ pub struct VmInstance {
     payload_state: Mutex<PayloadState>,
     /// Represents the condition that payload_state was updated
     payload_state_updated: Condvar,
+    /// The exit code which the payload reported when it finished, if any.
+    payload_exit_code: Mutex<Option<i32>>,
     /// The human readable name of requester_uid
     requester_uid_name: String,
+    /// The arguments crosvm was last started with, for debugging. File descriptors only appear as
+    /// their /proc/self/fd/N paths.
+    crosvm_args: Mutex<Vec<String>>,
 }
 
 impl fmt::Display for VmInstance {
@@ -384,22 +511,50 @@ impl VmInstance {
         requester_uid: u32,
         requester_debug_pid: i32,
         vm_context: VmContext,
+    ) -> Result<VmInstance, Error> {
+        Self::with_crosvm_path(
+            config,
+            PathBuf::from(CROSVM_PATH),
+            temporary_directory,
+            requester_uid,
+            requester_debug_pid,
+            vm_context,
+        )
+    }
+
+    /// Same as `new`, but the VM is run by the binary at `crosvm_path` instead of crosvm.
+    pub fn with_crosvm_path(
+        config: CrosvmConfig,
+        crosvm_path: PathBuf,
+        temporary_directory: PathBuf,
+        requester_uid: u32,
+        requester_debug_pid: i32,
+        vm_context: VmContext,
     ) -> Result<VmInstance, Error> {
         validate_config(&config)?;
         let cid = config.cid;
         let name = config.name.clone();
-        let protected = config.protected;
+        let protected = config.protection.is_protected();
+        let memory_mib = config.memory_mib;
+        let balloon = config.balloon;
+        let has_payload = config.has_payload;
         let requester_uid_name = User::from_uid(Uid::from_raw(requester_uid))
             .ok()
             .flatten()
             .map_or_else(|| format!("{}", requester_uid), |u| u.name);
         let instance = VmInstance {
             vm_state: Mutex::new(VmState::NotStarted { config: Box::new(config) }),
+            suspend_lock: Mutex::new(()),
             vm_context,
             cid,
             crosvm_control_socket_path: temporary_directory.join("crosvm.sock"),
             name,
             protected,
+            memory_mib,
+            balloon,
+            balloon_target_bytes: Mutex::new(0),
+            has_payload,
+            crosvm_path,
             temporary_directory,
             requester_uid,
             requester_debug_pid,
@@ -408,7 +563,9 @@ impl VmInstance {
             vm_metric: Mutex::new(Default::default()),
             payload_state: Mutex::new(PayloadState::Starting),
             payload_state_updated: Condvar::new(),
+            payload_exit_code: Mutex::new(None),
             requester_uid_name,
+            crosvm_args: Mutex::new(vec![]),
         };
         info!("{} created", &instance);
         Ok(instance)
@@ -417,22 +574,53 @@ impl VmInstance {
     /// Starts an instance of `crosvm` to manage the VM. The `crosvm` instance will be killed when
     /// the `VmInstance` is dropped.
     pub fn start(self: &Arc<Self>) -> Result<(), Error> {
-        let mut vm_metric = self.vm_metric.lock().unwrap();
-        vm_metric.start_timestamp = Some(SystemTime::now());
-        let ret = self.vm_state.lock().unwrap().start(self.clone());
-        if ret.is_ok() {
-            info!("{} started", &self);
+        self.vm_metric.lock().unwrap().start_timestamp = Some(SystemTime::now());
+        let (was_started, ret) = {
+            let mut vm_state = self.vm_state.lock().unwrap();
+            let was_started = !matches!(*vm_state, VmState::NotStarted { .. });
+            (was_started, vm_state.start(self.clone()))
+        };
+        let start_outcome = match ret {
+            Ok(start_outcome) => start_outcome,
+            Err(e) => {
+                if !was_started {
+                    // Clients aren't told about the death of a VM that never started by
+                    // monitor_vm_exit.
+                    self.callbacks.callback_on_died(
+                        self.cid,
+                        DeathReason::START_FAILED,
+                        NO_EXIT_CODE,
+                    );
+                }
+                return Err(e).with_context(|| format!("{} failed to start", &self));
+            }
+        };
+        // Report errors that make crosvm fail before running the VM (e.g. a disk image that can't
+        // be opened) as a failure to start, with the reason given by crosvm. Other exits, and later
+        // failures, are only reported to the callbacks by monitor_vm_exit.
+        match start_outcome.recv_timeout(CROSVM_STARTUP_TIMEOUT) {
+            Ok(Err(reason)) => bail!("{} failed to start: {reason}", &self),
+            Ok(Ok(())) | Err(RecvTimeoutError::Disconnected) => {}
+            Err(RecvTimeoutError::Timeout) => {
+                warn!("{} didn't run within {:?}", &self, CROSVM_STARTUP_TIMEOUT)
+            }
         }
-        ret.with_context(|| format!("{} failed to start", &self))
+        info!("{} started", &self);
+        Ok(())
     }
 
     /// Monitors the exit of the VM (i.e. termination of the `child` process). When that happens,
     /// handles the event by updating the state, noityfing the event to clients by calling
     /// callbacks, and removing temporary files for the VM.
+    ///
+    /// If crosvm failed to start, the reason is also sent to `start_outcome` with the last lines
+    /// of its `stderr`.
     fn monitor_vm_exit(
         &self,
         child: Arc<SharedChild>,
         mut failure_pipe_read: File,
+        stderr: Option<StderrTail>,
+        start_outcome: SyncSender<StartOutcome>,
         vfio_devices: Vec<VfioDevice>,
         tap: Option<File>,
     ) {
@@ -449,12 +637,6 @@ impl VmInstance {
             }
         }
 
-        let mut vm_state = self.vm_state.lock().unwrap();
-        *vm_state = VmState::Dead;
-        // Ensure that the mutex is released before calling the callbacks.
-        drop(vm_state);
-        info!("{} exited", &self);
-
         // Read the pipe to see if any failure reason is written
         let mut failure_reason = String::new();
         match failure_pipe_read.read_to_string(&mut failure_reason) {
@@ -472,13 +654,37 @@ impl VmInstance {
             } else {
                 Cow::from(failure_reason)
             };
-
-        self.handle_ramdump().unwrap_or_else(|e| error!("Error handling ramdump: {}", e));
-
         let death_reason = death_reason(&result, &failure_reason);
         let exit_signal = exit_signal(&result);
 
-        self.callbacks.callback_on_died(self.cid, death_reason);
+        let failed = is_failure(self.has_payload, self.payload_state(), death_reason);
+
+        let mut vm_state = self.vm_state.lock().unwrap();
+        *vm_state =
+            if failed { VmState::Failed { death_reason } } else { VmState::Dead { death_reason } };
+        // Ensure that the mutex is released before calling the callbacks.
+        drop(vm_state);
+
+        if let (Ok(status), DeathReason::START_FAILED) = (&result, death_reason) {
+            let stderr = stderr.map(StderrTail::collect).unwrap_or_default();
+            // This fails if VmInstance::start stopped waiting for it, which is fine.
+            let _ = start_outcome.send(Err(format!("crosvm exited with {status}:\n{stderr}")));
+        }
+        // Once crosvm has exited, VmInstance::start has nothing left to wait for.
+        drop(start_outcome);
+
+        // Wake up the threads waiting for the payload state, which stop once crosvm has exited.
+        // The lock is held so that a waiter can't miss the notification.
+        {
+            let _payload_state = self.payload_state.lock().unwrap();
+            self.payload_state_updated.notify_all();
+        }
+        info!("{} exited", &self);
+
+        self.handle_ramdump().unwrap_or_else(|e| error!("Error handling ramdump: {}", e));
+
+        let exit_code = self.payload_exit_code.lock().unwrap().unwrap_or(NO_EXIT_CODE);
+        self.callbacks.callback_on_died(self.cid, death_reason, exit_code);
 
         let vm_metric = self.vm_metric.lock().unwrap();
         write_vm_exited_stats_sync(
@@ -512,24 +718,55 @@ impl VmInstance {
         let (state, result) = self
             .payload_state_updated
             .wait_timeout_while(self.payload_state.lock().unwrap(), *BOOT_HANGUP_TIMEOUT, |s| {
-                *s < PayloadState::Started
+                *s < PayloadState::Started && is_running(&child)
             })
             .unwrap();
         drop(state); // we are not interested in state
-        let child_still_running = child.try_wait().ok() == Some(None);
-        if result.timed_out() && child_still_running {
+        let child_still_running = is_running(&child);
+        // If the payload state can't be updated, the boot timeout monitor already killed the VM.
+        if result.timed_out()
+            && child_still_running
+            && self.update_payload_state(PayloadState::Hangup).is_ok()
+        {
             error!(
                 "Microdroid({}) failed to start payload within {} secs timeout. Shutting down.",
                 child.id(),
                 BOOT_HANGUP_TIMEOUT.as_secs()
             );
-            self.update_payload_state(PayloadState::Hangup).unwrap();
             if let Err(e) = self.kill() {
                 error!("Error stopping timed-out VM with CID {}: {:?}", child.id(), e);
             }
         }
     }
 
+    /// Waits until the payload is ready, the VM exits, or the boot timeout requested by the client
+    /// expires. When the latter happens, kills the VM so that the client isn't left waiting for it,
+    /// as for a hangup.
+    fn monitor_boot_timeout(&self, child: Arc<SharedChild>, timeout: Duration) {
+        let (state, result) = self
+            .payload_state_updated
+            .wait_timeout_while(self.payload_state.lock().unwrap(), timeout, |s| {
+                *s < PayloadState::Ready && is_running(&child)
+            })
+            .unwrap();
+        drop(state); // we are not interested in state
+        let child_still_running = is_running(&child);
+        // If the payload state can't be updated, the hangup monitor already killed the VM.
+        if result.timed_out()
+            && child_still_running
+            && self.update_payload_state(PayloadState::Hangup).is_ok()
+        {
+            error!(
+                "VM({}) payload wasn't ready within {:?} boot timeout. Shutting down.",
+                child.id(),
+                timeout
+            );
+            if let Err(e) = self.kill() {
+                error!("Error stopping timed-out VM with CID {}: {:?}", self.cid, e);
+            }
+        }
+    }
+
     fn monitor_vm_status(&self, child: Arc<SharedChild>) {
         let pid = child.id();
 
@@ -537,7 +774,7 @@ impl VmInstance {
             {
                 // Check VM state
                 let vm_state = &*self.vm_state.lock().unwrap();
-                if let VmState::Dead = vm_state {
+                if let VmState::Dead { .. } | VmState::Failed { .. } = vm_state {
                     break;
                 }
 
@@ -565,11 +802,49 @@ impl VmInstance {
         }
     }
 
+    /// Waits until crosvm serves its control socket, which it only does once it runs the VM.
+    /// Returns false if crosvm exited before.
+    fn wait_for_control_socket(&self) -> bool {
+        // Suspending the VM is serialized with this, so that it isn't resumed by the request below.
+        let _suspend_guard = self.suspend_lock.lock().unwrap();
+        match &*self.vm_state.lock().unwrap() {
+            VmState::Running { suspended: true, .. } => return true,
+            VmState::Running { suspended: false, .. } => {}
+            _ => return false,
+        }
+        // Resuming the vCPUs of a VM which isn't suspended does nothing.
+        let request = VmRequest::ResumeVcpus;
+        matches!(
+            vm_control::client::handle_request(&request, &self.crosvm_control_socket_path),
+            Ok(VmResponse::Ok)
+        )
+    }
+
+    /// Records the arguments that crosvm is started with, and makes them available to
+    /// `debugListVms`.
+    fn set_crosvm_args(&self, args: Vec<String>) {
+        if let Err(e) = self.vm_context.global_context.setCrosvmArgs(&args) {
+            warn!("Failed to report crosvm arguments of VM with CID {}: {:?}", self.cid, e);
+        }
+        *self.crosvm_args.lock().unwrap() = args;
+    }
+
+    /// Returns the arguments that crosvm was last started with, if it was.
+    pub fn crosvm_args(&self) -> Vec<String> {
+        self.crosvm_args.lock().unwrap().clone()
+    }
+
     /// Returns the last reported state of the VM payload.
     pub fn payload_state(&self) -> PayloadState {
         *self.payload_state.lock().unwrap()
     }
 
+    /// Records the exit code which the payload reported when it finished, to be passed to the death
+    /// callbacks.
+    pub fn set_payload_exit_code(&self, exit_code: i32) {
+        *self.payload_exit_code.lock().unwrap() = Some(exit_code);
+    }
+
     /// Updates the payload state to the given value, if it is a valid state transition.
     pub fn update_payload_state(&self, new_state: PayloadState) -> Result<(), Error> {
         let mut state_locked = self.payload_state.lock().unwrap();
@@ -585,17 +860,21 @@ impl VmInstance {
     }
 
     /// Kills the crosvm instance, if it is running.
+    ///
+    /// Does nothing if the VM has already stopped, but fails if it was never started.
     pub fn kill(&self) -> Result<(), Error> {
         let monitor_vm_exit_thread = {
             let vm_state = &mut *self.vm_state.lock().unwrap();
-            if let VmState::Running { child, monitor_vm_exit_thread } = vm_state {
-                let id = child.id();
-                debug!("Killing crosvm({})", id);
-                // TODO: Talk to crosvm to shutdown cleanly.
-                child.kill().with_context(|| format!("Error killing crosvm({id}) instance"))?;
-                monitor_vm_exit_thread.take()
-            } else {
-                bail!("VM is not running")
+            match vm_state {
+                VmState::Running { child, monitor_vm_exit_thread, .. } => {
+                    let id = child.id();
+                    debug!("Killing crosvm({})", id);
+                    // TODO: Talk to crosvm to shutdown cleanly.
+                    child.kill().with_context(|| format!("Error killing crosvm({id}) instance"))?;
+                    monitor_vm_exit_thread.take()
+                }
+                VmState::Dead { .. } | VmState::Failed { .. } => return Ok(()),
+                VmState::NotStarted { .. } => bail!("VM is not running"),
             }
         };
 
@@ -626,16 +905,16 @@ impl VmInstance {
                         MemoryTrimLevel::TRIM_MEMORY_RUNNING_MODERATE => 10,
                         _ => bail!("Invalid memory trim level {:?}", level),
                     };
-                    let command = BalloonControlCommand::Adjust {
-                        num_bytes: total_memory * pct / 100,
-                        wait_for_success: false,
-                    };
+                    let num_bytes = total_memory * pct / 100;
+                    let command =
+                        BalloonControlCommand::Adjust { num_bytes, wait_for_success: false };
                     if let Err(e) = vm_control::client::handle_request(
                         &VmRequest::BalloonCommand(command),
                         &self.crosvm_control_socket_path,
                     ) {
                         bail!("Error sending balloon adjustment: {:?}", e);
                     }
+                    *self.balloon_target_bytes.lock().unwrap() = num_bytes;
                 }
             }
             Ok(VmResponse::Err(e)) => {
@@ -652,6 +931,101 @@ impl VmInstance {
         Ok(())
     }
 
+    /// Returns the amount of memory requested for the VM, in MiB, or the crosvm default. The guest
+    /// may get more than that, e.g. the memory that a protected VM shares with the host.
+    pub fn guest_memory_mib(&self) -> u32 {
+        self.memory_mib.map_or(CROSVM_DEFAULT_MEMORY_MIB, NonZeroU32::get)
+    }
+
+    /// Returns why the VM died, or None if it is yet to die.
+    pub fn death_reason(&self) -> Option<DeathReason> {
+        match &*self.vm_state.lock().unwrap() {
+            VmState::Dead { death_reason } | VmState::Failed { death_reason } => {
+                Some(*death_reason)
+            }
+            VmState::NotStarted { .. } | VmState::Running { .. } => None,
+        }
+    }
+
+    /// Suspends the vCPUs of the VM. Does nothing if they are already suspended.
+    pub fn suspend(&self) -> Result<(), Error> {
+        self.set_suspended(true)
+    }
+
+    /// Resumes the vCPUs of the VM. Does nothing if they are not suspended.
+    pub fn resume(&self) -> Result<(), Error> {
+        self.set_suspended(false)
+    }
+
+    fn set_suspended(&self, suspend: bool) -> Result<(), Error> {
+        // Serialize the requests, so that the state always reflects the last one that crosvm
+        // handled. The VM state itself isn't locked while waiting for crosvm, so that e.g.
+        // monitor_vm_exit isn't blocked by an unresponsive control socket.
+        let _suspend_guard = self.suspend_lock.lock().unwrap();
+        {
+            let vm_state = self.vm_state.lock().unwrap();
+            let VmState::Running { suspended, .. } = &*vm_state else {
+                bail!("VM is not running");
+            };
+            if *suspended == suspend {
+                return Ok(());
+            }
+        }
+        let (request, action) = if suspend {
+            (VmRequest::SuspendVcpus, "suspending")
+        } else {
+            (VmRequest::ResumeVcpus, "resuming")
+        };
+        match vm_control::client::handle_request(&request, &self.crosvm_control_socket_path) {
+            Ok(VmResponse::Ok) => {
+                if let VmState::Running { suspended, .. } = &mut *self.vm_state.lock().unwrap() {
+                    *suspended = suspend;
+                }
+                Ok(())
+            }
+            e => bail!("Error {} vCPUs: {:?}", action, e),
+        }
+    }
+
+    /// Inflates or deflates the virtio balloon so that it holds `size_mib` of guest memory.
+    pub fn set_memory_balloon(&self, size_mib: u64) -> Result<(), Error> {
+        if !matches!(&*self.vm_state.lock().unwrap(), VmState::Running { .. }) {
+            bail!("VM is not running");
+        }
+        let num_bytes = size_mib.checked_mul(1024 * 1024).context("Balloon size overflow")?;
+        let command = BalloonControlCommand::Adjust { num_bytes, wait_for_success: false };
+        match vm_control::client::handle_request(
+            &VmRequest::BalloonCommand(command),
+            &self.crosvm_control_socket_path,
+        ) {
+            Ok(VmResponse::Err(e)) => {
+                bail!("Errno return when adjusting balloon: {}", e.errno())
+            }
+            Ok(_) => {
+                *self.balloon_target_bytes.lock().unwrap() = num_bytes;
+                Ok(())
+            }
+            Err(e) => bail!("Error sending balloon adjustment: {:?}", e),
+        }
+    }
+
+    /// Returns the current memory usage of the VM, without waiting for crosvm nor for the guest.
+    /// Values that can't be read, e.g. because the VM just died, are reported as 0.
+    pub fn memory_stats(&self) -> MemoryStats {
+        let pid = match &*self.vm_state.lock().unwrap() {
+            VmState::Running { child, .. } => child.id(),
+            _ => return MemoryStats::default(),
+        };
+        let resident_bytes = get_resident_bytes(pid)
+            .inspect_err(|e| debug!("Failed to get RSS of crosvm({pid}): {e:?}"))
+            .unwrap_or(0);
+        let balloon_bytes = *self.balloon_target_bytes.lock().unwrap();
+        MemoryStats {
+            residentBytes: resident_bytes.try_into().unwrap_or(i64::MAX),
+            balloonBytes: balloon_bytes.try_into().unwrap_or(i64::MAX),
+        }
+    }
+
     /// Checks if ramdump has been created. If so, send it to tombstoned.
     fn handle_ramdump(&self) -> Result<(), Error> {
         let ramdump_path = self.temporary_directory.join("ramdump");
@@ -738,6 +1112,19 @@ fn get_guest_time(pid: u32) -> Result<i64> {
     Ok(guest_time_ticks * MILLIS_PER_SEC / ticks_per_sec)
 }
 
+// Get the resident set size, in bytes, from /proc/[crosvm pid]/status
+fn get_resident_bytes(pid: u32) -> Result<u64> {
+    let status = read_to_string(format!("/proc/{}/status", pid))?;
+    parse_vm_rss(&status).with_context(|| format!("Failed to parse VmRSS of process {pid}"))
+}
+
+/// Parses the `VmRSS` field of /proc/[pid]/status, e.g. `VmRSS:\t  123456 kB`, into bytes.
+fn parse_vm_rss(status: &str) -> Option<u64> {
+    let value = status.lines().find_map(|line| line.strip_prefix("VmRSS:"))?;
+    let kib = value.trim().strip_suffix("kB")?.trim_end().parse::<u64>().ok()?;
+    kib.checked_mul(1024)
+}
+
 // Get rss from /proc/[crosvm pid]/smaps
 fn get_rss(pid: u32) -> Result<Rss> {
     let file = read_to_string(format!("/proc/{}/smaps", pid))?;
@@ -767,6 +1154,28 @@ fn get_rss(pid: u32) -> Result<Rss> {
     Ok(Rss { vm: rss_vm_total, crosvm: rss_crosvm_total })
 }
 
+/// Returns whether the crosvm process hasn't exited yet.
+fn is_running(child: &SharedChild) -> bool {
+    child.try_wait().ok() == Some(None)
+}
+
+/// Returns whether a VM which died for the given reason should be considered to have failed rather
+/// than died: crosvm failed to start or crashed, or the VM never got to run its payload.
+///
+/// VMs without a payload (e.g. raw VMs) never report a payload state, so they only fail if crosvm
+/// does.
+fn is_failure(has_payload: bool, payload_state: PayloadState, death_reason: DeathReason) -> bool {
+    if death_reason == DeathReason::START_FAILED {
+        return true;
+    }
+    if !has_payload {
+        return death_reason == DeathReason::CRASH;
+    }
+    let payload_started = !matches!(payload_state, PayloadState::Starting | PayloadState::Hangup);
+    // Stopping a VM while it boots isn't a failure.
+    !payload_started && death_reason != DeathReason::KILLED
+}
+
 fn death_reason(result: &Result<ExitStatus, io::Error>, mut failure_reason: &str) -> DeathReason {
     if let Some((reason, info)) = failure_reason.split_once('|') {
         // Separator indicates extra context information is present after the failure name.
@@ -864,12 +1273,13 @@ fn append_platform_devices(
 /// Starts an instance of `crosvm` to manage a new VM.
 fn run_vm(
     config: CrosvmConfig,
-    crosvm_control_socket_path: &Path,
+    instance: &VmInstance,
     failure_pipe_write: File,
+    cgroup: Option<&CpuCgroup>,
 ) -> Result<SharedChild, Error> {
     validate_config(&config)?;
 
-    let mut command = Command::new(CROSVM_PATH);
+    let mut command = Command::new(&instance.crosvm_path);
     // TODO(qwandor): Remove --disable-sandbox.
     command
         .arg("--extended-status")
@@ -882,7 +1292,7 @@ fn run_vm(
         .arg("--cid")
         .arg(config.cid.to_string());
 
-    if system_properties::read_bool("hypervisor.memory_reclaim.supported", false)? {
+    if config.balloon {
         command.arg("--balloon-page-reporting");
     } else {
         command.arg("--no-balloon");
@@ -890,13 +1300,25 @@ fn run_vm(
 
     let mut memory_mib = config.memory_mib;
 
-    if config.protected {
-        match system_properties::read(SYSPROP_CUSTOM_PVMFW_PATH)? {
-            Some(pvmfw_path) if !pvmfw_path.is_empty() => {
-                command.arg("--protected-vm-with-firmware").arg(pvmfw_path)
-            }
-            _ => command.arg("--protected-vm"),
-        };
+    // Keep track of what file descriptors should be mapped to the crosvm process.
+    let mut preserved_fds = config.indirect_files.iter().map(|file| file.as_raw_fd()).collect();
+
+    if let Protection::Protected { pvmfw } = &config.protection {
+        if let Some(pvmfw) = pvmfw {
+            command
+                .arg("--protected-vm-with-firmware")
+                .arg(add_preserved_fd(&mut preserved_fds, pvmfw));
+        } else {
+            match system_properties::read(SYSPROP_CUSTOM_PVMFW_PATH)? {
+                Some(pvmfw_path) if !pvmfw_path.is_empty() => {
+                    if !Path::new(&pvmfw_path).is_file() {
+                        bail!("Custom pvmfw {pvmfw_path} isn't a file");
+                    }
+                    command.arg("--protected-vm-with-firmware").arg(pvmfw_path)
+                }
+                _ => command.arg("--protected-vm"),
+            };
+        }
 
         // 3 virtio-console devices + vsock = 4.
         let virtio_pci_device_count = 4 + config.disks.len();
@@ -959,9 +1381,6 @@ fn run_vm(
         command.arg("--gdb").arg(gdb_port.to_string());
     }
 
-    // Keep track of what file descriptors should be mapped to the crosvm process.
-    let mut preserved_fds = config.indirect_files.iter().map(|file| file.as_raw_fd()).collect();
-
     // Setup the serial devices.
     // 1. uart device: used as the output device by bootloaders and as early console by linux
     // 2. uart device: used to report the reason for the VM failing.
@@ -1017,8 +1436,8 @@ fn run_vm(
         command.arg("--initrd").arg(add_preserved_fd(&mut preserved_fds, initrd));
     }
 
-    if let Some(params) = &config.params {
-        command.arg("--params").arg(params);
+    if !config.params.is_empty() {
+        command.arg("--params").arg(config.params.join(" "));
     }
 
     for disk in &config.disks {
@@ -1031,7 +1450,7 @@ fn run_vm(
         command.arg(add_preserved_fd(&mut preserved_fds, kernel));
     }
 
-    let control_server_socket = UnixSeqpacketListener::bind(crosvm_control_socket_path)
+    let control_server_socket = UnixSeqpacketListener::bind(&instance.crosvm_control_socket_path)
         .context("failed to create control server")?;
     command
         .arg("--socket")
@@ -1132,6 +1551,10 @@ fn run_vm(
         command.arg("--hugepages");
     }
 
+    if let SwapPolicy::File(swap_dir) = &config.swap_policy {
+        command.arg("--swap").arg(swap_dir);
+    }
+
     if config.boost_uclamp {
         command.arg("--boost-uclamp");
     }
@@ -1147,13 +1570,91 @@ fn run_vm(
         }
     }
 
+    if let Some(cgroup) = cgroup {
+        cgroup.add_on_spawn(&mut command)?;
+    }
+
     print_crosvm_args(&command);
+    instance
+        .set_crosvm_args(command.get_args().map(|s| s.to_string_lossy().into_owned()).collect());
 
+    // Capture stderr to report the reason why crosvm fails to start.
+    command.stderr(Stdio::piped());
     let result = SharedChild::spawn(&mut command)?;
     debug!("Spawned crosvm({}).", result.id());
     Ok(result)
 }
 
+/// The last lines written by crosvm to its stderr, bounded in number and length.
+#[derive(Debug, Default)]
+struct StderrTail(Arc<(Mutex<StderrLines>, Condvar)>);
+
+#[derive(Debug, Default)]
+struct StderrLines {
+    lines: VecDeque<String>,
+    /// Whether the end of stderr has been reached.
+    closed: bool,
+}
+
+impl StderrTail {
+    /// Spawns a thread which logs the lines of `stderr` and keeps the last ones.
+    fn capture(stderr: ChildStderr, pid: u32) -> Self {
+        let tail = Self::default();
+        let shared = tail.0.clone();
+        thread::spawn(move || {
+            let (state, closed) = &*shared;
+            let mut stderr = BufReader::new(stderr);
+            let mut buf = Vec::new();
+            while let Ok(len) = read_bounded_line(&mut stderr, &mut buf) {
+                if len == 0 {
+                    break;
+                }
+                let line = String::from_utf8_lossy(&buf).trim_end().to_owned();
+                debug!("crosvm({pid}): {line}");
+                let lines = &mut state.lock().unwrap().lines;
+                if lines.len() == CROSVM_STDERR_MAX_LINES {
+                    lines.pop_front();
+                }
+                lines.push_back(line);
+            }
+            state.lock().unwrap().closed = true;
+            closed.notify_all();
+        });
+        tail
+    }
+
+    /// Waits briefly for the end of stderr, and returns the captured lines.
+    fn collect(self) -> String {
+        let (state, closed) = &*self.0;
+        let (state, _) = closed
+            .wait_timeout_while(state.lock().unwrap(), CROSVM_STDERR_TIMEOUT, |s| !s.closed)
+            .unwrap();
+        state.lines.iter().map(String::as_str).collect::<Vec<_>>().join("\n")
+    }
+}
+
+/// Reads a line into `buf`, keeping at most `CROSVM_STDERR_MAX_LINE_LEN` bytes of it and
+/// discarding the rest. Returns the number of bytes kept, which is 0 at the end of the stream.
+fn read_bounded_line(reader: &mut impl BufRead, buf: &mut Vec<u8>) -> io::Result<usize> {
+    buf.clear();
+    let len = reader.by_ref().take(CROSVM_STDERR_MAX_LINE_LEN as u64).read_until(b'\n', buf)?;
+    if len == CROSVM_STDERR_MAX_LINE_LEN && buf.last() != Some(&b'\n') {
+        loop {
+            let available = reader.fill_buf()?;
+            if available.is_empty() {
+                break;
+            }
+            if let Some(i) = available.iter().position(|&b| b == b'\n') {
+                reader.consume(i + 1);
+                break;
+            }
+            let len = available.len();
+            reader.consume(len);
+        }
+    }
+    Ok(len)
+}
+
 /// Ensure that the configuration has a valid combination of fields set, or return an error if not.
 fn validate_config(config: &CrosvmConfig) -> Result<(), Error> {
     if config.bootloader.is_none() && config.kernel.is_none() {
@@ -1162,6 +1663,12 @@ fn validate_config(config: &CrosvmConfig) -> Result<(), Error> {
     if config.bootloader.is_some() && (config.kernel.is_some() || config.initrd.is_some()) {
         bail!("Can't have both bootloader and kernel/initrd image.");
     }
+    if let Protection::Protected { pvmfw: Some(pvmfw) } = &config.protection {
+        let metadata = pvmfw.metadata().context("Failed to get pvmfw metadata")?;
+        if !metadata.is_file() || metadata.len() == 0 {
+            bail!("pvmfw must be a non-empty regular file");
+        }
+    }
     let version = Version::parse(CROSVM_PLATFORM_VERSION).unwrap();
     if !config.platform_version.matches(&version) {
         bail!(
@@ -1223,3 +1730,60 @@ fn create_pipe() -> Result<(File, File), Error> {
     let (read_fd, write_fd) = pipe2(OFlag::O_CLOEXEC)?;
     Ok((read_fd.into(), write_fd.into()))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn display_config(width: i32, height: i32) -> DisplayConfigParcelable {
+        DisplayConfigParcelable {
+            width,
+            height,
+            horizontalDpi: 320,
+            verticalDpi: 320,
+            refreshRate: 60,
+        }
+    }
+
+    #[test]
+    fn test_display_config_size() {
+        assert!(DisplayConfig::new(&display_config(1920, 1080)).is_ok());
+        assert!(DisplayConfig::new(&display_config(8192, 8192)).is_ok());
+        assert!(DisplayConfig::new(&display_config(0, 1080)).is_err());
+        assert!(DisplayConfig::new(&display_config(1920, -1)).is_err());
+        assert!(DisplayConfig::new(&display_config(8193, 1080)).is_err());
+        assert!(DisplayConfig::new(&display_config(1920, 8193)).is_err());
+    }
+
+    #[test]
+    fn test_is_failure() {
+        let reason = DeathReason::MICRODROID_FAILED_TO_CONNECT_TO_VIRTUALIZATION_SERVICE;
+        assert!(is_failure(true, PayloadState::Starting, reason));
+        assert!(is_failure(true, PayloadState::Starting, DeathReason::CRASH));
+        assert!(is_failure(true, PayloadState::Starting, DeathReason::START_FAILED));
+        assert!(is_failure(true, PayloadState::Hangup, DeathReason::HANGUP));
+        assert!(!is_failure(true, PayloadState::Starting, DeathReason::KILLED));
+        assert!(!is_failure(true, PayloadState::Started, DeathReason::CRASH));
+        assert!(!is_failure(true, PayloadState::Ready, DeathReason::SHUTDOWN));
+        assert!(!is_failure(true, PayloadState::Finished, DeathReason::SHUTDOWN));
+    }
+
+    #[test]
+    fn test_raw_vm_fails_only_if_crosvm_does() {
+        // Raw VMs have no payload to report that it started, so they stay in Starting.
+        assert!(is_failure(false, PayloadState::Starting, DeathReason::START_FAILED));
+        assert!(is_failure(false, PayloadState::Starting, DeathReason::CRASH));
+        assert!(!is_failure(false, PayloadState::Starting, DeathReason::SHUTDOWN));
+        assert!(!is_failure(false, PayloadState::Starting, DeathReason::REBOOT));
+        assert!(!is_failure(false, PayloadState::Starting, DeathReason::KILLED));
+    }
+
+    #[test]
+    fn test_parse_vm_rss() {
+        let status =
+            "Name:\tcrosvm\nVmHWM:\t  204800 kB\nVmRSS:\t  102400 kB\nRssAnon:\t 1024 kB\n";
+        assert_eq!(parse_vm_rss(status), Some(100 * 1024 * 1024));
+        assert_eq!(parse_vm_rss("Name:\tcrosvm\n"), None);
+        assert_eq!(parse_vm_rss("VmRSS:\t  garbage kB\n"), None);
+    }
+}
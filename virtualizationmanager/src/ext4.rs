@@ -0,0 +1,409 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module supports formatting a writable partition as an empty ext4 filesystem, so that VMs
+//! can mount it without running mke2fs first.
+//!
+//! The layout is the one of `mke2fs -t ext4 -O ^has_journal,^resize_inode,^64bit,...` with 4KiB
+//! blocks: every block group has its bitmaps and inode table, and the superblock and group
+//! descriptors are backed up following `sparse_super`. Only the root directory and lost+found are
+//! created.
+
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+
+const BLOCK_SIZE: u64 = 4096;
+const LOG_BLOCK_SIZE: u32 = 2; // log2(BLOCK_SIZE) - 10
+const BLOCKS_PER_GROUP: u64 = BLOCK_SIZE * 8;
+const INODE_SIZE: u64 = 256;
+const INODES_PER_BLOCK: u64 = BLOCK_SIZE / INODE_SIZE;
+const MAX_INODES_PER_GROUP: u64 = 8192;
+/// Same inode ratio as the mke2fs default.
+const BYTES_PER_INODE: u64 = 16384;
+const GROUP_DESC_SIZE: u64 = 32;
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const SUPERBLOCK_SIZE: usize = 1024;
+
+const EXT4_SUPER_MAGIC: u16 = 0xEF53;
+const EXT4_DYNAMIC_REV: u32 = 1;
+const EXT4_VALID_FS: u16 = 1;
+const EXT4_ERRORS_CONTINUE: u16 = 1;
+const EXT4_FEATURE_INCOMPAT_FILETYPE: u32 = 0x2;
+const EXT4_FEATURE_INCOMPAT_EXTENTS: u32 = 0x40;
+const EXT4_FEATURE_RO_COMPAT_SPARSE_SUPER: u32 = 0x1;
+const EXT4_FEATURE_RO_COMPAT_EXTRA_ISIZE: u32 = 0x40;
+const EXT4_EXTRA_ISIZE: u16 = 32;
+
+const EXT4_ROOT_INO: u32 = 2;
+/// Inodes below this one are reserved. It is used for lost+found, as mke2fs does.
+const EXT4_FIRST_INO: u32 = 11;
+const EXT4_EXTENTS_FL: u32 = 0x80000;
+const EXT4_EXT_MAGIC: u16 = 0xF30A;
+const EXT4_FT_DIR: u8 = 2;
+const S_IFDIR: u16 = 0o040000;
+
+/// Formats `part` as an empty ext4 filesystem of `size` bytes.
+///
+/// `part` must already be `size` bytes long and zero-filled, as only non-zero metadata is written.
+pub(crate) fn format<W: Write + Seek>(part: &mut W, size: u64) -> Result<()> {
+    let layout = Layout::new(size)?;
+    let mut uuid = [0u8; 16];
+    File::open("/dev/urandom")?.read_exact(&mut uuid)?;
+
+    let descriptors = layout.group_descriptors();
+    for group in (0..layout.groups).filter(|g| layout.has_super(*g)) {
+        let start = layout.group_start(group);
+        let offset = if group == 0 { SUPERBLOCK_OFFSET } else { start * BLOCK_SIZE };
+        write_at(part, offset, &layout.superblock(group, &uuid))?;
+        write_at(part, (start + 1) * BLOCK_SIZE, &descriptors)?;
+    }
+    for group in 0..layout.groups {
+        write_at(part, layout.block_bitmap(group) * BLOCK_SIZE, &layout.block_bitmap_data(group))?;
+        write_at(part, layout.inode_bitmap(group) * BLOCK_SIZE, &layout.inode_bitmap_data(group))?;
+    }
+
+    let root_block = layout.first_data_block();
+    let lost_found_block = root_block + 1;
+    write_at(
+        part,
+        layout.inode_offset(EXT4_ROOT_INO),
+        &dir_inode(0o755, 3, root_block.try_into().unwrap()),
+    )?;
+    write_at(
+        part,
+        layout.inode_offset(EXT4_FIRST_INO),
+        &dir_inode(0o700, 2, lost_found_block.try_into().unwrap()),
+    )?;
+    write_at(
+        part,
+        root_block * BLOCK_SIZE,
+        &dir_block(&[
+            (EXT4_ROOT_INO, b"."),
+            (EXT4_ROOT_INO, b".."),
+            (EXT4_FIRST_INO, b"lost+found"),
+        ]),
+    )?;
+    write_at(
+        part,
+        lost_found_block * BLOCK_SIZE,
+        &dir_block(&[(EXT4_FIRST_INO, b"."), (EXT4_ROOT_INO, b"..")]),
+    )?;
+
+    part.flush()
+}
+
+/// Geometry of the filesystem.
+#[derive(Debug)]
+struct Layout {
+    blocks_count: u64,
+    groups: u64,
+    inodes_per_group: u64,
+    gdt_blocks: u64,
+}
+
+impl Layout {
+    fn new(size: u64) -> Result<Self> {
+        let blocks_count = size / BLOCK_SIZE;
+        if blocks_count > u32::MAX.into() {
+            return Err(Error::new(ErrorKind::InvalidInput, "Partition too large for ext4"));
+        }
+        let inodes_per_group = (blocks_count.min(BLOCKS_PER_GROUP) * BLOCK_SIZE / BYTES_PER_INODE)
+            .clamp(INODES_PER_BLOCK, MAX_INODES_PER_GROUP)
+            / INODES_PER_BLOCK
+            * INODES_PER_BLOCK;
+        let mut layout = Self::with_blocks(blocks_count, inodes_per_group);
+
+        // Like mke2fs, drop a last group too small to hold its own metadata.
+        let last = layout.groups.saturating_sub(1);
+        if last > 0 && layout.group_blocks(last) <= layout.overhead(last) {
+            layout = Self::with_blocks(layout.group_start(last), inodes_per_group);
+        }
+        // The first group also holds the blocks of the two directories.
+        if layout.groups == 0 || layout.group_blocks(0) < layout.overhead(0) + 2 {
+            return Err(Error::new(ErrorKind::InvalidInput, "Partition too small for ext4"));
+        }
+
+        Ok(layout)
+    }
+
+    fn with_blocks(blocks_count: u64, inodes_per_group: u64) -> Self {
+        let groups = blocks_count.div_ceil(BLOCKS_PER_GROUP);
+        let gdt_blocks = (groups * GROUP_DESC_SIZE).div_ceil(BLOCK_SIZE);
+        Self { blocks_count, groups, inodes_per_group, gdt_blocks }
+    }
+
+    /// Whether the group holds a copy of the superblock and group descriptors, as per
+    /// `sparse_super`: groups 0, 1 and powers of 3, 5 and 7.
+    fn has_super(&self, group: u64) -> bool {
+        let is_power_of = |base: u64| {
+            let mut n = base;
+            while n < group {
+                n *= base;
+            }
+            n == group
+        };
+        group <= 1 || is_power_of(3) || is_power_of(5) || is_power_of(7)
+    }
+
+    fn group_start(&self, group: u64) -> u64 {
+        group * BLOCKS_PER_GROUP
+    }
+
+    fn group_blocks(&self, group: u64) -> u64 {
+        BLOCKS_PER_GROUP.min(self.blocks_count - self.group_start(group))
+    }
+
+    fn block_bitmap(&self, group: u64) -> u64 {
+        let super_blocks = if self.has_super(group) { 1 + self.gdt_blocks } else { 0 };
+        self.group_start(group) + super_blocks
+    }
+
+    fn inode_bitmap(&self, group: u64) -> u64 {
+        self.block_bitmap(group) + 1
+    }
+
+    fn inode_table(&self, group: u64) -> u64 {
+        self.block_bitmap(group) + 2
+    }
+
+    fn inode_table_blocks(&self) -> u64 {
+        self.inodes_per_group / INODES_PER_BLOCK
+    }
+
+    /// Number of blocks used by the metadata at the start of the group.
+    fn overhead(&self, group: u64) -> u64 {
+        self.inode_table(group) + self.inode_table_blocks() - self.group_start(group)
+    }
+
+    /// Number of blocks used in the group, including the directory blocks of the first group.
+    fn used_blocks(&self, group: u64) -> u64 {
+        self.overhead(group) + if group == 0 { 2 } else { 0 }
+    }
+
+    fn used_inodes(&self, group: u64) -> u64 {
+        if group == 0 {
+            EXT4_FIRST_INO.into()
+        } else {
+            0
+        }
+    }
+
+    fn first_data_block(&self) -> u64 {
+        self.overhead(0)
+    }
+
+    fn inode_offset(&self, inode: u32) -> u64 {
+        let index = u64::from(inode - 1);
+        let group = index / self.inodes_per_group;
+        self.inode_table(group) * BLOCK_SIZE + (index % self.inodes_per_group) * INODE_SIZE
+    }
+
+    fn free_blocks(&self) -> u64 {
+        (0..self.groups).map(|g| self.group_blocks(g) - self.used_blocks(g)).sum()
+    }
+
+    fn free_inodes(&self) -> u64 {
+        self.groups * self.inodes_per_group - EXT4_FIRST_INO as u64
+    }
+
+    fn superblock(&self, group: u64, uuid: &[u8; 16]) -> [u8; SUPERBLOCK_SIZE] {
+        let mut sb = [0u8; SUPERBLOCK_SIZE];
+        let inodes_count = (self.groups * self.inodes_per_group) as u32;
+        put(&mut sb, 0x0, &inodes_count.to_le_bytes());
+        put(&mut sb, 0x4, &(self.blocks_count as u32).to_le_bytes());
+        put(&mut sb, 0xC, &(self.free_blocks() as u32).to_le_bytes());
+        put(&mut sb, 0x10, &(self.free_inodes() as u32).to_le_bytes());
+        // s_first_data_block is 0 for block sizes larger than 1KiB.
+        put(&mut sb, 0x18, &LOG_BLOCK_SIZE.to_le_bytes());
+        put(&mut sb, 0x1C, &LOG_BLOCK_SIZE.to_le_bytes());
+        put(&mut sb, 0x20, &(BLOCKS_PER_GROUP as u32).to_le_bytes());
+        put(&mut sb, 0x24, &(BLOCKS_PER_GROUP as u32).to_le_bytes());
+        put(&mut sb, 0x28, &(self.inodes_per_group as u32).to_le_bytes());
+        put(&mut sb, 0x36, &u16::MAX.to_le_bytes()); // No mount count based checks.
+        put(&mut sb, 0x38, &EXT4_SUPER_MAGIC.to_le_bytes());
+        put(&mut sb, 0x3A, &EXT4_VALID_FS.to_le_bytes());
+        put(&mut sb, 0x3C, &EXT4_ERRORS_CONTINUE.to_le_bytes());
+        put(&mut sb, 0x4C, &EXT4_DYNAMIC_REV.to_le_bytes());
+        put(&mut sb, 0x54, &EXT4_FIRST_INO.to_le_bytes());
+        put(&mut sb, 0x58, &(INODE_SIZE as u16).to_le_bytes());
+        put(&mut sb, 0x5A, &(group as u16).to_le_bytes());
+        let incompat = EXT4_FEATURE_INCOMPAT_FILETYPE | EXT4_FEATURE_INCOMPAT_EXTENTS;
+        put(&mut sb, 0x60, &incompat.to_le_bytes());
+        let ro_compat = EXT4_FEATURE_RO_COMPAT_SPARSE_SUPER | EXT4_FEATURE_RO_COMPAT_EXTRA_ISIZE;
+        put(&mut sb, 0x64, &ro_compat.to_le_bytes());
+        put(&mut sb, 0x68, uuid);
+        put(&mut sb, 0x15C, &EXT4_EXTRA_ISIZE.to_le_bytes());
+        put(&mut sb, 0x15E, &EXT4_EXTRA_ISIZE.to_le_bytes());
+        sb
+    }
+
+    fn group_descriptors(&self) -> Vec<u8> {
+        let mut gdt = vec![0u8; (self.gdt_blocks * BLOCK_SIZE) as usize];
+        for group in 0..self.groups {
+            let desc = &mut gdt[(group * GROUP_DESC_SIZE) as usize..];
+            let free_blocks = self.group_blocks(group) - self.used_blocks(group);
+            let free_inodes = self.inodes_per_group - self.used_inodes(group);
+            let used_dirs: u16 = if group == 0 { 2 } else { 0 };
+            put(desc, 0x0, &(self.block_bitmap(group) as u32).to_le_bytes());
+            put(desc, 0x4, &(self.inode_bitmap(group) as u32).to_le_bytes());
+            put(desc, 0x8, &(self.inode_table(group) as u32).to_le_bytes());
+            put(desc, 0xC, &(free_blocks as u16).to_le_bytes());
+            put(desc, 0xE, &(free_inodes as u16).to_le_bytes());
+            put(desc, 0x10, &used_dirs.to_le_bytes());
+        }
+        gdt
+    }
+
+    fn block_bitmap_data(&self, group: u64) -> Vec<u8> {
+        let used = self.used_blocks(group);
+        bitmap(0..used, self.group_blocks(group))
+    }
+
+    fn inode_bitmap_data(&self, group: u64) -> Vec<u8> {
+        bitmap(0..self.used_inodes(group), self.inodes_per_group)
+    }
+}
+
+/// Returns a bitmap block with the `used` bits set, as well as the padding bits from `len`.
+fn bitmap(used: std::ops::Range<u64>, len: u64) -> Vec<u8> {
+    let mut data = vec![0u8; BLOCK_SIZE as usize];
+    for bit in used.chain(len..BLOCKS_PER_GROUP) {
+        data[(bit / 8) as usize] |= 1 << (bit % 8);
+    }
+    data
+}
+
+/// Returns a directory inode whose content is the single block `block`.
+fn dir_inode(permissions: u16, links_count: u16, block: u32) -> [u8; INODE_SIZE as usize] {
+    let mut inode = [0u8; INODE_SIZE as usize];
+    put(&mut inode, 0x0, &(S_IFDIR | permissions).to_le_bytes());
+    put(&mut inode, 0x4, &(BLOCK_SIZE as u32).to_le_bytes());
+    put(&mut inode, 0x1A, &links_count.to_le_bytes());
+    put(&mut inode, 0x1C, &((BLOCK_SIZE / 512) as u32).to_le_bytes());
+    put(&mut inode, 0x20, &EXT4_EXTENTS_FL.to_le_bytes());
+    // i_block holds the extent tree: a header and a single extent of one block.
+    put(&mut inode, 0x28, &EXT4_EXT_MAGIC.to_le_bytes());
+    put(&mut inode, 0x2A, &1u16.to_le_bytes()); // eh_entries
+    put(&mut inode, 0x2C, &4u16.to_le_bytes()); // eh_max
+    put(&mut inode, 0x38, &1u16.to_le_bytes()); // ee_len
+    put(&mut inode, 0x3C, &block.to_le_bytes()); // ee_start_lo
+    put(&mut inode, 0x80, &EXT4_EXTRA_ISIZE.to_le_bytes());
+    inode
+}
+
+/// Returns a directory block holding the given subdirectory entries.
+fn dir_block(entries: &[(u32, &[u8])]) -> Vec<u8> {
+    let mut data = vec![0u8; BLOCK_SIZE as usize];
+    let mut offset = 0;
+    for (i, (inode, name)) in entries.iter().enumerate() {
+        // The last entry spans the rest of the block.
+        let rec_len = if i == entries.len() - 1 {
+            BLOCK_SIZE as usize - offset
+        } else {
+            (8 + name.len()).next_multiple_of(4)
+        };
+        let entry = &mut data[offset..];
+        put(entry, 0x0, &inode.to_le_bytes());
+        put(entry, 0x4, &(rec_len as u16).to_le_bytes());
+        put(entry, 0x6, &[name.len() as u8, EXT4_FT_DIR]);
+        put(entry, 0x8, name);
+        offset += rec_len;
+    }
+    data
+}
+
+fn put(buffer: &mut [u8], offset: usize, value: &[u8]) {
+    buffer[offset..offset + value.len()].copy_from_slice(value);
+}
+
+fn write_at<W: Write + Seek>(part: &mut W, offset: u64, data: &[u8]) -> Result<()> {
+    part.seek(SeekFrom::Start(offset))?;
+    part.write_all(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const MIB: u64 = 1024 * 1024;
+
+    fn read_u16(data: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn read_u32(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn format_small_partition() -> Result<()> {
+        let size = 4 * MIB;
+        let mut part = Cursor::new(vec![0u8; size as usize]);
+        format(&mut part, size)?;
+        let data = part.into_inner();
+
+        let sb = &data[SUPERBLOCK_OFFSET as usize..];
+        assert_eq!(read_u16(sb, 0x38), EXT4_SUPER_MAGIC);
+        assert_eq!(read_u32(sb, 0x4), (size / BLOCK_SIZE) as u32);
+        assert_eq!(read_u32(sb, 0x0), 256);
+        assert_eq!(read_u32(sb, 0x10), 256 - EXT4_FIRST_INO);
+
+        // The root directory comes right after the metadata of the only group.
+        let layout = Layout::new(size)?;
+        let root = &data[layout.inode_offset(EXT4_ROOT_INO) as usize..];
+        assert_eq!(read_u16(root, 0x0), S_IFDIR | 0o755);
+        let root_block = read_u32(root, 0x3C) as usize;
+        assert_eq!(root_block as u64, layout.first_data_block());
+        let entries = &data[root_block * BLOCK_SIZE as usize..];
+        assert_eq!(read_u32(entries, 0x0), EXT4_ROOT_INO);
+        assert_eq!(&entries[0x20..0x2A], b"lost+found");
+        Ok(())
+    }
+
+    #[test]
+    fn format_with_backup_superblocks() -> Result<()> {
+        let size = 1024 * MIB;
+        let mut part = tempfile::tempfile()?;
+        part.set_len(size)?;
+        format(&mut part, size)?;
+
+        let layout = Layout::new(size)?;
+        assert_eq!(layout.groups, 8);
+        for group in 1..layout.groups {
+            let mut sb = [0u8; SUPERBLOCK_SIZE];
+            part.seek(SeekFrom::Start(layout.group_start(group) * BLOCK_SIZE))?;
+            part.read_exact(&mut sb)?;
+            let has_backup = read_u16(&sb, 0x38) == EXT4_SUPER_MAGIC;
+            assert_eq!(has_backup, [1, 3, 5, 7].contains(&group), "group {group}");
+            if has_backup {
+                assert_eq!(read_u16(&sb, 0x5A), group as u16);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn last_group_too_small_is_dropped() -> Result<()> {
+        let layout = Layout::new(BLOCKS_PER_GROUP * BLOCK_SIZE + 4 * BLOCK_SIZE)?;
+        assert_eq!(layout.groups, 1);
+        assert_eq!(layout.blocks_count, BLOCKS_PER_GROUP);
+        Ok(())
+    }
+
+    #[test]
+    fn partition_too_small() {
+        assert!(Layout::new(4 * BLOCK_SIZE).is_err());
+    }
+}
@@ -16,10 +16,12 @@
 
 mod aidl;
 mod atom;
+mod cgroup;
 mod composite;
 mod crosvm;
 mod debug_config;
 mod dt_overlay;
+mod ext4;
 mod payload;
 mod selinux;
 
@@ -333,7 +333,7 @@ fn make_payload_disk(
         });
     }
 
-    Ok(DiskImage { image: None, partitions, writable: false })
+    Ok(DiskImage { image: None, partitions, writable: false, ..Default::default() })
 }
 
 fn run_derive_classpath() -> Result<String> {
@@ -417,6 +417,7 @@ pub fn add_microdroid_vendor_image(vendor_image: File, vm_config: &mut VirtualMa
             image: Some(ParcelFileDescriptor::new(vendor_image)),
             writable: false,
         }],
+        ..Default::default()
     })
 }
 
@@ -453,6 +454,7 @@ pub fn add_microdroid_system_images(
         image: None,
         partitions: writable_partitions,
         writable: true,
+        ..Default::default()
     });
 
     Ok(())
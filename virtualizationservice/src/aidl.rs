@@ -15,6 +15,7 @@
 //! Implementation of the AIDL interface of the VirtualizationService.
 
 use crate::atom::{forward_vm_booted_atom, forward_vm_creation_atom, forward_vm_exited_atom};
+use crate::cgroup::{CpuCgroup, MAX_CPU_QUOTA_PERCENT};
 use crate::maintenance;
 use crate::remote_provisioning;
 use crate::rkpvm::{generate_ecdsa_p256_key_pair, request_attestation};
@@ -47,11 +48,14 @@ use serde::Deserialize;
 use service_vm_comm::Response;
 use std::collections::{HashMap, HashSet};
 use std::fs::{self, create_dir, remove_dir_all, remove_file, set_permissions, File, Permissions};
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
+use std::num::NonZeroU8;
 use std::os::unix::fs::PermissionsExt;
 use std::os::unix::raw::{pid_t, uid_t};
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
 use tombstoned_client::{DebuggerdDumpType, TombstonedConnection};
 use virtualizationcommon::Certificate::Certificate;
 use virtualizationmaintenance::{
@@ -88,8 +92,21 @@ const GUEST_CID_MAX: Cid = 65535;
 
 const SYSPROP_LAST_CID: &str = "virtualizationservice.state.last_cid";
 
+/// System property overriding the maximum number of VMs which a single client UID can have at the
+/// same time.
+const SYSPROP_MAX_VMS: &str = "virtualizationservice.max_vms";
+const DEFAULT_MAX_VMS: usize = 8;
+
 const CHUNK_RECV_MAX_LEN: usize = 1024;
 
+/// Maximum total length of the crosvm arguments that a client can record for debugging.
+const MAX_CROSVM_ARGS_LEN: usize = 64 * 1024;
+
+/// Bounds of the delay between attempts to bind the tombstone listener, which doubles after each
+/// failed attempt.
+const TOMBSTONE_LISTENER_MIN_BACKOFF: Duration = Duration::from_millis(100);
+const TOMBSTONE_LISTENER_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
 /// The fake certificate is used for testing only when a client VM requests attestation in test
 /// mode, it is a single certificate extracted on an unregistered device for testing.
 /// Here is the snapshot of the certificate:
@@ -184,11 +201,7 @@ impl VirtualizationServiceInternal {
             display_service_set: Arc::new(Condvar::new()),
         };
 
-        std::thread::spawn(|| {
-            if let Err(e) = handle_stream_connection_tombstoned() {
-                warn!("Error receiving tombstone from guest or writing them. Error: {:?}", e);
-            }
-        });
+        std::thread::spawn(handle_stream_connection_tombstoned);
 
         service
     }
@@ -248,14 +261,16 @@ impl IVirtualizationServiceInternal for VirtualizationServiceInternal {
     fn allocateGlobalVmContext(
         &self,
         requester_debug_pid: i32,
+        name: &str,
     ) -> binder::Result<Strong<dyn IGlobalVmContext>> {
         check_manage_access()?;
 
         let requester_uid = get_calling_uid();
         let requester_debug_pid = requester_debug_pid as pid_t;
         let state = &mut *self.state.lock().unwrap();
+        state.check_vm_limit(requester_uid).or_service_specific_exception(-1)?;
         state
-            .allocate_vm_context(requester_uid, requester_debug_pid)
+            .allocate_vm_context(requester_uid, requester_debug_pid, debug_vm_name(name))
             .or_binder_exception(ExceptionCode::ILLEGAL_STATE)
     }
 
@@ -287,6 +302,8 @@ impl IVirtualizationServiceInternal for VirtualizationServiceInternal {
                 temporaryDirectory: vm.get_temp_dir().to_string_lossy().to_string(),
                 requesterUid: vm.requester_uid as i32,
                 requesterPid: vm.requester_debug_pid,
+                name: vm.name.clone(),
+                crosvmArgs: vm.crosvm_args.lock().unwrap().clone(),
             })
             .collect();
         Ok(cids)
@@ -643,12 +660,19 @@ struct GlobalVmInstance {
     requester_uid: uid_t,
     /// PID of the client who requested this VM instance.
     requester_debug_pid: pid_t,
+    /// Name of the VM, given by the client for debugging.
+    name: String,
+    /// Arguments that crosvm was started with, reported by the client for debugging.
+    crosvm_args: Mutex<Vec<String>>,
+    /// The cgroup limiting the CPU time of crosvm, if the client asked for one.
+    cpu_cgroup: Mutex<Option<CpuCgroup>>,
+    /// Temporary directory of the VM, removed when its context is dropped.
+    temp_dir: PathBuf,
 }
 
 impl GlobalVmInstance {
     fn get_temp_dir(&self) -> PathBuf {
-        let cid = self.cid;
-        format!("{TEMPORARY_DIRECTORY}/{cid}").into()
+        self.temp_dir.clone()
     }
 }
 
@@ -681,6 +705,9 @@ impl GlobalState {
     /// Get the next available CID, or an error if we have run out. The last CID used is stored in
     /// a system property so that restart of virtualizationservice doesn't reuse CID while the host
     /// Android is up.
+    ///
+    /// The search wraps around to GUEST_CID_MIN after GUEST_CID_MAX, so CIDs of VMs which are gone
+    /// are eventually recycled: this only fails if every guest CID is held by a live VM context.
     fn get_next_available_cid(&mut self) -> Result<Cid> {
         // Start trying to find a CID from the last used CID + 1. This ensures
         // that we do not eagerly recycle CIDs. It makes debugging easier but
@@ -721,6 +748,28 @@ impl GlobalState {
         Ok(cid)
     }
 
+    /// Fails if allocating one more VM context for the given client would exceed the maximum
+    /// number of VMs per client. Only the client's live VMs count, so that one client can't
+    /// prevent others from creating VMs.
+    fn check_vm_limit(&mut self, requester_uid: uid_t) -> Result<()> {
+        let max_vms = max_vms();
+        ensure!(
+            self.live_vm_count(requester_uid) < max_vms,
+            "Too many VMs for UID {requester_uid}: the limit is {max_vms}"
+        );
+        Ok(())
+    }
+
+    /// Returns the number of live VMs requested by the given client.
+    fn live_vm_count(&mut self, requester_uid: uid_t) -> usize {
+        self.held_contexts.retain(|_, instance| instance.strong_count() > 0);
+        self.held_contexts
+            .values()
+            .filter_map(Weak::upgrade)
+            .filter(|instance| instance.requester_uid == requester_uid)
+            .count()
+    }
+
     fn find_available_cid<I>(&self, mut range: I) -> Option<Cid>
     where
         I: Iterator<Item = Cid>,
@@ -732,16 +781,24 @@ impl GlobalState {
         &mut self,
         requester_uid: uid_t,
         requester_debug_pid: pid_t,
+        name: String,
     ) -> Result<Strong<dyn IGlobalVmContext>> {
         // Garbage collect unused VM contexts.
         self.held_contexts.retain(|_, instance| instance.strong_count() > 0);
 
         let cid = self.get_next_available_cid()?;
-        let instance = Arc::new(GlobalVmInstance { cid, requester_uid, requester_debug_pid });
+        let instance = Arc::new(GlobalVmInstance {
+            cid,
+            requester_uid,
+            requester_debug_pid,
+            name,
+            temp_dir: Path::new(TEMPORARY_DIRECTORY).join(cid.to_string()),
+            ..Default::default()
+        });
         create_temporary_directory(&instance.get_temp_dir(), Some(requester_uid))?;
 
         self.held_contexts.insert(cid, Arc::downgrade(&instance));
-        let binder = GlobalVmContext { instance, ..Default::default() };
+        let binder = GlobalVmContext { instance, lazy_service_guard: Default::default() };
         Ok(BnGlobalVmContext::new_binder(binder, BinderFeatures::default()))
     }
 
@@ -775,6 +832,22 @@ impl GlobalState {
     }
 }
 
+fn max_vms() -> usize {
+    system_properties::read(SYSPROP_MAX_VMS)
+        .unwrap_or_else(|e| {
+            error!("Failed to read '{}': {:?}", SYSPROP_MAX_VMS, e);
+            None
+        })
+        .and_then(|val| {
+            val.parse::<usize>()
+                .inspect_err(|_| {
+                    error!("Invalid value '{}' of property '{}'", val, SYSPROP_MAX_VMS)
+                })
+                .ok()
+        })
+        .unwrap_or(DEFAULT_MAX_VMS)
+}
+
 fn create_temporary_directory(path: &PathBuf, requester_uid: Option<uid_t>) -> Result<()> {
     // Directory may exist if previous attempt to create it had failed.
     // Delete it before trying again.
@@ -834,28 +907,108 @@ impl IGlobalVmContext for GlobalVmContext {
     fn getTemporaryDirectory(&self) -> binder::Result<String> {
         Ok(self.instance.get_temp_dir().to_string_lossy().to_string())
     }
+
+    fn setCrosvmArgs(&self, args: &[String]) -> binder::Result<()> {
+        let len: usize = args.iter().map(String::len).sum();
+        if len > MAX_CROSVM_ARGS_LEN {
+            return Err(anyhow!("crosvm arguments are too long ({len} bytes)"))
+                .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT);
+        }
+        *self.instance.crosvm_args.lock().unwrap() = args.to_vec();
+        Ok(())
+    }
+
+    fn createCpuCgroup(&self, quota_percent: i32) -> binder::Result<String> {
+        let quota_percent = u8::try_from(quota_percent)
+            .ok()
+            .filter(|percent| *percent <= MAX_CPU_QUOTA_PERCENT)
+            .and_then(NonZeroU8::new)
+            .ok_or_else(|| anyhow!("Invalid CPU quota: {quota_percent}%"))
+            .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT)?;
+        let mut cpu_cgroup = self.instance.cpu_cgroup.lock().unwrap();
+        if cpu_cgroup.is_some() {
+            return Err(anyhow!("The VM already has a cpu cgroup"))
+                .or_binder_exception(ExceptionCode::ILLEGAL_STATE);
+        }
+        let name = format!("crosvm_{}", self.instance.cid);
+        let cgroup =
+            CpuCgroup::create(get_calling_pid(), &name, quota_percent, self.instance.requester_uid)
+                .with_context(|| format!("Failed to create cpu cgroup for VM {name}"))
+                .with_log()
+                .or_service_specific_exception(-1)?;
+        let procs_path = cgroup.procs_path().to_string_lossy().to_string();
+        *cpu_cgroup = Some(cgroup);
+        Ok(procs_path)
+    }
+}
+
+impl Drop for GlobalVmContext {
+    fn drop(&mut self) {
+        // The client drops the context only after crosvm exited, so the cgroup is empty and
+        // nothing uses the files in the temporary directory anymore. As the instance is still
+        // alive, its CID (and thus its directory) can't have been reused yet.
+        self.instance.cpu_cgroup.lock().unwrap().take();
+        let path = self.instance.get_temp_dir();
+        if path.exists() {
+            remove_temporary_dir(&path).unwrap_or_else(|e| {
+                warn!("Could not delete temporary directory {:?}: {}", path, e);
+            });
+        }
+    }
 }
 
-fn handle_stream_connection_tombstoned() -> Result<()> {
+/// Receives tombstones from guests, forever. If the listener can't be bound or fails, it is bound
+/// again, so that guests can always send tombstones.
+fn handle_stream_connection_tombstoned() {
     // Should not listen for tombstones on a guest VM's port.
     assert!(!is_valid_guest_cid(VM_TOMBSTONES_SERVICE_PORT as Cid));
-    let listener =
-        VsockListener::bind_with_cid_port(VMADDR_CID_HOST, VM_TOMBSTONES_SERVICE_PORT as Cid)?;
+    loop {
+        let listener = bind_tombstone_listener();
+        let e = accept_tombstone_connections(&listener);
+        error!("Tombstone listener failed, binding it again: {:?}", e);
+    }
+}
+
+/// Binds the listener for tombstones, retrying with an exponential backoff until it succeeds.
+fn bind_tombstone_listener() -> VsockListener {
+    let mut backoff = TOMBSTONE_LISTENER_MIN_BACKOFF;
+    loop {
+        match VsockListener::bind_with_cid_port(VMADDR_CID_HOST, VM_TOMBSTONES_SERVICE_PORT as Cid)
+        {
+            Ok(listener) => return listener,
+            Err(e) => {
+                error!("Failed to bind tombstone listener, retrying in {:?}: {:?}", backoff, e);
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(TOMBSTONE_LISTENER_MAX_BACKOFF);
+            }
+        }
+    }
+}
+
+/// Handles the incoming tombstone connections until the listener fails, returning the error.
+fn accept_tombstone_connections(listener: &VsockListener) -> io::Error {
     for incoming_stream in listener.incoming() {
         let mut incoming_stream = match incoming_stream {
+            Err(e) if is_listener_error(&e) => return e,
             Err(e) => {
                 warn!("invalid incoming connection: {:?}", e);
                 continue;
             }
             Ok(s) => s,
         };
-        std::thread::spawn(move || {
+        thread::spawn(move || {
             if let Err(e) = handle_tombstone(&mut incoming_stream) {
                 error!("Failed to write tombstone- {:?}", e);
             }
         });
     }
-    Ok(())
+    io::Error::other("Listener stopped accepting connections")
+}
+
+/// Returns whether an error returned by `accept` means that the listener itself is unusable,
+/// rather than that a single connection failed.
+fn is_listener_error(e: &io::Error) -> bool {
+    matches!(e.raw_os_error(), Some(libc::EBADF | libc::EINVAL | libc::ENOTSOCK | libc::EOPNOTSUPP))
 }
 
 fn handle_tombstone(stream: &mut VsockStream) -> Result<()> {
@@ -931,6 +1084,19 @@ fn check_internet_permission() -> binder::Result<()> {
     check_permission("android.permission.INTERNET")
 }
 
+/// Maximum length of a VM's name reported by debugListVms, in characters.
+const MAX_VM_NAME_LEN: usize = 64;
+
+/// Returns the name of a VM as reported for debugging: truncated to `MAX_VM_NAME_LEN` characters,
+/// and with non-printable characters replaced. Names are not validated, as existing clients may
+/// already use any name.
+fn debug_vm_name(name: &str) -> String {
+    name.chars()
+        .take(MAX_VM_NAME_LEN)
+        .map(|c| if c.is_control() { char::REPLACEMENT_CHARACTER } else { c })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -949,4 +1115,70 @@ mod tests {
         }
         Ok(())
     }
+
+    #[test]
+    fn listener_errors_are_distinguished_from_connection_errors() {
+        let os_error = io::Error::from_raw_os_error;
+        assert!(is_listener_error(&os_error(libc::EBADF)));
+        assert!(is_listener_error(&os_error(libc::EINVAL)));
+        assert!(!is_listener_error(&os_error(libc::ECONNABORTED)));
+        assert!(!is_listener_error(&os_error(libc::EMFILE)));
+        assert!(!is_listener_error(&io::Error::other("not an OS error")));
+    }
+
+    #[test]
+    fn debug_vm_name_is_bounded_and_printable() {
+        assert_eq!(debug_vm_name(""), "");
+        assert_eq!(debug_vm_name("my_vm (2)"), "my_vm (2)");
+        assert_eq!(debug_vm_name(&"a".repeat(MAX_VM_NAME_LEN)), "a".repeat(MAX_VM_NAME_LEN));
+        assert_eq!(debug_vm_name(&"a".repeat(MAX_VM_NAME_LEN + 1)), "a".repeat(MAX_VM_NAME_LEN));
+        assert_eq!(debug_vm_name("my\nvm"), "my\u{fffd}vm");
+        assert_eq!(debug_vm_name("my\x1bvm"), "my\u{fffd}vm");
+    }
+
+    #[test]
+    fn live_vms_are_counted_per_client() {
+        let mut state = GlobalState {
+            held_contexts: HashMap::new(),
+            dtbo_file: Mutex::new(None),
+            sk_state: None,
+            display_service: None,
+        };
+        let vms: Vec<_> = [(2048, 1000), (2049, 1000), (2050, 1001)]
+            .into_iter()
+            .map(|(cid, requester_uid)| {
+                Arc::new(GlobalVmInstance { cid, requester_uid, ..Default::default() })
+            })
+            .collect();
+        for vm in &vms {
+            state.held_contexts.insert(vm.cid, Arc::downgrade(vm));
+        }
+        assert_eq!(state.live_vm_count(1000), 2);
+        assert_eq!(state.live_vm_count(1001), 1);
+        assert_eq!(state.live_vm_count(1002), 0);
+
+        drop(vms);
+        assert_eq!(state.live_vm_count(1000), 0);
+        assert!(state.held_contexts.is_empty());
+    }
+
+    #[test]
+    fn dropping_vm_context_removes_temporary_directory() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let vm_temp_dir = temp_dir.path().join("2048");
+        create_temporary_directory(&vm_temp_dir, None)?;
+        fs::write(vm_temp_dir.join("crosvm.sock"), b"")?;
+
+        let instance = Arc::new(GlobalVmInstance {
+            cid: 2048,
+            temp_dir: vm_temp_dir.clone(),
+            ..Default::default()
+        });
+        let context = GlobalVmContext { instance, lazy_service_guard: Default::default() };
+        assert!(vm_temp_dir.is_dir());
+
+        drop(context);
+        assert!(!vm_temp_dir.exists());
+        Ok(())
+    }
 }
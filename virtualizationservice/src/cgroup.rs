@@ -0,0 +1,150 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Limits on the host CPU time used by crosvm, enforced with a cgroup of the (v1) cpu controller.
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::{error, info};
+use nix::unistd::{chown, Uid};
+use std::fs;
+use std::num::NonZeroU8;
+use std::os::unix::raw::{pid_t, uid_t};
+use std::path::{Path, PathBuf};
+
+/// Where Android mounts the hierarchy of the cpu controller, see cgroups.json.
+const CPUCTL_ROOT: &str = "/dev/cpuctl";
+
+/// Period over which the CPU quota of a cgroup is enforced.
+const CPU_PERIOD_US: u64 = 100_000;
+
+/// Maximum value of a CPU quota, in percent.
+pub const MAX_CPU_QUOTA_PERCENT: u8 = 100;
+
+/// A cgroup of the cpu controller limiting the CPU time of the crosvm process of a VM. It is
+/// removed when dropped, which must happen after that process has exited.
+#[derive(Debug)]
+pub struct CpuCgroup {
+    path: PathBuf,
+}
+
+impl CpuCgroup {
+    /// Creates a cgroup, below the cpu cgroup of the process `parent_pid` so that the VM keeps the
+    /// scheduling class of its client, which limits the processes it contains to `quota_percent`
+    /// of the CPU time of the host (across all its CPUs).
+    ///
+    /// Its cgroup.procs file is given to `owner_uid`, so that the client can move crosvm (which
+    /// runs with the same UID) to the cgroup before it executes.
+    pub fn create(
+        parent_pid: pid_t,
+        name: &str,
+        quota_percent: NonZeroU8,
+        owner_uid: uid_t,
+    ) -> Result<Self> {
+        if quota_percent.get() > MAX_CPU_QUOTA_PERCENT {
+            bail!("Invalid CPU quota {quota_percent}%");
+        }
+        let path = cpu_cgroup_of(parent_pid)?.join(name);
+        fs::create_dir(&path).with_context(|| format!("Failed to create cgroup {path:?}"))?;
+        let cgroup = Self { path };
+
+        let num_cpus = get_num_cpus().unwrap_or(1);
+        cgroup.write("cpu.cfs_period_us", &CPU_PERIOD_US.to_string())?;
+        cgroup.write("cpu.cfs_quota_us", &cfs_quota_us(quota_percent, num_cpus).to_string())?;
+        let procs_path = cgroup.procs_path();
+        chown(&procs_path, Some(Uid::from_raw(owner_uid)), None)
+            .with_context(|| format!("Failed to set ownership of {procs_path:?}"))?;
+
+        info!("Limiting CPU time to {quota_percent}% with cgroup {:?}", cgroup.path);
+        Ok(cgroup)
+    }
+
+    /// Returns the path of the file to write a PID to, to move that process into the cgroup.
+    pub fn procs_path(&self) -> PathBuf {
+        self.path.join("cgroup.procs")
+    }
+
+    fn write(&self, file: &str, value: &str) -> Result<()> {
+        let path = self.path.join(file);
+        fs::write(&path, value).with_context(|| format!("Failed to write {path:?}"))
+    }
+}
+
+impl Drop for CpuCgroup {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir(&self.path) {
+            error!("Failed to remove cgroup {:?}: {:?}", self.path, e);
+        }
+    }
+}
+
+/// Returns the value of cpu.cfs_quota_us for the given quota, with a period of `CPU_PERIOD_US`.
+fn cfs_quota_us(quota_percent: NonZeroU8, num_cpus: usize) -> u64 {
+    let num_cpus = u64::try_from(num_cpus.max(1)).unwrap_or(1);
+    CPU_PERIOD_US * num_cpus * u64::from(quota_percent.get()) / 100
+}
+
+/// Returns the path of the cgroup of the cpu controller that the process `pid` belongs to.
+fn cpu_cgroup_of(pid: pid_t) -> Result<PathBuf> {
+    let cgroups_path = format!("/proc/{pid}/cgroup");
+    let cgroups = fs::read_to_string(&cgroups_path)
+        .with_context(|| format!("Failed to read {cgroups_path}"))?;
+    parse_cpu_cgroup(&cgroups)
+        .map(|cgroup| Path::new(CPUCTL_ROOT).join(cgroup.trim_start_matches('/')))
+        .ok_or_else(|| anyhow!("Process {pid} doesn't belong to a cgroup of the cpu controller"))
+}
+
+/// Finds the cgroup of the cpu controller in the contents of /proc/<pid>/cgroup, whose lines are
+/// of the form `<hierarchy ID>:<comma separated controllers>:<cgroup>`.
+fn parse_cpu_cgroup(cgroups: &str) -> Option<&str> {
+    cgroups.lines().find_map(|line| {
+        let mut fields = line.splitn(3, ':');
+        let _hierarchy_id = fields.next()?;
+        let controllers = fields.next()?;
+        let cgroup = fields.next()?;
+        controllers.split(',').any(|c| c == "cpu").then_some(cgroup)
+    })
+}
+
+fn get_num_cpus() -> Option<usize> {
+    // SAFETY: Only integer constants passed back and forth.
+    let ret = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_CONF) };
+    if ret > 0 {
+        ret.try_into().ok()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cfs_quota_us() {
+        let percent = |p| NonZeroU8::new(p).unwrap();
+        assert_eq!(cfs_quota_us(percent(100), 1), 100_000);
+        assert_eq!(cfs_quota_us(percent(50), 1), 50_000);
+        assert_eq!(cfs_quota_us(percent(50), 8), 400_000);
+        assert_eq!(cfs_quota_us(percent(1), 0), 1_000);
+    }
+
+    #[test]
+    fn test_parse_cpu_cgroup() {
+        let cgroups = "4:memory:/\n3:cpu:/top-app\n1:cpuset:/top-app\n0::/uid_10100/pid_1234\n";
+        assert_eq!(parse_cpu_cgroup(cgroups), Some("/top-app"));
+        assert_eq!(parse_cpu_cgroup("2:cpu,cpuacct:/background\n"), Some("/background"));
+        assert_eq!(parse_cpu_cgroup("2:cpuacct:/\n"), None);
+        assert_eq!(parse_cpu_cgroup("1:cpuset:/top-app\n0::/uid_10100/pid_1234\n"), None);
+    }
+}
@@ -338,6 +338,7 @@ fn parse_partition_type(s: &str) -> Result<PartitionType, String> {
     match s {
         "raw" => Ok(PartitionType::RAW),
         "instance" => Ok(PartitionType::ANDROID_VM_INSTANCE),
+        "ext4" => Ok(PartitionType::ANDROID_VM_EXT4),
         _ => Err(format!("Invalid partition type {}", s)),
     }
 }
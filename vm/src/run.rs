@@ -280,6 +280,8 @@ fn state_to_str(vm_state: VirtualMachineState) -> &'static str {
         VirtualMachineState::READY => "READY",
         VirtualMachineState::FINISHED => "FINISHED",
         VirtualMachineState::DEAD => "DEAD",
+        VirtualMachineState::SUSPENDED => "SUSPENDED",
+        VirtualMachineState::FAILED => "FAILED",
         _ => "(invalid state)",
     }
 }
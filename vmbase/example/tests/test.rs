@@ -77,7 +77,12 @@ fn test_run_example_vm() -> Result<(), Error> {
         test_image.write_all(&i.to_le_bytes())?;
     }
     let test_image = ParcelFileDescriptor::new(test_image);
-    let disk_image = DiskImage { image: Some(test_image), writable: false, partitions: vec![] };
+    let disk_image = DiskImage {
+        image: Some(test_image),
+        writable: false,
+        partitions: vec![],
+        ..Default::default()
+    };
 
     // Make file for empty test disk image.
     let empty_image = File::options()
@@ -88,8 +93,12 @@ fn test_run_example_vm() -> Result<(), Error> {
         .open(EMPTY_DISK_IMAGE_PATH)
         .with_context(|| format!("Failed to open empty disk image {}", EMPTY_DISK_IMAGE_PATH))?;
     let empty_image = ParcelFileDescriptor::new(empty_image);
-    let empty_disk_image =
-        DiskImage { image: Some(empty_image), writable: false, partitions: vec![] };
+    let empty_disk_image = DiskImage {
+        image: Some(empty_image),
+        writable: false,
+        partitions: vec![],
+        ..Default::default()
+    };
 
     let config = VirtualMachineConfig::RawConfig(VirtualMachineRawConfig {
         name: String::from("VmBaseTest"),
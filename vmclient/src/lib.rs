@@ -191,6 +191,11 @@ impl VmInstance {
         self.vm.start()
     }
 
+    /// Forcibly stops the VM. Use `wait_for_death` to wait until its process has been reaped.
+    pub fn stop(&self) -> BinderResult<()> {
+        self.vm.stop()
+    }
+
     /// Returns the CID used for vsock connections to the VM.
     pub fn cid(&self) -> i32 {
         self.cid
@@ -353,7 +358,7 @@ impl IVirtualMachineCallback for VirtualMachineCallback {
         Ok(())
     }
 
-    fn onDied(&self, cid: i32, reason: AidlDeathReason) -> BinderResult<()> {
+    fn onDied(&self, cid: i32, reason: AidlDeathReason, _exit_code: i32) -> BinderResult<()> {
         let reason = reason.into();
         self.state.notify_death(reason);
         if let Some(ref callback) = self.client_callback {